@@ -0,0 +1,129 @@
+use crate::point3::Point3;
+use crate::ray::Ray;
+
+/// Axis aligned bounding box. Used to quickly skip objects that a ray can't possibly hit
+#[derive(Debug, Clone, Copy)]
+pub struct Aabb {
+    min: Point3,
+    max: Point3,
+}
+
+impl Aabb {
+    /// Creates the smallest box that contains both points
+    pub fn new(a: Point3, b: Point3) -> Self {
+        Self {
+            min: Point3::from_xyz(a.x().min(b.x()), a.y().min(b.y()), a.z().min(b.z())),
+            max: Point3::from_xyz(a.x().max(b.x()), a.y().max(b.y()), a.z().max(b.z())),
+        }
+    }
+
+    /// Creates the smallest box that contains all the points
+    pub fn from_points(points: &[Point3]) -> Self {
+        points
+            .iter()
+            .skip(1)
+            .fold(Aabb::new(points[0], points[0]), |bbox, p| {
+                bbox.surrounding(&Aabb::new(*p, *p))
+            })
+    }
+
+    /// returns the center of the box
+    pub fn centroid(&self) -> Point3 {
+        (self.min + self.max) * 0.5
+    }
+
+    /// returns all 8 corners of the box
+    pub fn corners(&self) -> [Point3; 8] {
+        let (a, b) = (self.min, self.max);
+        [
+            Point3::from_xyz(a.x(), a.y(), a.z()),
+            Point3::from_xyz(b.x(), a.y(), a.z()),
+            Point3::from_xyz(a.x(), b.y(), a.z()),
+            Point3::from_xyz(b.x(), b.y(), a.z()),
+            Point3::from_xyz(a.x(), a.y(), b.z()),
+            Point3::from_xyz(b.x(), a.y(), b.z()),
+            Point3::from_xyz(a.x(), b.y(), b.z()),
+            Point3::from_xyz(b.x(), b.y(), b.z()),
+        ]
+    }
+
+    /// The smallest box that contains both this box and the other
+    pub fn surrounding(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: Point3::from_xyz(
+                self.min.x().min(other.min.x()),
+                self.min.y().min(other.min.y()),
+                self.min.z().min(other.min.z()),
+            ),
+            max: Point3::from_xyz(
+                self.max.x().max(other.max.x()),
+                self.max.y().max(other.max.y()),
+                self.max.z().max(other.max.z()),
+            ),
+        }
+    }
+
+    /// The box where this box and the other overlap. Is None if they don't overlap
+    pub fn overlap(&self, other: &Aabb) -> Option<Aabb> {
+        let min = Point3::from_xyz(
+            self.min.x().max(other.min.x()),
+            self.min.y().max(other.min.y()),
+            self.min.z().max(other.min.z()),
+        );
+        let max = Point3::from_xyz(
+            self.max.x().min(other.max.x()),
+            self.max.y().min(other.max.y()),
+            self.max.z().min(other.max.z()),
+        );
+
+        if min.x() > max.x() || min.y() > max.y() || min.z() > max.z() {
+            return None;
+        }
+        Some(Aabb { min, max })
+    }
+
+    /// Returns the box grown by the amount in every direction
+    pub fn pad(&self, amount: f64) -> Aabb {
+        Aabb {
+            min: self.min - amount,
+            max: self.max + amount,
+        }
+    }
+
+    /// Returns the axis where the box is the longest. 0 is x, 1 is y and 2 is z
+    pub fn longest_axis(&self) -> usize {
+        let size = self.max - self.min;
+        if size.x() > size.y() && size.x() > size.z() {
+            0
+        } else if size.y() > size.z() {
+            1
+        } else {
+            2
+        }
+    }
+
+    /// Checks whether the ray passes through the box somewhere between t_min and t_max, using the slab method
+    pub fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+        self.hit_interval(r, t_min, t_max).is_some()
+    }
+
+    /// Finds where the ray enters and leaves the box, clipped to between t_min and t_max
+    pub fn hit_interval(&self, r: &Ray, mut t_min: f64, mut t_max: f64) -> Option<(f64, f64)> {
+        for axis in 0..3 {
+            let inv_d = 1.0 / r.direction().axis(axis);
+            let mut t0 = (self.min.axis(axis) - r.origin().axis(axis)) * inv_d;
+            let mut t1 = (self.max.axis(axis) - r.origin().axis(axis)) * inv_d;
+            if inv_d < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+
+            t_min = t_min.max(t0);
+            t_max = t_max.min(t1);
+            if t_max < t_min {
+                return None;
+            }
+        }
+
+        Some((t_min, t_max))
+    }
+}
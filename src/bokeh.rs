@@ -0,0 +1,224 @@
+use rand::Rng;
+use std::{
+    f64::consts::PI,
+    fs,
+    io::{Error, ErrorKind},
+    path::Path,
+};
+
+use crate::error;
+use crate::pgm::read_pgm;
+use crate::point3::Point3;
+
+/// The shape of the opening in the lens. Out of focus highlights take this shape
+pub enum ApertureShape {
+    Disk,
+    Polygon { blades: u32, rotation: f64 }, // An opening made by straight blades. The rotation is in degrees
+    Mask(ApertureMask),                     // An opening drawn in an image
+}
+
+impl ApertureShape {
+    /// Finds a shape from its name on the command line, like "disk" or "polygon:6:15" for
+    /// 6 blades rotated 15 degrees. Masks have to be loaded from a file instead
+    pub fn from_name(name: &str) -> Option<ApertureShape> {
+        let mut parts = name.split(':');
+        match parts.next()? {
+            "disk" => Some(ApertureShape::Disk),
+            "polygon" => Some(ApertureShape::Polygon {
+                blades: parts.next()?.parse().ok()?,
+                rotation: parts.next().map_or(Some(0.0), |r| r.parse().ok())?,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Samples a point uniformly inside the shape, which fits inside the square from -1 to 1
+    fn sample(&self, rng: &mut impl Rng) -> (f64, f64) {
+        match self {
+            ApertureShape::Disk => {
+                let p = Point3::random_in_unit_circle(rng);
+                (p.x(), p.y())
+            }
+            ApertureShape::Polygon { blades, rotation } => {
+                // pick one of the triangles between the center and two neighbouring corners, and a point inside it
+                let blade = rng.gen_range(0..*blades) as f64;
+                let angle =
+                    |corner: f64| rotation.to_radians() + 2.0 * PI * corner / *blades as f64;
+                let (a, b) = (angle(blade), angle(blade + 1.0));
+
+                let r = rng.gen::<f64>().sqrt();
+                let t = rng.gen::<f64>();
+                (
+                    r * ((1.0 - t) * a.cos() + t * b.cos()),
+                    r * ((1.0 - t) * a.sin() + t * b.sin()),
+                )
+            }
+            ApertureShape::Mask(mask) => mask.sample(rng),
+        }
+    }
+}
+
+/// An image of the aperture, where white lets light through and black blocks it
+pub struct ApertureMask {
+    width: usize,
+    height: usize,
+    cdf: Vec<f64>, // The running sum of the pixel values, so pixels can be picked in proportion to their brightness
+}
+
+impl ApertureMask {
+    /// Creates a mask from brightness values between 0 and 1, stored row by row from the top left corner
+    pub fn from_values(dimensions: (usize, usize), values: Vec<f32>) -> error::Result<Self> {
+        let (width, height) = dimensions;
+        if width.checked_mul(height) != Some(values.len()) {
+            return Err(error::Error::InvalidSettings(
+                "mask has the wrong amount of values".to_string(),
+            ));
+        }
+
+        let cdf = values
+            .iter()
+            .scan(0.0, |sum, v| {
+                *sum += v.max(0.0) as f64;
+                Some(*sum)
+            })
+            .collect::<Vec<_>>();
+        if !cdf.last().is_some_and(|total| *total > 0.0) {
+            return Err(error::Error::InvalidSettings(
+                "mask has to let some light through".to_string(),
+            ));
+        }
+
+        Ok(Self { width, height, cdf })
+    }
+
+    /// Loads a mask from a grayscale pgm image
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let ((width, height), values) = read_pgm(&fs::read(path)?)?;
+        if !values.iter().any(|v| *v > 0.0) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "the aperture mask is completely black",
+            ));
+        }
+
+        ApertureMask::from_values((width, height), values)
+            .map_err(|err| Error::new(ErrorKind::InvalidData, err.to_string()))
+    }
+
+    fn sample(&self, rng: &mut impl Rng) -> (f64, f64) {
+        let total = self.cdf[self.cdf.len() - 1];
+        let target = rng.gen::<f64>() * total;
+        let index = self
+            .cdf
+            .partition_point(|sum| *sum <= target)
+            .min(self.cdf.len() - 1);
+
+        // a random point inside the picked pixel. The longest side of the image goes from -1 to 1
+        let scale = self.width.max(self.height) as f64;
+        let px = (index % self.width) as f64 + rng.gen::<f64>();
+        let py = (index / self.width) as f64 + rng.gen::<f64>();
+        (
+            (2.0 * px - self.width as f64) / scale,
+            (self.height as f64 - 2.0 * py) / scale,
+        )
+    }
+}
+
+/// How out of focus parts of the image look. Only used by the perspective projection when the aperture is open
+pub struct Bokeh {
+    shape: ApertureShape,
+    squeeze: f64,  // Anamorphic squeeze. Highlights are this many times taller than wide
+    cats_eye: f64, // How much the lens barrel cuts off the aperture towards the corners
+    chromatic_aberration: f64, // How far red and blue focus from green, relative to the focus distance
+}
+
+impl Default for Bokeh {
+    fn default() -> Self {
+        Bokeh::new(ApertureShape::Disk)
+    }
+}
+
+impl Bokeh {
+    pub fn new(shape: ApertureShape) -> Self {
+        Self {
+            shape,
+            squeeze: 1.0,
+            cats_eye: 0.0,
+            chromatic_aberration: 0.0,
+        }
+    }
+
+    /// Squeezes the aperture horizontally like an anamorphic lens, making the highlights oval
+    pub fn with_squeeze(mut self, squeeze: f64) -> Self {
+        self.squeeze = squeeze;
+        self
+    }
+
+    /// Makes the highlights towards the corners of the image cat's eye shaped, and darkens the corners.
+    /// At 1 the aperture seen from the corners is cut off by a circle moved one aperture radius to the side
+    pub fn with_cats_eye(mut self, cats_eye: f64) -> Self {
+        self.cats_eye = cats_eye;
+        self
+    }
+
+    /// Focuses red light slightly further away and blue light slightly closer, giving out of focus edges
+    /// colored fringes. 0.02 moves the focus of red and blue 2% of the focus distance from green
+    pub fn with_chromatic_aberration(mut self, chromatic_aberration: f64) -> Self {
+        self.chromatic_aberration = chromatic_aberration;
+        self
+    }
+
+    /// Checks that the settings make sense
+    pub fn validate(&self) -> Result<(), String> {
+        if let ApertureShape::Polygon { blades, .. } = self.shape {
+            if blades < 3 {
+                return Err(format!(
+                    "a polygon aperture needs at least 3 blades, got {}",
+                    blades
+                ));
+            }
+        }
+        if self.squeeze <= 0.0 {
+            return Err(format!(
+                "the anamorphic squeeze has to be positive, got {}",
+                self.squeeze
+            ));
+        }
+        if self.cats_eye < 0.0 {
+            return Err(format!(
+                "the cat's eye amount can't be negative, got {}",
+                self.cats_eye
+            ));
+        }
+        if !(0.0..1.0).contains(&self.chromatic_aberration) {
+            return Err(format!(
+                "the chromatic aberration has to be between 0 and 1, got {}",
+                self.chromatic_aberration
+            ));
+        }
+        Ok(())
+    }
+
+    /// returns the chromatic aberration
+    pub fn chromatic_aberration(&self) -> f64 {
+        self.chromatic_aberration
+    }
+
+    /// Samples a point on the aperture, inside the square from -1 to 1. The image position is where the ray goes
+    /// through the image, where the center is (0, 0) and the corners are 1 away from it.
+    /// Returns None if the lens barrel blocks the ray
+    pub fn sample(&self, image_position: (f64, f64), rng: &mut impl Rng) -> Option<(f64, f64)> {
+        let (x, y) = self.shape.sample(rng);
+
+        // the barrel is seen as a circle that moves away from the aperture towards the corners of the image
+        let (dx, dy) = (
+            x - image_position.0 * self.cats_eye,
+            y - image_position.1 * self.cats_eye,
+        );
+        if dx * dx + dy * dy > 1.0 {
+            return None;
+        }
+
+        Some((x / self.squeeze, y))
+    }
+}
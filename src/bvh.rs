@@ -0,0 +1,121 @@
+use crate::aabb::Aabb;
+use crate::hitable::{HitRecord, Hitable};
+use crate::ray::Ray;
+use crate::stats;
+
+/// Bounding volume hierarchy. A binary tree of bounding boxes over a list of objects,
+/// so a ray only has to be tested against the objects whose boxes it passes through
+pub struct Bvh {
+    root: Option<BvhNode>,
+    unbounded: Vec<Box<dyn Hitable>>, // Objects without a bounding box, which are always tested
+}
+
+enum BvhNode {
+    Leaf {
+        object: Box<dyn Hitable>,
+        bbox: Aabb,
+    },
+    Branch {
+        left: Box<BvhNode>,
+        right: Box<BvhNode>,
+        bbox: Aabb,
+    },
+}
+
+impl Bvh {
+    /// Builds the hierarchy from a list of objects
+    pub fn new(objects: Vec<Box<dyn Hitable>>) -> Self {
+        let mut bounded = Vec::new();
+        let mut unbounded = Vec::new();
+        for object in objects {
+            match object.bounding_box() {
+                Some(bbox) => bounded.push((object, bbox)),
+                None => unbounded.push(object),
+            }
+        }
+
+        Self {
+            root: BvhNode::build(bounded),
+            unbounded,
+        }
+    }
+}
+
+impl BvhNode {
+    fn build(mut objects: Vec<(Box<dyn Hitable>, Aabb)>) -> Option<BvhNode> {
+        if objects.len() <= 1 {
+            return objects
+                .pop()
+                .map(|(object, bbox)| BvhNode::Leaf { object, bbox });
+        }
+
+        // split the objects in half along the axis where their centers are the most spread out
+        let centroids = objects
+            .iter()
+            .map(|(_, bbox)| bbox.centroid())
+            .collect::<Vec<_>>();
+        let axis = Aabb::from_points(&centroids).longest_axis();
+        objects
+            .sort_by(|(_, a), (_, b)| a.centroid().axis(axis).total_cmp(&b.centroid().axis(axis)));
+
+        let right_objects = objects.split_off(objects.len() / 2);
+        let left = BvhNode::build(objects)?;
+        let right = BvhNode::build(right_objects)?;
+        let bbox = left.bbox().surrounding(&right.bbox());
+
+        Some(BvhNode::Branch {
+            left: Box::new(left),
+            right: Box::new(right),
+            bbox,
+        })
+    }
+
+    fn bbox(&self) -> Aabb {
+        match self {
+            BvhNode::Leaf { bbox, .. } | BvhNode::Branch { bbox, .. } => *bbox,
+        }
+    }
+
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        stats::count_bvh_node();
+        if !self.bbox().hit(r, t_min, t_max) {
+            return None;
+        }
+
+        match self {
+            BvhNode::Leaf { object, .. } => {
+                stats::count_primitive_test();
+                object.hit(r, t_min, t_max)
+            }
+            BvhNode::Branch { left, right, .. } => {
+                let left_hit = left.hit(r, t_min, t_max);
+                let closest = left_hit.as_ref().map_or(t_max, |rec| rec.t());
+                right.hit(r, t_min, closest).or(left_hit)
+            }
+        }
+    }
+}
+
+impl Hitable for Bvh {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let mut closest = self.unbounded.hit(r, t_min, t_max);
+        let closest_t = closest.as_ref().map_or(t_max, |rec| rec.t());
+
+        if let Some(rec) = self
+            .root
+            .as_ref()
+            .and_then(|root| root.hit(r, t_min, closest_t))
+        {
+            closest = Some(rec);
+        }
+
+        closest
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        if !self.unbounded.is_empty() {
+            return None;
+        }
+        self.root.as_ref().map(|root| root.bbox())
+    }
+}
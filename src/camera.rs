@@ -1,16 +1,38 @@
-use rand::{rngs::ThreadRng, Rng};
-use rayon::prelude::*;
+use rand::Rng;
 use std::{
-    f64::{consts::PI, INFINITY},
+    collections::BTreeMap,
+    f64::consts::PI,
     fs::File,
-    io::{BufWriter, Write},
-    sync::{Arc, Mutex},
+    io::BufWriter,
+    ops::Range,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc,
+    },
+    thread::{Scope, ScopedJoinHandle},
+    time::{Duration, Instant},
 };
 
 use crate::{
-    hitable::Hitable,
+    bokeh::Bokeh,
+    cancel::CancelToken,
+    checkpoint::{pass_rng, Checkpoint, CheckpointSettings},
+    distributed::Coordinator,
+    error::{self, Error},
+    film::{Film, Filter},
+    hitable::{HitRecord, Hitable},
+    medium::Fog,
     point3::{Color, Point3, Vec3},
+    preview::PreviewServer,
+    progress::{ProgressSink, ProgressTracker, Quiet},
+    projection::Projection,
+    random,
     ray::Ray,
+    region::Region,
+    render::Image,
+    spectrum::{SampledSpectrum, SampledWavelengths, LAMBDA_MAX, LAMBDA_MIN},
+    stats::{self, RenderStats, Termination},
+    stereo::{Eye, Stereo},
 };
 
 /// The virtual camera
@@ -18,10 +40,8 @@ pub struct Camera {
     samples_pr_pixel: i64,  // The amount of rays sent out pr pixel
     max_light_bounces: i32, // The max amount of ray bounces in the scene
 
-    vfov: f64,         // the vertical field of view (stored in radians)
-    vup: Vec3,         // Camera-relative up direction
     look_from: Point3, // Where the camera is looking from
-    look_at: Point3,   // where the camera is looking at
+    projection: Projection,
 
     // Vectors that define camera dimensions
     u: Vec3,
@@ -32,338 +52,1356 @@ pub struct Camera {
     pixel_delta_v: Vec3, // THe distance betweem vertical pixels
     pixel_00_loc: Vec3,  // the location of the top left pixel of the camera
 
-    img_width: i64,    // The width of the image in pixels
-    img_height: i64,   // The height of the image in pixels
-    aspect_ratio: f64, // The ratio between the height and width of the image
+    img_width: i64,  // The width of the image in pixels
+    img_height: i64, // The height of the image in pixels
+    filter: Filter,  // How samples are spread over the pixels around them
 
     focus_distance: f64,
     defocus_angle: f64,
     defocus_disk_u: Vec3,
     defocus_disk_v: Vec3,
+    bokeh: Bokeh, // The shape of the aperture, and other lens effects
+
+    stereo: Option<Stereo>, // Renders a left and a right eye view next to each other
+    region: Option<Region>, // Only renders this rectangle of the image
+
+    checkpoint: Option<CheckpointSettings>, // Where the progress of the render is saved
+    resume: Option<Arc<Checkpoint>>,        // Saved progress that the render continues from
+
+    cancel: CancelToken,           // Stops the render early when it is cancelled
+    time_budget: Option<Duration>, // Stops the render early when it has run this long
+    progress: Arc<dyn ProgressSink>, // Shows how far the render has come
+    preview: Option<Arc<PreviewServer>>, // Shows the image after every pass in a browser
+
+    spectral: bool,   // Whether rays carry wavelengths instead of rgb light
+    fog: Option<Fog>, // Fog that fills the whole scene
+
+    shutter_open: f64,  // The time when the shutter opens
+    shutter_close: f64, // The time when the shutter closes. Rays are sent out at random times in between
+
+    lights: Vec<Box<dyn Hitable>>, // Objects that diffuse surfaces send some of their rays towards
+    background: Option<Color>, // The color rays see when they dont hit anything. None is the sky
+}
+
+/// Where the camera focuses. Either a fixed distance, or the distance to a point in the scene
+#[derive(Clone, Copy)]
+enum Focus {
+    Distance(f64),
+    Point(Point3),
+    LookAt, // Focus on the point the camera is looking at
 }
 
-impl Camera {}
+/// Collects the settings of a camera, and computes all the vectors derived from them when the camera is built
+pub struct CameraBuilder {
+    look_from: Point3,
+    look_at: Point3,
+    vup: Vec3,
+    vfov: f64, // the vertical field of view in degrees
+    projection: Projection,
+
+    defocus_angle: f64, // The angle of the cone of rays through each pixel. 0 means everything is in focus
+    focus: Focus,
+    bokeh: Bokeh,
+
+    img_width: i64,
+    img_height: i64,
+    filter: Filter,
+    samples_pr_pixel: i64,
+    max_light_bounces: i32,
 
-impl Default for Camera {
+    spectral: bool,
+    fog: Option<Fog>,
+    shutter_open: f64,
+    shutter_close: f64,
+    lights: Vec<Box<dyn Hitable>>,
+    background: Option<Color>,
+    stereo: Option<Stereo>,
+    region: Option<Region>,
+    checkpoint: Option<CheckpointSettings>,
+    resume: Option<Arc<Checkpoint>>,
+    cancel: CancelToken,
+    time_budget: Option<Duration>,
+    progress: Arc<dyn ProgressSink>,
+    preview: Option<Arc<PreviewServer>>,
+}
+
+impl Default for CameraBuilder {
     fn default() -> Self {
-        // Camera positioning
-        let look_from = Point3::from_xyz(0, 0, 1);
-        let look_at = Point3::from_xyz(0, 0, 0);
-        let vup = Vec3::from_xyz(0, 1, 0); // Camera - relative up direction
+        Self {
+            look_from: Point3::from_xyz(0, 0, 1),
+            look_at: Point3::from_xyz(0, 0, 0),
+            vup: Vec3::from_xyz(0, 1, 0),
+            vfov: 90.0,
+            projection: Projection::Perspective,
+            defocus_angle: 0.0,
+            focus: Focus::LookAt,
+            bokeh: Bokeh::default(),
+            img_width: 400,
+            img_height: 225,
+            filter: Filter::default(),
+            samples_pr_pixel: 50,
+            max_light_bounces: 50,
+            spectral: false,
+            fog: None,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+            lights: Vec::new(),
+            background: None,
+            stereo: None,
+            region: None,
+            checkpoint: None,
+            resume: None,
+            cancel: CancelToken::new(),
+            time_budget: None,
+            progress: Arc::new(Quiet),
+            preview: None,
+        }
+    }
+}
+
+impl CameraBuilder {
+    /// Where the camera is placed
+    pub fn look_from(mut self, look_from: Point3) -> Self {
+        self.look_from = look_from;
+        self
+    }
+
+    /// The point the camera is looking at
+    pub fn look_at(mut self, look_at: Point3) -> Self {
+        self.look_at = look_at;
+        self
+    }
+
+    /// The direction that is up relative to the camera
+    pub fn vup(mut self, vup: Vec3) -> Self {
+        self.vup = vup;
+        self
+    }
+
+    /// The vertical field of view in degrees
+    pub fn vfov(mut self, vfov: f64) -> Self {
+        self.vfov = vfov;
+        self
+    }
+
+    /// How pixels are mapped to rays. The field of view and aperture are only used by the perspective projection
+    pub fn projection(mut self, projection: Projection) -> Self {
+        self.projection = projection;
+        self
+    }
+
+    /// The defocus angle in degrees. Larger angles blur things that are further from the focus distance
+    pub fn aperture(mut self, defocus_angle: f64) -> Self {
+        self.defocus_angle = defocus_angle;
+        self
+    }
+
+    /// Sets the shape of the aperture and the other lens effects that show up out of focus
+    pub fn bokeh(mut self, bokeh: Bokeh) -> Self {
+        self.bokeh = bokeh;
+        self
+    }
+
+    /// Focuses the camera at a fixed distance along the view direction
+    pub fn focus_distance(mut self, focus_distance: f64) -> Self {
+        self.focus = Focus::Distance(focus_distance);
+        self
+    }
+
+    /// Focuses the camera on a point in the scene. The focus distance is found when the camera is built
+    pub fn focus_on(mut self, point: Point3) -> Self {
+        self.focus = Focus::Point(point);
+        self
+    }
+
+    /// The width and height of the final image in pixels
+    pub fn resolution(mut self, img_width: i64, img_height: i64) -> Self {
+        self.img_width = img_width;
+        self.img_height = img_height;
+        self
+    }
+
+    /// The reconstruction filter that spreads each sample over the pixels around it
+    pub fn filter(mut self, filter: Filter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// The amount of rays sent out pr pixel
+    pub fn samples(mut self, samples_pr_pixel: i64) -> Self {
+        self.samples_pr_pixel = samples_pr_pixel;
+        self
+    }
+
+    /// The max amount of times a ray bounces before it is stopped
+    pub fn bounces(mut self, max_light_bounces: i32) -> Self {
+        self.max_light_bounces = max_light_bounces;
+        self
+    }
+
+    /// Turns spectral rendering on or off. In spectral mode each path carries a set of wavelengths
+    /// instead of rgb light, which is slower, but makes dispersive glass split light into colors
+    pub fn spectral(mut self, spectral: bool) -> Self {
+        self.spectral = spectral;
+        self
+    }
+
+    /// Fills the whole scene with fog, which fades what the camera sees towards the fog color with distance.
+    /// The background is infinitely far away, so it is hidden behind the fog color. Only the rays from the camera
+    /// go through the fog, and light that bounces between objects isn't fogged. None means no fog
+    pub fn fog(mut self, fog: Option<Fog>) -> Self {
+        self.fog = fog;
+        self
+    }
+
+    /// Sets when the shutter opens and closes. Objects that move in this time are blurred along their path
+    pub fn shutter(mut self, open: f64, close: f64) -> Self {
+        self.shutter_open = open;
+        self.shutter_close = close;
+        self
+    }
+
+    /// Sets the objects that diffuse surfaces send some of their rays towards. These should be the emissive objects
+    /// of the scene, and have to be objects that can be sampled, like quads, disks and rects
+    pub fn lights(mut self, lights: Vec<Box<dyn Hitable>>) -> Self {
+        self.lights = lights;
+        self
+    }
+
+    /// Sets a constant background color instead of the sky. None means the sky
+    pub fn background(mut self, background: Option<Color>) -> Self {
+        self.background = background;
+        self
+    }
+
+    /// Renders a view for each eye into the same image. The resolution is the size of each view.
+    /// None renders a single view
+    pub fn stereo(mut self, stereo: Option<Stereo>) -> Self {
+        self.stereo = stereo;
+        self
+    }
+
+    /// Only renders a rectangle of the image. The rectangle is in pixels of the whole output image,
+    /// so for stereo images it is placed in the image holding both views
+    pub fn region(mut self, region: Option<Region>) -> Self {
+        self.region = region;
+        self
+    }
+
+    /// Saves the progress of the render regularly, and when it is done
+    pub fn checkpoint(mut self, checkpoint: Option<CheckpointSettings>) -> Self {
+        self.checkpoint = checkpoint;
+        self
+    }
+
+    /// Continues a render from saved progress. The render keeps going until it has the amount of samples
+    /// set on the camera, so a finished render can get more samples by raising it.
+    /// The checkpoint is only read, so it can be shared with whatever built the world from its scene seed
+    pub fn resume(mut self, resume: Option<Arc<Checkpoint>>) -> Self {
+        self.resume = resume;
+        self
+    }
+
+    /// A token that stops the render when it is cancelled. The image is still written from the samples taken so far
+    pub fn cancel_token(mut self, cancel: CancelToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Stops the render when it has run for this long, and writes the image from the samples taken so far
+    pub fn time_budget(mut self, time_budget: Option<Duration>) -> Self {
+        self.time_budget = time_budget;
+        self
+    }
+
+    /// Where the progress of the render is shown. Nothing is shown by default
+    pub fn progress(mut self, progress: Arc<dyn ProgressSink>) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    /// A server that the image is shown on after every finished pass
+    pub fn preview(mut self, preview: Option<Arc<PreviewServer>>) -> Self {
+        self.preview = preview;
+        self
+    }
+
+    /// Checks the settings and creates the camera. Returns an error describing the first invalid setting
+    pub fn build(self) -> error::Result<Camera> {
+        self.build_camera().map_err(Error::InvalidSettings)
+    }
+
+    fn build_camera(self) -> Result<Camera, String> {
+        if self.img_width <= 0 || self.img_height <= 0 {
+            return Err(format!(
+                "the resolution has to be positive, got {}x{}",
+                self.img_width, self.img_height
+            ));
+        }
+        if self.samples_pr_pixel <= 0 {
+            return Err(format!(
+                "the camera needs at least one sample pr pixel, got {}",
+                self.samples_pr_pixel
+            ));
+        }
+        if self.max_light_bounces < 0 {
+            return Err(format!(
+                "the max amount of bounces can't be negative, got {}",
+                self.max_light_bounces
+            ));
+        }
+        if !(self.vfov > 0.0 && self.vfov < 180.0) {
+            return Err(format!(
+                "the field of view has to be between 0 and 180 degrees, got {}",
+                self.vfov
+            ));
+        }
+        if !(0.0..180.0).contains(&self.defocus_angle) {
+            return Err(format!(
+                "the aperture has to be between 0 and 180 degrees, got {}",
+                self.defocus_angle
+            ));
+        }
+        if self.shutter_close < self.shutter_open {
+            return Err(format!(
+                "the shutter closes at {} before it opens at {}",
+                self.shutter_close, self.shutter_open
+            ));
+        }
+
+        self.projection.validate()?;
+        self.bokeh.validate()?;
+        self.filter.validate()?;
+        let (width, height) = match &self.stereo {
+            Some(stereo) => stereo.output_size(self.img_width, self.img_height),
+            None => (self.img_width, self.img_height),
+        };
+        if let Some(region) = &self.region {
+            region.validate(width, height)?;
+        }
+        if let Some(checkpoint) = &self.resume {
+            if checkpoint.size() != (width, height) {
+                let (checkpoint_width, checkpoint_height) = checkpoint.size();
+                return Err(format!(
+                    "the checkpoint is {}x{}, but the image is {}x{}",
+                    checkpoint_width, checkpoint_height, width, height
+                ));
+            }
+            // the film only has the sums of the samples, so they have to keep landing in the same pixels
+            if checkpoint.filter() != self.filter {
+                return Err(format!(
+                    "the checkpoint was rendered with the filter {:?}, but the camera uses {:?}",
+                    checkpoint.filter(),
+                    self.filter
+                ));
+            }
+            let region = self.region.as_ref().map(|region| region.bounds());
+            if checkpoint.region() != region {
+                return Err(format!(
+                    "the checkpoint was rendered in the region {:?}, but the camera renders {:?}",
+                    checkpoint.region(),
+                    region
+                ));
+            }
+            if let Some(settings) = &self.checkpoint {
+                if checkpoint.scene_seed() != settings.scene_seed() {
+                    return Err(format!(
+                        "the checkpoint was rendered with the scene seed {}, but the scene seed is {}",
+                        checkpoint.scene_seed(),
+                        settings.scene_seed()
+                    ));
+                }
+            }
+        }
+        if let Some(stereo) = &self.stereo {
+            stereo.validate()?;
+            if let Projection::Orthographic { .. } = self.projection {
+                return Err("an orthographic camera can't render in stereo".to_string());
+            }
+        }
+
+        let view = self.look_at - self.look_from;
+        if view.near_zero() {
+            return Err("the camera can't look at the point it is placed at".to_string());
+        }
 
         // Camera basic vectors
-        let w = (look_from - look_at).unit_vec();
-        let u = vup.cross_product(&w).unit_vec();
+        let w = -view.unit_vec();
+        let u = self.vup.cross_product(&w);
+        if u.near_zero() {
+            return Err("the up direction can't be parallel to the view direction".to_string());
+        }
+        let u = u.unit_vec();
         let v = w.cross_product(&u);
 
-        let focus_distance = 10.0;
-        let defocus_angle = 0.0;
+        let focus_distance = match self.focus {
+            Focus::Distance(distance) => distance,
+            Focus::Point(point) => (point - self.look_from).dot_product(&-w),
+            Focus::LookAt => view.length(),
+        };
+        if focus_distance.is_nan() || focus_distance <= 0.0 {
+            return Err(format!(
+                "the focus distance has to be in front of the camera, got {}",
+                focus_distance
+            ));
+        }
 
-        let defocus_radius = focus_distance * Camera::degrees_to_radians(defocus_angle / 2.0).tan();
+        let defocus_radius =
+            focus_distance * Camera::degrees_to_radians(self.defocus_angle / 2.0).tan();
         let defocus_disk_u = u * defocus_radius;
         let defocus_disk_v = v * defocus_radius;
 
-        // image dimensions
-        let aspect_ratio = 16.0 / 9.0;
-        let img_width = 400;
-        let img_height = (img_width as f64 / aspect_ratio) as i64;
-
-        // Viewport dimensions:
-        let vfov = 90.0;
-        let theta = Camera::degrees_to_radians(vfov);
-        let h = (theta / 2.0).tan();
-        let viewport_height = 2.0 * h * focus_distance;
+        // Viewport dimensions. An orthographic viewport goes through the camera position instead of the focus plane
+        let aspect_ratio = self.img_width as f64 / self.img_height as f64;
+        let (viewport_height, viewport_center) = match self.projection {
+            Projection::Orthographic { height } => (height, self.look_from),
+            _ => {
+                let h = (Camera::degrees_to_radians(self.vfov) / 2.0).tan();
+                (
+                    2.0 * h * focus_distance,
+                    self.look_from - w * focus_distance,
+                )
+            }
+        };
         let viewport_width = viewport_height * aspect_ratio;
 
         let viewport_u = u * viewport_width;
         let viewport_v = -v * viewport_height;
-        let pixel_delta_u = viewport_u / img_width as f64;
-        let pixel_delta_v = viewport_v / img_height as f64;
-        let upper_left = look_from - (w * focus_distance) - viewport_u / 2.0 - viewport_v / 2.0;
+        let pixel_delta_u = viewport_u / self.img_width as f64;
+        let pixel_delta_v = viewport_v / self.img_height as f64;
+        let upper_left = viewport_center - viewport_u / 2.0 - viewport_v / 2.0;
         let pixel_00_loc = upper_left + (pixel_delta_v + pixel_delta_u) * 0.5;
 
-        // Render settings
-        let samples_pr_pixel = 50;
-        let max_depth = 50;
-
-        Self {
-            samples_pr_pixel,
-            max_light_bounces: max_depth,
-            img_width,
-            img_height,
-            aspect_ratio,
-            vfov,
-            vup,
-            look_from,
-            look_at,
-            v,
+        Ok(Camera {
+            samples_pr_pixel: self.samples_pr_pixel,
+            max_light_bounces: self.max_light_bounces,
+            look_from: self.look_from,
+            projection: self.projection,
             u,
+            v,
             w,
             pixel_delta_u,
             pixel_delta_v,
             pixel_00_loc,
+            img_width: self.img_width,
+            img_height: self.img_height,
+            filter: self.filter,
             focus_distance,
-            defocus_angle,
+            defocus_angle: self.defocus_angle,
             defocus_disk_u,
             defocus_disk_v,
+            bokeh: self.bokeh,
+            spectral: self.spectral,
+            fog: self.fog,
+            shutter_open: self.shutter_open,
+            shutter_close: self.shutter_close,
+            lights: self.lights,
+            background: self.background,
+            stereo: self.stereo,
+            region: self.region,
+            checkpoint: self.checkpoint,
+            resume: self.resume,
+            cancel: self.cancel,
+            time_budget: self.time_budget,
+            progress: self.progress,
+            preview: self.preview,
+        })
+    }
+}
+
+impl Camera {
+    /// Starts building a camera with the default settings
+    pub fn builder() -> CameraBuilder {
+        CameraBuilder::default()
+    }
+
+    /// The size of the rendered image in pixels. A stereo image holds both views
+    pub fn output_size(&self) -> (i64, i64) {
+        match &self.stereo {
+            Some(stereo) => stereo.output_size(self.img_width, self.img_height),
+            None => (self.img_width, self.img_height),
         }
     }
 }
 
 impl Camera {
-    fn defocus_disk_sample(&self, rng: &mut ThreadRng) -> Point3 {
-        let p = Point3::random_in_unit_circle(rng);
+    /// Samples a point on the lens for a ray through (x, y) of the view. Returns None if the lens barrel blocks the ray
+    fn defocus_disk_sample(&self, x: f64, y: f64, rng: &mut impl Rng) -> Option<Point3> {
+        // the position in the image, where the corners are 1 away from the center
+        let half_diagonal = (self.img_width as f64).hypot(self.img_height as f64) / 2.0;
+        let image_position = (
+            (x - self.img_width as f64 / 2.0) / half_diagonal,
+            (self.img_height as f64 / 2.0 - y) / half_diagonal,
+        );
+        let (px, py) = self.bokeh.sample(image_position, rng)?;
 
-        return self.look_from + (self.defocus_disk_u * p.x()) + (self.defocus_disk_v * p.y());
+        Some(self.look_from + (self.defocus_disk_u * px) + (self.defocus_disk_v * py))
+    }
+
+    /// Whether each sample focuses at a distance depending on its color
+    fn chromatic_aberration(&self) -> bool {
+        self.bokeh.chromatic_aberration() > 0.0
+            && self.defocus_angle > 0.0
+            && matches!(self.projection, Projection::Perspective)
     }
 
     fn degrees_to_radians(degrees: f64) -> f64 {
         degrees * PI / 180.0
     }
 
-    pub fn get_ray(&self, x: f64, y: f64, rng: &mut ThreadRng) -> Ray {
-        let pixel_center = self.pixel_00_loc
-            + self.pixel_delta_u * (x + rng.gen::<f64>())
-            + self.pixel_delta_v * (y + rng.gen::<f64>());
+    /// Creates a ray through the point (x, y) of the view, following the projection of the camera.
+    /// The pixel (i, j) covers i to i + 1 and j to j + 1. The eye is None for a single view, and chroma is where the color of the light is between
+    /// blue at -1 and red at 1, which moves the focus when the lens has chromatic aberration.
+    /// Returns None if the point is outside the image, like the corners of a fisheye image
+    pub fn get_ray(
+        &self,
+        x: f64,
+        y: f64,
+        eye: Option<Eye>,
+        chroma: f64,
+        rng: &mut impl Rng,
+    ) -> Option<Ray> {
+        // pixel_00_loc is the center of the top left pixel, which is half a pixel in from the corner
+        let pixel_center =
+            self.pixel_00_loc + self.pixel_delta_u * (x - 0.5) + self.pixel_delta_v * (y - 0.5);
+
+        // how far the eye is moved to the right of the camera, and the distance where the eyes' views line up
+        let (eye_offset, convergence) = match (eye, &self.stereo) {
+            (Some(eye), Some(stereo)) => (
+                eye.side() * stereo.eye_offset(),
+                stereo.convergence_or(self.focus_distance),
+            ),
+            _ => (0.0, self.focus_distance),
+        };
+
+        let ray = match self.projection {
+            Projection::Perspective => {
+                // the eyes look in parallel, with their views shifted so they line up at the convergence distance
+                let eye_position = self.look_from + self.u * eye_offset;
+                let converge_point = self.look_from
+                    + (pixel_center - self.look_from) * (convergence / self.focus_distance);
+                let direction = converge_point - eye_position;
+
+                if self.defocus_angle <= 0.0 {
+                    Ray::new(eye_position, direction)
+                } else {
+                    let focus_distance =
+                        self.focus_distance * (1.0 + chroma * self.bokeh.chromatic_aberration());
+                    let focus_point = eye_position
+                        + direction * (focus_distance / direction.dot_product(&-self.w));
+                    let ray_origin = self.defocus_disk_sample(x, y, rng)? + self.u * eye_offset;
+                    Ray::new(ray_origin, focus_point - ray_origin)
+                }
+            }
+            Projection::Orthographic { .. } => Ray::new(pixel_center, -self.w),
+            projection => {
+                let aspect_ratio = self.img_width as f64 / self.img_height as f64;
+                let local = projection.panorama_direction(
+                    x / self.img_width as f64,
+                    y / self.img_height as f64,
+                    aspect_ratio,
+                )?;
+                let direction = self.u * local.x() + self.v * local.y() - self.w * local.z();
+
+                // omni-directional stereo: each eye sits on a circle around the camera, to the side of the ray's
+                // horizontal direction. The circle shrinks towards the poles, where there is no left or right
+                let side = self.u * local.z() + self.w * local.x();
+                let eye_position = self.look_from + side * eye_offset;
+                let converge_point = self.look_from + direction * convergence;
+                Ray::new(eye_position, converge_point - eye_position)
+            }
+        };
 
-        let ray_origin = if self.defocus_angle <= 0.0 {
-            self.look_from
+        let time = if self.shutter_close > self.shutter_open {
+            rng.gen_range(self.shutter_open..self.shutter_close)
         } else {
-            self.defocus_disk_sample(rng)
+            self.shutter_open
         };
 
-        Ray::new(ray_origin, pixel_center - ray_origin)
+        Some(ray.with_time(time))
     }
 
-    ///function for making a quick color for the rays
-    pub fn par_ray_color<T: Hitable>(r: &Ray, world: Arc<T>, depth: i32) -> Color {
-        // If we've exceeded the ray bounce limit, no more light is gathered.
-        if depth <= 0 {
-            return Color::new();
+    /// Sends a single sample through the point (x, y) of the output image, and returns the color it brings back
+    fn sample_pixel(&self, x: f64, y: f64, world: &dyn Hitable, rng: &mut impl Rng) -> Color {
+        let (eye, x, y) = match &self.stereo {
+            Some(stereo) => {
+                let (eye, x, y) = stereo.split(x, y, self.img_width, self.img_height);
+                (Some(eye), x, y)
+            }
+            None => (None, x, y),
+        };
+
+        if self.spectral {
+            let mut wavelengths = SampledWavelengths::sample(rng.gen());
+            let chroma = if self.chromatic_aberration() {
+                // the lens bends the wavelengths differently, so only the hero wavelength follows this ray
+                wavelengths.terminate_secondary();
+                (wavelengths.hero() - LAMBDA_MIN) / (LAMBDA_MAX - LAMBDA_MIN) * 2.0 - 1.0
+            } else {
+                0.0
+            };
+
+            let Some(r) = self.get_ray(x, y, eye, chroma, rng) else {
+                stats::count_termination(Termination::Blocked);
+                return Color::new();
+            };
+            let r = r.with_wavelength(Some(wavelengths.hero()));
+            let radiance = self.ray_spectrum(&r, world, self.max_light_bounces, &mut wavelengths);
+            wavelengths.radiance_to_color(&radiance)
+        } else if self.chromatic_aberration() {
+            // each sample only carries one of the color channels, focused at the distance of that channel
+            let (chroma, channel) = match rng.gen_range(0..3) {
+                0 => (1.0, Color::from_rgb(3, 0, 0)),
+                1 => (0.0, Color::from_rgb(0, 3, 0)),
+                _ => (-1.0, Color::from_rgb(0, 0, 3)),
+            };
+
+            let Some(r) = self.get_ray(x, y, eye, chroma, rng) else {
+                stats::count_termination(Termination::Blocked);
+                return Color::new();
+            };
+            self.ray_color(&r, world, self.max_light_bounces) * channel
+        } else {
+            let Some(r) = self.get_ray(x, y, eye, 0.0, rng) else {
+                stats::count_termination(Termination::Blocked);
+                return Color::new();
+            };
+            self.ray_color(&r, world, self.max_light_bounces)
+        }
+    }
+
+    ///Render the image in parallel with threads, and write it to the file. Returns the stats of the render
+    pub fn render_with_threads<T: Hitable>(
+        &self,
+        world: &T,
+        file: &mut BufWriter<File>,
+    ) -> error::Result<RenderStats> {
+        let (film, render_stats) = self.render_film_with_threads(world)?;
+        self.write_film(&film, file)?;
+        Ok(render_stats)
+    }
+
+    /// Renders the image and returns it instead of writing it to a file, on threads or on this thread only.
+    /// With a region only the region is in the image
+    pub fn render_image(
+        &self,
+        world: &dyn Hitable,
+        threads: bool,
+    ) -> error::Result<(Image, RenderStats)> {
+        let (film, render_stats) = if threads {
+            self.render_film_with_threads(world)?
+        } else {
+            self.render_film(world)?
+        };
+        Ok((Image::from_film(&film, self.pixel_bounds()), render_stats))
+    }
+
+    /// Renders the image onto a film in parallel with threads.
+    /// A thread that panics stops the render, and the error tells which rows it was rendering
+    fn render_film_with_threads(&self, world: &dyn Hitable) -> error::Result<(Film, RenderStats)> {
+        let start = Instant::now();
+        let deadline = self.time_budget.map(|budget| Instant::now() + budget);
+
+        let (mut film, mut passes, seed) = self.start_state()?;
+        let mut last_checkpoint = Instant::now();
+        let progress = self.progress_tracker(passes);
+        let mut render_stats = RenderStats::default();
+
+        // the image is rendered in passes of one sample pr pixel, so the progress can be saved between passes
+        std::thread::scope(|scope| -> error::Result<()> {
+            let mut threads = RenderThreads::spawn(scope, self, world, seed, deadline, &progress)?;
+            while passes < self.samples_pr_pixel {
+                if self.should_stop(deadline) {
+                    // stopped between passes, so the film only has finished passes in it
+                    self.checkpoint(&film, seed, passes, &mut last_checkpoint, true);
+                    break;
+                }
+                let (pass_films, finished, pass_stats) = threads.render_pass(passes)?;
+                render_stats.merge(&pass_stats);
+
+                if !self.finish_pass(
+                    &mut film,
+                    pass_films,
+                    finished,
+                    seed,
+                    &mut passes,
+                    &mut last_checkpoint,
+                ) {
+                    break;
+                }
+            }
+            Ok(())
+        })?;
+
+        progress.finish();
+        self.finish_render(&film, seed, passes, &mut last_checkpoint);
+
+        render_stats.set_elapsed(start.elapsed());
+        Ok((film, render_stats))
+    }
+
+    /// Renders the image on worker processes, which are handed one pass at a time by the coordinator,
+    /// and writes it to the file. Returns the stats of the render, added up from all the workers
+    pub fn render_distributed(
+        &self,
+        coordinator: Coordinator,
+        file: &mut BufWriter<File>,
+    ) -> error::Result<RenderStats> {
+        let (film, render_stats) = self.render_film_distributed(coordinator)?;
+        self.write_film(&film, file)?;
+        Ok(render_stats)
+    }
+
+    /// Renders the image onto a film on worker processes
+    fn render_film_distributed(
+        &self,
+        coordinator: Coordinator,
+    ) -> error::Result<(Film, RenderStats)> {
+        let start = Instant::now();
+        let ((x0, y0), (x1, y1)) = self.pixel_bounds();
+        let deadline = self.time_budget.map(|budget| Instant::now() + budget);
+
+        let (mut film, mut passes, seed) = self.start_state()?;
+        let mut last_checkpoint = Instant::now();
+        let progress = self.progress_tracker(passes);
+        let mut render_stats = RenderStats::default();
+
+        let farm = coordinator.start(seed, passes..self.samples_pr_pixel, self.output_size());
+        // the workers can finish the passes in any order, but the checkpoints need the first passes to be done,
+        // so passes that are finished early wait here until the passes before them are in
+        let mut waiting = BTreeMap::new();
+
+        while passes < self.samples_pr_pixel && !self.should_stop(deadline) {
+            for message in farm.messages() {
+                self.progress.message(&message);
+            }
+            let Some(result) = farm.next_result(Duration::from_millis(100)) else {
+                if let Err(err) = farm.check_workers() {
+                    farm.stop();
+                    return Err(err);
+                }
+                continue;
+            };
+
+            let mut pass_film = self.new_film();
+            pass_film.set_data(result.data())?;
+            waiting.insert(result.pass(), pass_film);
+
+            let rows = (y1 - y0) as u64;
+            progress.tiles_done(rows, rows * (x1 - x0) as u64, result.stats().rays());
+            render_stats.merge(result.stats());
+
+            while let Some(pass_film) = waiting.remove(&passes) {
+                self.finish_pass(
+                    &mut film,
+                    std::iter::once(&pass_film),
+                    true,
+                    seed,
+                    &mut passes,
+                    &mut last_checkpoint,
+                );
+            }
         }
+        farm.stop();
 
-        // let world = **world.lock().unwrap();
-        if let Some(rec) = world.hit(r, 0.001, INFINITY) {
-            if let Some((scattered, attenuation)) = rec.material().scatter(r, &rec) {
-                return attenuation * Camera::par_ray_color(&scattered, world, depth - 1);
+        // the passes that finished after a pass that never did are still added to the image, but not to the checkpoint.
+        // finish_pass saves the passes that are in order before adding them
+        if waiting.is_empty() {
+            if passes < self.samples_pr_pixel {
+                self.checkpoint(&film, seed, passes, &mut last_checkpoint, true);
             }
+        } else {
+            self.finish_pass(
+                &mut film,
+                waiting.values(),
+                false,
+                seed,
+                &mut passes,
+                &mut last_checkpoint,
+            );
+        }
 
-            return Color::new();
+        progress.finish();
+        self.finish_render(&film, seed, passes, &mut last_checkpoint);
+
+        render_stats.set_elapsed(start.elapsed());
+        Ok((film, render_stats))
+    }
+
+    /// Renders one pass of the image on all the cores, for a coordinator. Every pixel gets one sample
+    pub fn render_pass<T: Hitable>(
+        &self,
+        world: &T,
+        seed: u64,
+        pass: i64,
+    ) -> error::Result<(Film, RenderStats)> {
+        let progress = ProgressTracker::new(Arc::new(Quiet), 0, 0);
+
+        std::thread::scope(|scope| {
+            let mut threads = RenderThreads::spawn(scope, self, world, seed, None, &progress)?;
+            let (thread_films, _, stats) = threads.render_pass(pass)?;
+            let mut film = self.new_film();
+            for thread_film in thread_films {
+                film.merge(thread_film);
+            }
+            Ok((film, stats))
+        })
+    }
+
+    /// Takes one sample for every pixel in the rows, on the columns of the rendered part of the image.
+    /// Returns false if the render was stopped before all the rows were done
+    fn render_rows(
+        &self,
+        film: &mut Film,
+        (pass, seed): (i64, u64),
+        rows: Range<i64>,
+        world: &dyn Hitable,
+        deadline: Option<Instant>,
+        progress: &ProgressTracker,
+    ) -> bool {
+        let ((x0, _), (x1, _)) = self.pixel_bounds();
+        for y in rows {
+            if self.should_stop(deadline) {
+                return false;
+            }
+
+            let rays_before = stats::rays_cast();
+            let mut rng = pass_rng(seed, pass, y);
+            random::seed(rng.gen());
+            for x in x0..x1 {
+                self.sample_into(film, x, y, world, &mut rng);
+            }
+            progress.tiles_done(1, (x1 - x0) as u64, stats::rays_cast() - rays_before);
         }
+        true
+    }
 
-        let unit_vec = r.direction().unit_vec();
-        let t = 0.5 * (unit_vec.y() + 1.0);
+    /// Tracks the progress of the passes that are left to render
+    fn progress_tracker(&self, passes: i64) -> ProgressTracker {
+        let ((x0, y0), (x1, y1)) = self.pixel_bounds();
+        let tiles = ((y1 - y0) * (self.samples_pr_pixel - passes).max(0)) as u64;
+        ProgressTracker::new(self.progress.clone(), tiles, tiles * (x1 - x0) as u64)
+    }
 
-        return Color::from_rgb(1, 1, 1) * (1.0 - t) + Color::from_rgb(0.5, 0.7, 1.0) * t;
+    /// Whether the render has been cancelled or has run out of time
+    fn should_stop(&self, deadline: Option<Instant>) -> bool {
+        self.cancel.is_cancelled() || deadline.is_some_and(|deadline| Instant::now() >= deadline)
     }
 
-    ///Render the image in parallel with threads
-    pub fn render_with_threads<'a, T: Hitable + 'static>(
-        &'static self,
-        world: Arc<T>,
-        file: &'a mut BufWriter<File>,
-    ) {
-        // First write the header for the image file
-        file.write_all(format!("P3\n{} {}\n255\n", self.img_width, self.img_height).as_bytes())
-            .expect("couldnt write header");
-
-        // find out how many threads are available
-        let available_threads = std::thread::available_parallelism()
-            .expect("Couldnt get number of available cores for parallelism")
-            .get();
-
-        // the amount of pixelrows each thread is supposed to render
-        let chunk_size = self.img_height / available_threads as i64;
-
-        // Vector to store the pixels in
-        let pixel_results: Arc<Mutex<Vec<Option<Vec<_>>>>> =
-            Arc::new(Mutex::new(vec![None; available_threads]));
-
-        let new_self = Arc::new(self.clone());
-
-        // spawning threads and rendering
-        let handles: Vec<_> = (0..available_threads)
-            .into_iter()
-            .map(|i| {
-                let start = chunk_size * i as i64;
-                let end = if i - 1 != available_threads {
-                    chunk_size * (i + 1) as i64
-                } else {
-                    self.img_height
-                };
-
-                let arc_self = new_self.clone();
-                let arc_world = world.clone();
-                let pixels_clone = pixel_results.clone();
-
-                std::thread::spawn(move || {
-                    let mut rng = rand::thread_rng();
-
-                    let mut pixels = Vec::new();
-                    for y in start..end {
-                        for x in 0..arc_self.img_width {
-                            let mut pixel_color = Color::new();
-
-                            for _s in 0..arc_self.samples_pr_pixel {
-                                let r = arc_self.get_ray(x as f64, y as f64, &mut rng);
-                                pixel_color = pixel_color
-                                    + Camera::par_ray_color(
-                                        &r,
-                                        arc_world.to_owned(),
-                                        arc_self.max_light_bounces,
-                                    );
-                            }
-                            pixels.push(pixel_color);
-                        }
-                    }
+    /// Adds the films of a pass to the image, saves a checkpoint if it is time for one, and updates the preview.
+    /// A pass that was stopped before it finished is still added to the image, but not to the checkpoint,
+    /// so a resumed render does the whole pass again. Returns whether the render should continue
+    fn finish_pass<'a>(
+        &self,
+        film: &mut Film,
+        pass_films: impl Iterator<Item = &'a Film>,
+        finished: bool,
+        seed: u64,
+        passes: &mut i64,
+        last_checkpoint: &mut Instant,
+    ) -> bool {
+        if !finished {
+            self.checkpoint(film, seed, *passes, last_checkpoint, true);
+        }
 
-                    pixels_clone.lock().expect("couldnt lock pixel_results")[i] = Some(pixels);
-                })
-            })
-            .collect();
+        for pass_film in pass_films {
+            film.merge(pass_film);
+        }
+        if finished {
+            *passes += 1;
+            self.checkpoint(film, seed, *passes, last_checkpoint, false);
+        }
 
-        for (_, handle) in handles.into_iter().enumerate() {
-            handle.join().expect("cant join thread");
+        if let Some(preview) = &self.preview {
+            preview.publish(film, *passes, self.samples_pr_pixel);
         }
+        finished
+    }
 
-        let mut pixels = Vec::new();
-        for i in pixel_results
-            .lock()
-            .unwrap()
-            // .into_inner()
-            // .expect("couldnt get inner value of mutex")
-            .clone()
-        // .iter()
-        {
-            pixels.push(i);
+    /// Saves the last checkpoint, and tells how far the render got if it was stopped early
+    fn finish_render(&self, film: &Film, seed: u64, passes: i64, last_checkpoint: &mut Instant) {
+        if passes < self.samples_pr_pixel {
+            self.progress.message(&format!(
+                "Render stopped after {} of {} samples pr pixel",
+                passes, self.samples_pr_pixel
+            ));
+        } else {
+            self.checkpoint(film, seed, passes, last_checkpoint, true);
         }
-        let pixels: Vec<Color> = pixels.into_iter().flat_map(|i| i.unwrap()).collect();
+    }
 
-        // write to file
-        for col in pixels {
-            file.write_all(
-                format!("\n{}", col.write_color(self.samples_pr_pixel as f64)).as_bytes(),
-            )
-            .expect("couldnt write all");
+    /// The film to render onto, the amount of passes that are already in it, and the seed of the render.
+    /// A resumed render continues from its checkpoint, and a new render starts from an empty film
+    fn start_state(&self) -> error::Result<(Film, i64, u64)> {
+        let mut film = self.new_film();
+        match &self.resume {
+            Some(checkpoint) => {
+                checkpoint.restore(&mut film)?;
+                Ok((film, checkpoint.passes(), checkpoint.seed()))
+            }
+            None => Ok((film, 0, rand::random())),
         }
     }
 
+    /// Saves the progress of the render if it is time for a new checkpoint, or if the render is done.
+    /// A failed checkpoint is reported to the progress sink, but doesn't stop the render
+    fn checkpoint(&self, film: &Film, seed: u64, passes: i64, last: &mut Instant, done: bool) {
+        let Some(settings) = &self.checkpoint else {
+            return;
+        };
+        if !done && last.elapsed() < settings.interval() {
+            return;
+        }
+
+        let seeds = (seed, settings.scene_seed());
+        let region = self.region.as_ref().map(|region| region.bounds());
+        if let Err(err) = Checkpoint::save(settings.path(), seeds, passes.max(0), film, region) {
+            self.progress.message(&format!(
+                "couldn't save checkpoint to {}: {}",
+                settings.path().display(),
+                err
+            ));
+        }
+        *last = Instant::now();
+    }
+
+    /// The first and one past the last pixel that is rendered, as ((x0, y0), (x1, y1))
+    fn pixel_bounds(&self) -> ((i64, i64), (i64, i64)) {
+        match &self.region {
+            Some(region) => region.bounds(),
+            None => ((0, 0), self.output_size()),
+        }
+    }
+
+    /// Writes the finished film to the file, or only the region of it when there is one
+    fn write_film(&self, film: &Film, file: &mut BufWriter<File>) -> std::io::Result<()> {
+        match &self.region {
+            Some(region) => region.write_ppm(film, file),
+            None => film.write_ppm(file),
+        }
+    }
+
+    /// Creates an empty film for the output image
+    fn new_film(&self) -> Film {
+        let (width, height) = self.output_size();
+        Film::new(width, height, self.filter).with_views(self.img_width, self.img_height)
+    }
+
+    /// Sends a sample through a random point of the pixel at (x, y) of the output image, and adds it to the film
+    fn sample_into(
+        &self,
+        film: &mut Film,
+        x: i64,
+        y: i64,
+        world: &dyn Hitable,
+        rng: &mut impl Rng,
+    ) {
+        let (sx, sy) = (x as f64 + rng.gen::<f64>(), y as f64 + rng.gen::<f64>());
+        let color = self.sample_pixel(sx, sy, world, rng);
+        film.add_sample(sx, sy, color);
+    }
+
     ///function for making a quick color for the rays
-    pub fn ray_color(r: &Ray, world: &dyn Hitable, depth: i32) -> Color {
+    pub fn ray_color(&self, r: &Ray, world: &dyn Hitable, depth: i32) -> Color {
         // If we've exceeded the ray bounce limit, no more light is gathered.
         if depth <= 0 {
+            stats::count_termination(Termination::MaxDepth);
             return Color::new();
         }
+        self.count_ray(depth);
+
+        let (color, distance) = if let Some(rec) = world.hit(r, 0.00001, f64::INFINITY) {
+            let emitted = rec.material().emitted(r, &rec);
+            let color = match rec.material().scatter(r, &rec) {
+                Some((scattered, attenuation)) => {
+                    let (scattered, attenuation) =
+                        self.sample_lights(r, &rec, scattered, attenuation);
+                    let scattered = scattered.with_time(r.time());
+                    emitted + attenuation * self.ray_color(&scattered, world, depth - 1)
+                }
+                None => {
+                    stats::count_termination(Termination::Absorbed);
+                    emitted
+                }
+            };
+            (color, rec.t() * r.direction().length())
+        } else {
+            stats::count_termination(Termination::Escaped);
+            (self.background(r), f64::INFINITY)
+        };
 
-        if let Some(rec) = world.hit(r, 0.00001, INFINITY) {
-            if let Some((scattered, attenuation)) = rec.material().scatter(r, &rec) {
-                return attenuation * Camera::ray_color(&scattered, world, depth - 1);
+        // the fog absorbs some of the light, and adds its own color instead
+        match self.camera_fog(depth) {
+            Some(fog) => {
+                let transmittance = fog.transmittance(distance);
+                color * transmittance + fog.color() * (1.0 - transmittance)
             }
+            None => color,
+        }
+    }
 
-            return Color::new();
+    /// The fog between the camera and what a camera ray hit, or the background as the far end of the fog for rays
+    /// that escape. The fog is only a camera effect: rays that have bounced aren't fogged, so the light reaching an
+    /// object isn't dimmed by the fog on the way there. A ConstantMedium fogs every ray that goes through it
+    fn camera_fog(&self, depth: i32) -> Option<Fog> {
+        self.fog.filter(|_| depth == self.max_light_bounces)
+    }
+
+    /// Counts a ray in the render stats. Rays with all the bounces left come from the camera
+    fn count_ray(&self, depth: i32) {
+        if depth == self.max_light_bounces {
+            stats::count_primary_ray();
+        } else {
+            stats::count_secondary_ray();
+        }
+    }
+
+    /// Same as ray_color, but the light is carried as a spectrum over the sampled wavelengths
+    pub fn ray_spectrum(
+        &self,
+        r: &Ray,
+        world: &dyn Hitable,
+        depth: i32,
+        wavelengths: &mut SampledWavelengths,
+    ) -> SampledSpectrum {
+        // If we've exceeded the ray bounce limit, no more light is gathered.
+        if depth <= 0 {
+            stats::count_termination(Termination::MaxDepth);
+            return SampledSpectrum::constant(0.0);
+        }
+        self.count_ray(depth);
+
+        let (radiance, distance) = if let Some(rec) = world.hit(r, 0.00001, f64::INFINITY) {
+            let emitted =
+                SampledSpectrum::from_color(&rec.material().emitted(r, &rec), wavelengths);
+            let radiance = match rec.material().scatter(r, &rec) {
+                Some((scattered, attenuation)) => {
+                    // the other wavelengths would have been bent in another direction
+                    if rec.material().is_dispersive() {
+                        wavelengths.terminate_secondary();
+                    }
+
+                    let (scattered, attenuation) =
+                        self.sample_lights(r, &rec, scattered, attenuation);
+                    let scattered = scattered
+                        .with_wavelength(r.wavelength())
+                        .with_time(r.time());
+                    emitted
+                        + SampledSpectrum::from_color(&attenuation, wavelengths)
+                            * self.ray_spectrum(&scattered, world, depth - 1, wavelengths)
+                }
+                None => {
+                    stats::count_termination(Termination::Absorbed);
+                    emitted
+                }
+            };
+            (radiance, rec.t() * r.direction().length())
+        } else {
+            stats::count_termination(Termination::Escaped);
+            (
+                SampledSpectrum::from_color(&self.background(r), wavelengths),
+                f64::INFINITY,
+            )
+        };
+
+        // the fog absorbs some of the light, and adds its own color instead
+        match self.camera_fog(depth) {
+            Some(fog) => {
+                let transmittance = fog.transmittance(distance);
+                radiance * transmittance
+                    + SampledSpectrum::from_color(&fog.color(), wavelengths) * (1.0 - transmittance)
+            }
+            None => radiance,
+        }
+    }
+
+    /// Sends half of the rays scattered off diffuse surfaces towards the lights instead, which finds small lights
+    /// much faster. The attenuation is weighted with the pdf of both strategies combined, so the result stays the same
+    fn sample_lights(
+        &self,
+        r: &Ray,
+        rec: &HitRecord,
+        scattered: Ray,
+        attenuation: Color,
+    ) -> (Ray, Color) {
+        let material = rec.material();
+        if self.lights.is_empty() || material.scattering_pdf(r, rec, &scattered) <= 0.0 {
+            return (scattered, attenuation);
+        }
+
+        let mut rng = random::rng();
+        let scattered = if rng.gen::<bool>() {
+            let light = &self.lights[rng.gen_range(0..self.lights.len())];
+            Ray::new(rec.point(), light.random_direction(rec.point()))
+        } else {
+            scattered
+        };
+
+        let scattering_pdf = material.scattering_pdf(r, rec, &scattered);
+        let light_pdf = self
+            .lights
+            .iter()
+            .map(|light| light.pdf_value(rec.point(), scattered.direction()))
+            .sum::<f64>()
+            / self.lights.len() as f64;
+
+        let pdf = 0.5 * scattering_pdf + 0.5 * light_pdf;
+        if pdf <= 0.0 {
+            return (scattered, Color::new());
+        }
+
+        (scattered, attenuation * (scattering_pdf / pdf))
+    }
+
+    /// The color that rays that dont hit anything see. Without a background color this is the sky
+    fn background(&self, r: &Ray) -> Color {
+        if let Some(color) = self.background {
+            return color;
         }
 
         let unit_vec = r.direction().unit_vec();
         let t = 0.5 * (unit_vec.y() + 1.0);
 
-        return Color::from_rgb(1, 1, 1) * (1.0 - t) + Color::from_rgb(0.5, 0.7, 1.0) * t;
+        Color::from_rgb(1, 1, 1) * (1.0 - t) + Color::from_rgb(0.5, 0.7, 1.0) * t
+    }
+
+    /// Render the image without parallelisation, and write it to the file. Returns the stats of the render
+    pub fn render<T: Hitable>(
+        &self,
+        world: &T,
+        file: &mut BufWriter<File>,
+    ) -> error::Result<RenderStats> {
+        let (film, render_stats) = self.render_film(world)?;
+        self.write_film(&film, file)?;
+        Ok(render_stats)
     }
 
-    /// Render the image without parallelisation
-    pub fn render<T: Hitable>(&self, world: &T, file: &mut BufWriter<File>) {
-        let mut stderr = std::io::stderr();
+    /// Renders the image onto a film without parallelisation
+    fn render_film(&self, world: &dyn Hitable) -> error::Result<(Film, RenderStats)> {
+        let start = Instant::now();
+        // start counting from 0, in case something was rendered on this thread before
+        stats::take();
 
-        let mut rng = rand::thread_rng();
+        let ((_, y0), (_, y1)) = self.pixel_bounds();
+        let deadline = self.time_budget.map(|budget| Instant::now() + budget);
+        let (mut film, mut passes, seed) = self.start_state()?;
+        let mut last_checkpoint = Instant::now();
+        let progress = self.progress_tracker(passes);
+        let mut pass_film = self.new_film();
 
         //render
-        file.write_all(format!("P3\n{} {}\n255\n", self.img_width, self.img_height).as_bytes())
-            .expect("couldnt write header");
-
-        let mut counter: i64 = 0;
-        for y in 0..self.img_height {
-            // prints how many coloumns of pixels remain
-            stderr
-                .write(format!("\x1b[2K \x1b[0G").as_bytes())
-                .expect("couldnt clear terminal");
-            stderr
-                .write(format!("Scanlines remaining: {}", self.img_height - y).as_bytes())
-                .expect("cant write to stderr");
-            stderr.flush().expect("couldnt flush stderr");
-
-            for x in 0..self.img_width {
-                let mut pixel_color = Color::new();
-
-                for _s in 0..self.samples_pr_pixel {
-                    let r = self.get_ray(x as f64, y as f64, &mut rng);
-                    pixel_color =
-                        pixel_color + Camera::ray_color(&r, world, self.max_light_bounces);
-
-                    counter += 1;
-                }
+        while passes < self.samples_pr_pixel {
+            if self.should_stop(deadline) {
+                // stopped between passes, so the film only has finished passes in it
+                self.checkpoint(&film, seed, passes, &mut last_checkpoint, true);
+                break;
+            }
+            pass_film.clear();
+            let finished = self.render_rows(
+                &mut pass_film,
+                (passes, seed),
+                y0..y1,
+                world,
+                deadline,
+                &progress,
+            );
 
-                file.write_all(
-                    format!(
-                        "\n{}",
-                        pixel_color.write_color(self.samples_pr_pixel as f64)
-                    )
-                    .as_bytes(),
-                )
-                .expect("couldnt write all");
+            let pass_films = std::iter::once(&pass_film);
+            if !self.finish_pass(
+                &mut film,
+                pass_films,
+                finished,
+                seed,
+                &mut passes,
+                &mut last_checkpoint,
+            ) {
+                break;
             }
         }
 
-        println!("\nFor loop ran: {} times", counter);
+        progress.finish();
+        self.finish_render(&film, seed, passes, &mut last_checkpoint);
+
+        let mut render_stats = stats::take();
+        render_stats.set_elapsed(start.elapsed());
+        Ok((film, render_stats))
     }
+}
 
-    /// Set camera settings that aren't the default values
-    // Should probably have use a builder pattern or something, but this was just faster...
-    pub fn set_camera_settings(
+/// The threads of a render, which are spawned once and render every pass. Each thread renders its own band of rows,
+/// onto a film of the rows its samples reach, and the films go back and forth with the passes, so they are only
+/// allocated once. Merging them costs about one image pr pass, however many threads there are
+struct RenderThreads<'scope> {
+    jobs: Vec<Sender<(i64, Film)>>, // The pass to render, and the film to render it onto
+    results: Vec<Receiver<(Film, bool, RenderStats)>>,
+    handles: Vec<Option<ScopedJoinHandle<'scope, ()>>>,
+    rows: Vec<Range<i64>>,    // The rows each thread renders
+    films: Vec<Option<Film>>, // The film of each thread, while the thread isn't rendering onto it
+}
+
+impl<'scope> RenderThreads<'scope> {
+    /// Spawns a thread for every core, but never more threads than there are rows to render
+    fn spawn<'env>(
+        scope: &'scope Scope<'scope, 'env>,
+        camera: &'env Camera,
+        world: &'env dyn Hitable,
+        seed: u64,
+        deadline: Option<Instant>,
+        progress: &'env ProgressTracker,
+    ) -> error::Result<Self> {
+        let ((_, y0), (_, y1)) = camera.pixel_bounds();
+        let rows = (y1 - y0).max(1);
+        let threads = (std::thread::available_parallelism()?.get() as i64).min(rows);
+        let (width, height) = camera.output_size();
+        // samples are splatted onto the rows around them, as far as the filter reaches
+        let reach = camera.filter.radius().ceil() as i64 + 1;
+
+        let mut render_threads = Self {
+            jobs: Vec::new(),
+            results: Vec::new(),
+            handles: Vec::new(),
+            rows: Vec::new(),
+            films: Vec::new(),
+        };
+        for i in 0..threads {
+            // the rows are split evenly, so no thread has more than one row more than the others
+            let thread_rows = y0 + rows * i / threads..y0 + rows * (i + 1) / threads;
+            let film = Film::band(
+                width,
+                height,
+                thread_rows.start - reach..thread_rows.end + reach,
+                camera.filter,
+            )
+            .with_views(camera.img_width, camera.img_height);
+
+            let (job_sender, jobs) = mpsc::channel::<(i64, Film)>();
+            let (results, result_receiver) = mpsc::channel();
+            let rows = thread_rows.clone();
+            let handle = scope.spawn(move || {
+                for (pass, mut film) in jobs {
+                    film.clear();
+                    let finished = camera.render_rows(
+                        &mut film,
+                        (pass, seed),
+                        rows.clone(),
+                        world,
+                        deadline,
+                        progress,
+                    );
+                    if results.send((film, finished, stats::take())).is_err() {
+                        break;
+                    }
+                }
+            });
+
+            render_threads.jobs.push(job_sender);
+            render_threads.results.push(result_receiver);
+            render_threads.handles.push(Some(handle));
+            render_threads.rows.push(thread_rows);
+            render_threads.films.push(Some(film));
+        }
+        Ok(render_threads)
+    }
+
+    /// Renders a pass, and returns the films of the threads, whether every thread finished its rows,
+    /// and the stats of the pass. A thread that panics stops the render, and the error tells which rows it was rendering
+    fn render_pass(
         &mut self,
-        camera_placement: Point3,
-        look_at: Point3,
-        vfov: f64,
-        max_light_bounces: i32,
-        samples: i64,
-        defocus_angle: f64,
-        focus_distance: f64,
-    ) {
-        self.look_from = camera_placement;
-        self.look_at = look_at;
-        self.vfov = vfov;
-        self.samples_pr_pixel = samples;
-        self.max_light_bounces = max_light_bounces;
-        self.focus_distance = focus_distance;
-        self.defocus_angle = defocus_angle;
+        pass: i64,
+    ) -> error::Result<(impl Iterator<Item = &Film>, bool, RenderStats)> {
+        for (job, film) in self.jobs.iter().zip(&mut self.films) {
+            let film = film.take().expect("the films are back after every pass");
+            // a thread that is gone is found out when its film doesn't come back
+            let _ = job.send((pass, film));
+        }
 
-        // Camera basic vectors
-        self.w = (self.look_from - self.look_at).unit_vec();
-        self.u = self.vup.cross_product(&self.w).unit_vec();
-        self.v = self.w.cross_product(&self.u);
+        let mut finished = true;
+        let mut pass_stats = RenderStats::default();
+        let mut panicked = None;
+        // every thread is waited for before a panic is reported, so none of them is still rendering the pass
+        for (i, results) in self.results.iter().enumerate() {
+            match results.recv() {
+                Ok((film, thread_finished, thread_stats)) => {
+                    self.films[i] = Some(film);
+                    finished &= thread_finished;
+                    pass_stats.merge(&thread_stats);
+                }
+                // a thread only hangs up without sending its film back when it panics. It is joined here,
+                // since a scope that has to join a panicked thread itself panics too
+                Err(_) => {
+                    if let Some(Err(payload)) = self.handles[i].take().map(|handle| handle.join()) {
+                        panicked.get_or_insert(Error::thread_panicked(
+                            pass,
+                            self.rows[i].clone(),
+                            payload,
+                        ));
+                    }
+                }
+            }
+        }
 
-        let defocus_radius =
-            self.focus_distance * Camera::degrees_to_radians(self.defocus_angle / 2.0).tan();
-        self.defocus_disk_u = self.u * defocus_radius;
-        self.defocus_disk_v = self.v * defocus_radius;
-
-        // Viewport dimensions:
-        let h = (Camera::degrees_to_radians(self.vfov) / 2.0).tan();
-        let viewport_height = 2.0 * h * self.focus_distance;
-        let viewport_width = viewport_height * self.aspect_ratio;
-
-        let viewport_u = self.u * viewport_width;
-        let viewport_v = -self.v * viewport_height;
-        self.pixel_delta_u = viewport_u / self.img_width as f64;
-        self.pixel_delta_v = viewport_v / self.img_height as f64;
-        let upper_left =
-            self.look_from - (self.w * self.focus_distance) - viewport_u / 2.0 - viewport_v / 2.0;
-        self.pixel_00_loc = upper_left + (self.pixel_delta_v + self.pixel_delta_u) * 0.5;
-    }
-
-    /// Sets the dimensions of the final image based on aspect ratio and width measured in pixels.
-    /// image height is calculated based on these two components
-    pub fn set_img_dimensions(&mut self, aspect_ratio: f64, img_width: i64) {
-        self.aspect_ratio = aspect_ratio;
-        self.img_width = img_width;
-        self.img_height = (self.img_width as f64 / self.aspect_ratio) as i64;
+        match panicked {
+            Some(err) => Err(err),
+            None => Ok((self.films.iter().flatten(), finished, pass_stats)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::{Dielectric, DiffuseLight, Lambertian};
+    use crate::planar::{Disk, Quad};
+    use crate::sphere::Sphere;
+
+    #[test]
+    fn pass_takes_the_same_samples_every_time_it_is_rendered() {
+        let light = || {
+            Quad::from_corner_edges_material(
+                Point3::from_xyz(-1, 2, -2),
+                Vec3::from_xyz(2, 0, 0),
+                Vec3::from_xyz(0, 0, 2),
+                DiffuseLight::from_color(Color::from_rgb(4, 4, 4)),
+            )
+        };
+        let lamp = || {
+            Disk::from_center_normal_radius_material(
+                Point3::from_xyz(2, 1, -1),
+                Vec3::from_xyz(-1, 0, 0),
+                0.5,
+                DiffuseLight::from_color(Color::from_rgb(2, 2, 2)),
+            )
+        };
+        let world: Vec<Box<dyn Hitable>> = vec![
+            Box::new(Sphere::from_center_radius_material(
+                Point3::from_xyz(0, -100.5, -1),
+                100.0,
+                Lambertian::from_color(Color::from_rgb(0.5, 0.5, 0.5)),
+            )),
+            Box::new(Sphere::from_center_radius_material(
+                Point3::from_xyz(0, 0, -1),
+                0.5,
+                Dielectric::from_ir(1.5),
+            )),
+            Box::new(light()),
+            Box::new(lamp()),
+        ];
+        let camera = Camera::builder()
+            .resolution(12, 8)
+            .lights(vec![Box::new(light()), Box::new(lamp())])
+            .build()
+            .unwrap();
+
+        let (film, _) = camera.render_pass(&world, 7, 3).unwrap();
+        let (again, _) = std::thread::scope(|scope| {
+            scope
+                .spawn(|| camera.render_pass(&world, 7, 3).unwrap())
+                .join()
+                .unwrap()
+        });
+        assert_eq!(film.data(), again.data());
+
+        let (other_pass, _) = camera.render_pass(&world, 7, 4).unwrap();
+        assert_ne!(film.data(), other_pass.data());
     }
 }
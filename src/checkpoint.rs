@@ -0,0 +1,284 @@
+use rand::{rngs::StdRng, SeedableRng};
+use std::{
+    fs::{self, File},
+    io::{BufReader, BufWriter, Error, ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::error;
+use crate::film::{Film, Filter, VALUES_PR_PIXEL};
+
+const MAGIC: &[u8; 8] = b"RTCHECK3";
+
+// The first and one past the last pixel of a region, as ((x0, y0), (x1, y1))
+type Bounds = ((i64, i64), (i64, i64));
+
+/// Where and how often the progress of a render is saved, so it can be resumed if the render is stopped
+pub struct CheckpointSettings {
+    path: PathBuf,
+    interval: Duration, // The least amount of time between two checkpoints
+    scene_seed: u64,    // The seed the world was built with
+}
+
+impl CheckpointSettings {
+    pub fn new(path: impl Into<PathBuf>, interval: Duration) -> Self {
+        Self {
+            path: path.into(),
+            interval,
+            scene_seed: 0,
+        }
+    }
+
+    /// Sets the seed the random parts of the world were built with. It is saved in the checkpoint,
+    /// so the render can be resumed with the same world, and a checkpoint from another world is refused
+    pub fn with_scene_seed(mut self, scene_seed: u64) -> Self {
+        self.scene_seed = scene_seed;
+        self
+    }
+
+    /// returns the path of the checkpoint file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// returns the least amount of time between two checkpoints
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// returns the seed the world was built with
+    pub fn scene_seed(&self) -> u64 {
+        self.scene_seed
+    }
+}
+
+/// The saved progress of a render. Samples are taken in passes of one sample pr pixel,
+/// and the random numbers of each pass are made from the seed and the pass number,
+/// so the seed and the amount of finished passes is all the random state the render needs to continue.
+/// The film only has the sums of the samples, so the checkpoint also keeps what decides where the samples went,
+/// which a resumed render has to match
+pub struct Checkpoint {
+    seed: u64,
+    scene_seed: u64, // The seed the world was built with
+    passes: i64,     // The amount of samples pr pixel that are already in the film
+    width: i64,
+    height: i64,
+    filter: Filter,
+    region: Option<Bounds>, // The part of the image that was rendered, if not all of it
+    data: Vec<f64>,         // The data of the film, VALUES_PR_PIXEL values pr pixel
+}
+
+impl Checkpoint {
+    /// Saves the film of a render that has finished the given amount of passes, along with the seeds of the samples
+    /// and of the world, and the region of the image that is rendered.
+    /// The file is written next to the old checkpoint first, so a crash while saving doesn't lose the old one
+    pub fn save(
+        path: &Path,
+        (seed, scene_seed): (u64, u64),
+        passes: i64,
+        film: &Film,
+        region: Option<Bounds>,
+    ) -> std::io::Result<()> {
+        let temporary = path.with_extension("tmp");
+        {
+            let mut file = BufWriter::new(File::create(&temporary)?);
+            file.write_all(MAGIC)?;
+            let (width, height) = film.size();
+            let (filter_kind, filter_settings) = film.filter().to_values();
+            let ((x0, y0), (x1, y1)) = region.unwrap_or_default();
+            for value in [
+                seed,
+                scene_seed,
+                passes as u64,
+                width as u64,
+                height as u64,
+                filter_kind,
+                region.is_some() as u64,
+                x0 as u64,
+                y0 as u64,
+                x1 as u64,
+                y1 as u64,
+            ] {
+                file.write_all(&value.to_le_bytes())?;
+            }
+            for value in filter_settings.into_iter().chain(film.data()) {
+                file.write_all(&value.to_le_bytes())?;
+            }
+            file.flush()?;
+        }
+        fs::rename(temporary, path)
+    }
+
+    /// Loads a checkpoint saved by a render
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let invalid = |message: &str| Error::new(ErrorKind::InvalidData, message.to_string());
+        let mut file = BufReader::new(File::open(path)?);
+
+        let mut magic = [0; 8];
+        file.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a checkpoint file"));
+        }
+
+        let mut read_u64 = || -> std::io::Result<u64> {
+            let mut bytes = [0; 8];
+            file.read_exact(&mut bytes)?;
+            Ok(u64::from_le_bytes(bytes))
+        };
+        let (seed, scene_seed, passes) = (read_u64()?, read_u64()?, read_u64()?);
+        let (width, height) = (read_u64()?, read_u64()?);
+        let filter_kind = read_u64()?;
+        let has_region = read_u64()? != 0;
+        let region = ((read_u64()?, read_u64()?), (read_u64()?, read_u64()?));
+        let filter_settings = [read_u64()?, read_u64()?, read_u64()?].map(f64::from_bits);
+
+        let filter = Filter::from_values(filter_kind, filter_settings)
+            .ok_or_else(|| invalid("checkpoint has an unknown filter"))?;
+        let region = has_region.then(|| {
+            let ((x0, y0), (x1, y1)) = region;
+            ((x0 as i64, y0 as i64), (x1 as i64, y1 as i64))
+        });
+
+        // the dimensions come from the file, so they can be anything
+        let (width, height) = match (i64::try_from(width), i64::try_from(height)) {
+            (Ok(width), Ok(height)) => (width, height),
+            _ => return Err(invalid("checkpoint dimensions are too large")),
+        };
+        let data_len = width
+            .checked_mul(height)
+            .and_then(|pixels| pixels.checked_mul(VALUES_PR_PIXEL as i64 * 8))
+            .ok_or_else(|| invalid("checkpoint dimensions are too large"))?;
+
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        if bytes.len() as i64 != data_len {
+            return Err(invalid("checkpoint data doesn't match its dimensions"));
+        }
+        let data = bytes
+            .chunks_exact(8)
+            .map(|c| f64::from_le_bytes(c.try_into().expect("chunks are 8 bytes")))
+            .collect();
+
+        Ok(Self {
+            seed,
+            scene_seed,
+            passes: passes as i64,
+            width,
+            height,
+            filter,
+            region,
+            data,
+        })
+    }
+
+    /// returns the seed of the render
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// returns the seed the world was built with
+    pub fn scene_seed(&self) -> u64 {
+        self.scene_seed
+    }
+
+    /// returns the amount of finished passes
+    pub fn passes(&self) -> i64 {
+        self.passes
+    }
+
+    /// returns the filter the samples were spread over the pixels with
+    pub fn filter(&self) -> Filter {
+        self.filter
+    }
+
+    /// returns the part of the image that was rendered, as ((x0, y0), (x1, y1)), or None for the whole image
+    pub fn region(&self) -> Option<Bounds> {
+        self.region
+    }
+
+    /// returns the width and height of the image
+    pub fn size(&self) -> (i64, i64) {
+        (self.width, self.height)
+    }
+
+    /// Puts the saved samples into an empty film of the same size
+    pub fn restore(&self, film: &mut Film) -> error::Result<()> {
+        film.set_data(&self.data)
+    }
+}
+
+/// The random number generator for a row of pixels in a pass. Every row of every pass gets its own
+/// generator, so the samples don't depend on how the rows are split between threads
+pub fn pass_rng(seed: u64, pass: i64, row: i64) -> StdRng {
+    // splitmix64, to spread the bits of the seed, pass and row over the whole seed of the generator
+    let mut z = seed
+        .wrapping_add((pass as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15))
+        .wrapping_add((row as u64).wrapping_mul(0xd1b5_4a32_d192_ed03));
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    StdRng::seed_from_u64(z ^ (z >> 31))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::point3::Color;
+
+    /// A path in the temporary directory that no other test uses
+    fn temporary_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("raytracer-{}-{}.rtcheck", name, std::process::id()))
+    }
+
+    #[test]
+    fn saved_checkpoint_loads_the_same_render() {
+        let filter = Filter::Gaussian {
+            radius: 1.5,
+            alpha: 2.0,
+        };
+        let mut film = Film::new(4, 3, filter);
+        for i in 0..30 {
+            let i = i as f64;
+            film.add_sample(
+                (i * 0.37) % 4.0,
+                (i * 0.61) % 3.0,
+                Color::from_rgb(i, 1, 0.5),
+            );
+        }
+        let region = Some(((1, 0), (3, 2)));
+
+        let path = temporary_path("round-trip");
+        Checkpoint::save(&path, (7, 42), 5, &film, region).unwrap();
+        let checkpoint = Checkpoint::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(checkpoint.seed(), 7);
+        assert_eq!(checkpoint.scene_seed(), 42);
+        assert_eq!(checkpoint.passes(), 5);
+        assert_eq!(checkpoint.size(), (4, 3));
+        assert_eq!(checkpoint.filter(), filter);
+        assert_eq!(checkpoint.region(), region);
+
+        let mut restored = Film::new(4, 3, filter);
+        checkpoint.restore(&mut restored).unwrap();
+        assert_eq!(restored.data(), film.data());
+    }
+
+    #[test]
+    fn checkpoint_with_impossible_dimensions_is_rejected() {
+        let path = temporary_path("overflow");
+        Checkpoint::save(&path, (0, 0), 1, &Film::new(1, 1, Filter::default()), None).unwrap();
+        // make the width so large that width * height * the bytes pr pixel overflows
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[MAGIC.len() + 3 * 8..MAGIC.len() + 4 * 8]
+            .copy_from_slice(&(1u64 << 62).to_le_bytes());
+        fs::write(&path, bytes).unwrap();
+        let loaded = Checkpoint::load(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            loaded.err().map(|err| err.kind()),
+            Some(ErrorKind::InvalidData)
+        );
+    }
+}
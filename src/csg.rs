@@ -0,0 +1,248 @@
+use crate::aabb::Aabb;
+use crate::hitable::{HitRecord, Hitable};
+use crate::ray::Ray;
+
+/// The parts of a ray that are inside an object, as pairs of where it enters and leaves
+type Intervals<'a> = Vec<(HitRecord<'a>, HitRecord<'a>)>;
+
+/// How a csg node decides whether a point is inside, from whether it is inside each of its two objects
+#[derive(Clone, Copy, PartialEq)]
+enum Operation {
+    Union,
+    Intersection,
+    Difference,
+}
+
+impl Operation {
+    fn inside(self, in_a: bool, in_b: bool) -> bool {
+        match self {
+            Operation::Union => in_a || in_b,
+            Operation::Intersection => in_a && in_b,
+            Operation::Difference => in_a && !in_b,
+        }
+    }
+
+    /// Walks along the ray through every place where it enters or leaves one of the objects,
+    /// and keeps the places where it enters or leaves the combined object
+    fn combine<'a>(self, a: Intervals<'a>, b: Intervals<'a>) -> Intervals<'a> {
+        let mut events = Vec::with_capacity(2 * (a.len() + b.len()));
+        for (intervals, from_a) in [(a, true), (b, false)] {
+            for (enter, exit) in intervals {
+                events.push((enter, from_a, true));
+                events.push((exit, from_a, false));
+            }
+        }
+        events.sort_by(|x, y| x.0.t().total_cmp(&y.0.t()));
+
+        let (mut in_a, mut in_b) = (false, false);
+        let mut intervals = Vec::new();
+        let mut enter = None;
+        for (rec, from_a, entering) in events {
+            let was_inside = self.inside(in_a, in_b);
+            if from_a {
+                in_a = entering;
+            } else {
+                in_b = entering;
+            }
+
+            // the surface of the object that is cut away faces the other way in the result
+            let rec = if self == Operation::Difference && !from_a {
+                rec.flip_normal()
+            } else {
+                rec
+            };
+
+            match (was_inside, self.inside(in_a, in_b)) {
+                (false, true) => enter = Some(rec),
+                (true, false) => {
+                    if let Some(enter) = enter.take() {
+                        intervals.push((enter, rec));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        intervals
+    }
+}
+
+/// Finds the first place between t_min and t_max where the ray enters or leaves one of the intervals
+fn first_hit(intervals: Intervals<'_>, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+    intervals
+        .into_iter()
+        .flat_map(|(enter, exit)| [enter, exit])
+        .find(|rec| t_min < rec.t() && rec.t() < t_max)
+}
+
+/// Everything that is inside either of the two objects. Both objects have to be closed
+pub struct Union<A: Hitable, B: Hitable> {
+    a: A,
+    b: B,
+}
+
+impl<A: Hitable, B: Hitable> Union<A, B> {
+    pub fn new(a: A, b: B) -> Self {
+        Self { a, b }
+    }
+}
+
+impl<A: Hitable, B: Hitable> Hitable for Union<A, B> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        first_hit(self.hit_intervals(r), t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.a.bounding_box()?.surrounding(&self.b.bounding_box()?))
+    }
+
+    fn hit_intervals(&self, r: &Ray) -> Vec<(HitRecord<'_>, HitRecord<'_>)> {
+        Operation::Union.combine(self.a.hit_intervals(r), self.b.hit_intervals(r))
+    }
+}
+
+/// Only what is inside both of the two objects, like a lens made from two spheres. Both objects have to be closed
+pub struct Intersection<A: Hitable, B: Hitable> {
+    a: A,
+    b: B,
+}
+
+impl<A: Hitable, B: Hitable> Intersection<A, B> {
+    pub fn new(a: A, b: B) -> Self {
+        Self { a, b }
+    }
+}
+
+impl<A: Hitable, B: Hitable> Hitable for Intersection<A, B> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        first_hit(self.hit_intervals(r), t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        match (self.a.bounding_box(), self.b.bounding_box()) {
+            (Some(a), Some(b)) => Some(a.overlap(&b).unwrap_or(a)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    fn hit_intervals(&self, r: &Ray) -> Vec<(HitRecord<'_>, HitRecord<'_>)> {
+        let a = self.a.hit_intervals(r);
+        if a.is_empty() {
+            return a;
+        }
+        Operation::Intersection.combine(a, self.b.hit_intervals(r))
+    }
+}
+
+/// The first object with the second one cut out of it. The cut surfaces get the material of the second object.
+/// Both objects have to be closed
+pub struct Difference<A: Hitable, B: Hitable> {
+    a: A,
+    b: B,
+}
+
+impl<A: Hitable, B: Hitable> Difference<A, B> {
+    pub fn new(a: A, b: B) -> Self {
+        Self { a, b }
+    }
+}
+
+impl<A: Hitable, B: Hitable> Hitable for Difference<A, B> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        first_hit(self.hit_intervals(r), t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.a.bounding_box()
+    }
+
+    fn hit_intervals(&self, r: &Ray) -> Vec<(HitRecord<'_>, HitRecord<'_>)> {
+        let a = self.a.hit_intervals(r);
+        if a.is_empty() {
+            return a;
+        }
+        Operation::Difference.combine(a, self.b.hit_intervals(r))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+    use crate::point3::{Point3, Vec3};
+    use crate::sphere::Sphere;
+
+    fn sphere(x: f64) -> Sphere<Lambertian> {
+        Sphere::from_center_radius_material(Point3::from_xyz(x, 0, 0), 1.0, Lambertian::new())
+    }
+
+    /// Where the ray enters and leaves the object, as distances along the ray
+    fn spans(object: &dyn Hitable, r: &Ray) -> Vec<(f64, f64)> {
+        object
+            .hit_intervals(r)
+            .iter()
+            .map(|(enter, exit)| (enter.t(), exit.t()))
+            .collect()
+    }
+
+    fn assert_spans(found: Vec<(f64, f64)>, expected: &[(f64, f64)]) {
+        assert_eq!(found.len(), expected.len(), "found {:?}", found);
+        for ((enter, exit), (expected_enter, expected_exit)) in found.iter().zip(expected) {
+            assert!((enter - expected_enter).abs() < 1e-9, "found {:?}", found);
+            assert!((exit - expected_exit).abs() < 1e-9, "found {:?}", found);
+        }
+    }
+
+    // a ray along the x axis is inside the first sphere from t = 4 to 6, and inside the second from t = 5 to 7
+    fn ray() -> Ray {
+        Ray::new(Point3::from_xyz(-5, 0, 0), Vec3::from_xyz(1, 0, 0))
+    }
+
+    #[test]
+    fn overlapping_objects_combine_into_the_right_spans() {
+        let r = ray();
+        assert_spans(
+            spans(&Union::new(sphere(0.0), sphere(1.0)), &r),
+            &[(4.0, 7.0)],
+        );
+        assert_spans(
+            spans(&Intersection::new(sphere(0.0), sphere(1.0)), &r),
+            &[(5.0, 6.0)],
+        );
+        assert_spans(
+            spans(&Difference::new(sphere(0.0), sphere(1.0)), &r),
+            &[(4.0, 5.0)],
+        );
+        assert_spans(
+            spans(&Difference::new(sphere(1.0), sphere(0.0)), &r),
+            &[(6.0, 7.0)],
+        );
+    }
+
+    #[test]
+    fn separate_objects_keep_their_own_spans() {
+        let r = ray();
+        let union = Union::new(sphere(0.0), sphere(3.0));
+        assert_spans(spans(&union, &r), &[(4.0, 6.0), (7.0, 9.0)]);
+        assert_spans(spans(&Intersection::new(sphere(0.0), sphere(3.0)), &r), &[]);
+
+        // cutting the middle out of a long object leaves two pieces
+        let long = Union::new(Union::new(sphere(0.0), sphere(1.0)), sphere(2.0));
+        let cut = Difference::new(long, sphere(1.0));
+        assert_spans(spans(&cut, &r), &[(4.0, 5.0), (7.0, 8.0)]);
+    }
+
+    #[test]
+    fn cut_surface_faces_out_of_the_difference() {
+        let difference = Difference::new(sphere(0.0), sphere(1.0));
+        let intervals = difference.hit_intervals(&ray());
+        let (enter, exit) = &intervals[0];
+        // the ray leaves the difference where it enters the sphere that is cut away, and the surface there
+        // faces along the ray, out of what is left
+        assert!(enter.normal().x() < 0.0);
+        assert!(exit.normal().x() > 0.0);
+
+        let hit = difference.hit(&ray(), 4.5, f64::INFINITY).unwrap();
+        assert!((hit.t() - 5.0).abs() < 1e-9);
+    }
+}
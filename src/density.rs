@@ -0,0 +1,302 @@
+use rand::{seq::SliceRandom, Rng};
+use std::{
+    fs,
+    io::{Error, ErrorKind},
+    path::Path,
+};
+
+use crate::error;
+use crate::point3::{Point3, Vec3};
+
+/// Something that describes how dense a heterogeneous medium is at each point in space
+pub trait DensityField: Send + Sync {
+    /// The density at a point in world space
+    fn density(&self, p: Point3) -> f64;
+
+    /// An upper bound of the density everywhere. Used as the majorant when tracking through the medium,
+    /// so it has to be greater than or equal to any value returned by density()
+    fn max_density(&self) -> f64;
+}
+
+const PERLIN_POINT_COUNT: usize = 256;
+
+/// Perlin noise, using random gradient vectors on a lattice
+pub struct Perlin {
+    gradients: Vec<Vec3>,
+    perm_x: Vec<usize>,
+    perm_y: Vec<usize>,
+    perm_z: Vec<usize>,
+}
+
+impl Perlin {
+    /// Creates the noise from random gradients and permutations, so the same rng state gives the same noise
+    pub fn new(rng: &mut impl Rng) -> Self {
+        let gradients = (0..PERLIN_POINT_COUNT)
+            .map(|_| {
+                Vec3::from_xyz(
+                    rng.gen_range(-1.0..1.0),
+                    rng.gen_range(-1.0..1.0),
+                    rng.gen_range(-1.0..1.0),
+                )
+                .unit_vec()
+            })
+            .collect();
+
+        let mut permutation = || {
+            let mut p: Vec<usize> = (0..PERLIN_POINT_COUNT).collect();
+            p.shuffle(rng);
+            p
+        };
+
+        Self {
+            gradients,
+            perm_x: permutation(),
+            perm_y: permutation(),
+            perm_z: permutation(),
+        }
+    }
+
+    /// Smooth noise between -1 and 1
+    pub fn noise(&self, p: Point3) -> f64 {
+        let u = p.x() - p.x().floor();
+        let v = p.y() - p.y().floor();
+        let w = p.z() - p.z().floor();
+
+        let i = p.x().floor() as i64;
+        let j = p.y().floor() as i64;
+        let k = p.z().floor() as i64;
+
+        // hermite smoothing of the interpolation weights
+        let uu = u * u * (3.0 - 2.0 * u);
+        let vv = v * v * (3.0 - 2.0 * v);
+        let ww = w * w * (3.0 - 2.0 * w);
+
+        let mut accum = 0.0;
+        for di in 0..2 {
+            for dj in 0..2 {
+                for dk in 0..2 {
+                    let index = self.perm_x[((i + di) & 255) as usize]
+                        ^ self.perm_y[((j + dj) & 255) as usize]
+                        ^ self.perm_z[((k + dk) & 255) as usize];
+                    let weight = Vec3::from_xyz(u - di as f64, v - dj as f64, w - dk as f64);
+
+                    let (di, dj, dk) = (di as f64, dj as f64, dk as f64);
+                    accum += (di * uu + (1.0 - di) * (1.0 - uu))
+                        * (dj * vv + (1.0 - dj) * (1.0 - vv))
+                        * (dk * ww + (1.0 - dk) * (1.0 - ww))
+                        * self.gradients[index].dot_product(&weight);
+                }
+            }
+        }
+
+        accum
+    }
+
+    /// Fractal noise made by adding several octaves of noise together
+    pub fn fbm(&self, p: Point3, octaves: u32) -> f64 {
+        let mut accum = 0.0;
+        let mut p = p;
+        let mut weight = 1.0;
+
+        for _ in 0..octaves {
+            accum += weight * self.noise(p);
+            weight *= 0.5;
+            p = p * 2.0;
+        }
+
+        accum
+    }
+}
+
+impl Default for Perlin {
+    fn default() -> Self {
+        Perlin::new(&mut rand::thread_rng())
+    }
+}
+
+/// Procedural cloudy density made from fractal perlin noise
+pub struct NoiseDensity {
+    perlin: Perlin,
+    max_density: f64,
+    frequency: f64, // How many noise features there are pr unit of distance
+    octaves: u32,
+    coverage: f64, // Between 0 and 1. Higher values leave fewer empty holes in the medium
+}
+
+impl NoiseDensity {
+    pub fn new(
+        rng: &mut impl Rng,
+        max_density: f64,
+        frequency: f64,
+        octaves: u32,
+        coverage: f64,
+    ) -> Self {
+        Self {
+            perlin: Perlin::new(rng),
+            max_density,
+            frequency,
+            octaves,
+            coverage: coverage.clamp(0.0, 1.0),
+        }
+    }
+}
+
+impl DensityField for NoiseDensity {
+    fn density(&self, p: Point3) -> f64 {
+        let noise = self.perlin.fbm(p * self.frequency, self.octaves);
+        self.max_density * (noise + self.coverage * 2.0 - 1.0).clamp(0.0, 1.0)
+    }
+
+    fn max_density(&self) -> f64 {
+        self.max_density
+    }
+}
+
+/// Density stored in a 3d grid of voxels, stretched over a box in world space.
+/// Values between the voxels are interpolated trilinearly, and everything outside the box is empty
+pub struct GridDensity {
+    nx: usize,
+    ny: usize,
+    nz: usize,
+    values: Vec<f32>,
+    min: Point3, // The corner of the box with the smallest coordinates
+    max: Point3, // The corner of the box with the largest coordinates
+    max_density: f64,
+}
+
+impl GridDensity {
+    /// Creates a grid from values stored with x changing fastest, then y, then z.
+    /// Every dimension has to be at least 1, and there has to be a value for every voxel
+    pub fn from_values(
+        dimensions: (usize, usize, usize),
+        values: Vec<f32>,
+        min: Point3,
+        max: Point3,
+    ) -> error::Result<Self> {
+        let (nx, ny, nz) = dimensions;
+        if nx == 0 || ny == 0 || nz == 0 {
+            return Err(error::Error::InvalidSettings(
+                "grid needs at least one voxel on every axis".to_string(),
+            ));
+        }
+        if nx.checked_mul(ny).and_then(|n| n.checked_mul(nz)) != Some(values.len()) {
+            return Err(error::Error::InvalidSettings(
+                "grid has the wrong amount of values".to_string(),
+            ));
+        }
+
+        let max_density = values.iter().fold(0.0f32, |acc, v| acc.max(*v)) as f64;
+        Ok(Self {
+            nx,
+            ny,
+            nz,
+            values,
+            min,
+            max,
+            max_density,
+        })
+    }
+
+    /// Loads a grid from a simple volume file. The file starts with a line of text: "VOL {nx} {ny} {nz}",
+    /// followed by the raw voxel values as little endian f32, with x changing fastest, then y, then z
+    pub fn load(path: impl AsRef<Path>, min: Point3, max: Point3) -> std::io::Result<Self> {
+        let bytes = fs::read(path)?;
+        let header_end = bytes
+            .iter()
+            .position(|b| *b == b'\n')
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "missing volume header"))?;
+
+        let header = String::from_utf8_lossy(&bytes[..header_end]);
+        let mut parts = header.split_whitespace();
+        if parts.next() != Some("VOL") {
+            return Err(Error::new(ErrorKind::InvalidData, "not a volume file"));
+        }
+
+        let mut dimension = || -> std::io::Result<usize> {
+            parts
+                .next()
+                .and_then(|d| d.parse().ok())
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData, "invalid volume dimensions"))
+        };
+        let dimensions = (dimension()?, dimension()?, dimension()?);
+        if dimensions.0 == 0 || dimensions.1 == 0 || dimensions.2 == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "volume needs at least one voxel on every axis",
+            ));
+        }
+
+        GridDensity::from_bytes(&bytes[header_end + 1..], dimensions, min, max)
+    }
+
+    fn from_bytes(
+        bytes: &[u8],
+        dimensions: (usize, usize, usize),
+        min: Point3,
+        max: Point3,
+    ) -> std::io::Result<Self> {
+        let (nx, ny, nz) = dimensions;
+        let voxels = nx.checked_mul(ny).and_then(|n| n.checked_mul(nz));
+        if voxels.and_then(|n| n.checked_mul(4)) != Some(bytes.len()) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "volume data doesn't match its dimensions",
+            ));
+        }
+
+        let values = bytes
+            .chunks_exact(4)
+            .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+            .collect();
+
+        GridDensity::from_values(dimensions, values, min, max)
+            .map_err(|err| Error::new(ErrorKind::InvalidData, err.to_string()))
+    }
+
+    fn voxel(&self, x: usize, y: usize, z: usize) -> f64 {
+        self.values[x + self.nx * (y + self.ny * z)] as f64
+    }
+}
+
+impl DensityField for GridDensity {
+    fn density(&self, p: Point3) -> f64 {
+        // position inside the box from 0 to 1 on each axis
+        let local = (p - self.min) / (self.max - self.min);
+        if [local.x(), local.y(), local.z()]
+            .iter()
+            .any(|c| !(0.0..=1.0).contains(c))
+        {
+            return 0.0;
+        }
+
+        // continuous voxel coordinates, where voxel centers are at whole numbers
+        let gx = (local.x() * self.nx as f64 - 0.5).clamp(0.0, (self.nx - 1) as f64);
+        let gy = (local.y() * self.ny as f64 - 0.5).clamp(0.0, (self.ny - 1) as f64);
+        let gz = (local.z() * self.nz as f64 - 0.5).clamp(0.0, (self.nz - 1) as f64);
+
+        let (x0, y0, z0) = (gx as usize, gy as usize, gz as usize);
+        let (x1, y1, z1) = (
+            (x0 + 1).min(self.nx - 1),
+            (y0 + 1).min(self.ny - 1),
+            (z0 + 1).min(self.nz - 1),
+        );
+        let (fx, fy, fz) = (gx - x0 as f64, gy - y0 as f64, gz - z0 as f64);
+
+        let lerp = |a: f64, b: f64, t: f64| a * (1.0 - t) + b * t;
+        let c00 = lerp(self.voxel(x0, y0, z0), self.voxel(x1, y0, z0), fx);
+        let c10 = lerp(self.voxel(x0, y1, z0), self.voxel(x1, y1, z0), fx);
+        let c01 = lerp(self.voxel(x0, y0, z1), self.voxel(x1, y0, z1), fx);
+        let c11 = lerp(self.voxel(x0, y1, z1), self.voxel(x1, y1, z1), fx);
+
+        lerp(lerp(c00, c10, fy), lerp(c01, c11, fy), fz)
+    }
+
+    fn max_density(&self) -> f64 {
+        self.max_density
+    }
+}
+
+/// Samples the distance to the next tentative collision in a medium with the given majorant
+pub fn sample_free_flight(majorant: f64, rng: &mut impl Rng) -> f64 {
+    -(1.0 - rng.gen::<f64>()).ln() / majorant
+}
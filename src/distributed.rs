@@ -0,0 +1,666 @@
+use std::{
+    collections::VecDeque,
+    fs,
+    io::{BufReader, BufWriter, Error, ErrorKind, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    ops::Range,
+    path::PathBuf,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Condvar, Mutex, OnceLock,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{error, film::VALUES_PR_PIXEL, stats::RenderStats};
+
+const MAGIC: &[u8; 8] = b"RTDIST03";
+
+// the messages the coordinator sends to a worker
+const JOB: u8 = 1;
+const SHUTDOWN: u8 = 0;
+
+/// Hands out the passes of a render to worker processes over tcp, and collects the films they send back.
+/// The scene is sent as what it is built from: the command line of the render, the seeds, and the contents of the files
+/// the command line loads, like heightmaps. That is everything the workers need to build the same scene and camera,
+/// and take the same samples, as long as they run the same build.
+/// The coordinator and the workers compare a hash of their executables, and workers running another build are
+/// turned away. The hash is of the whole file, so two builds of the same source that were linked separately,
+/// like in another directory or on another machine, count as different builds.
+/// Copy the same executable to every machine
+pub struct Coordinator {
+    listener: TcpListener,
+    worker_args: Vec<String>,
+    files: Vec<(String, Vec<u8>)>, // The files the command line loads, by the path in the command line
+    build_id: u64,
+    pass_timeout: Duration, // How long a worker gets to send back a pass before it is treated as lost
+}
+
+/// How long a worker gets to render a pass by default. It is generous, since a pass of a big image takes a while
+/// on a slow machine, and a worker that is too slow has its pass taken away and rendered again by another one
+const DEFAULT_PASS_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// What every worker is told about the render, and what it is expected to send back
+struct WorkerRender {
+    build_id: u64,
+    seed: u64,
+    args: Vec<String>,
+    files: Vec<(String, Vec<u8>)>,
+    data_len: u64, // The amount of values in the film of a pass
+    pass_timeout: Duration,
+}
+
+impl Coordinator {
+    /// Listens for workers on the address. The arguments are the command line the workers render with
+    pub fn bind(addr: impl ToSocketAddrs, worker_args: Vec<String>) -> std::io::Result<Self> {
+        Ok(Self {
+            listener: TcpListener::bind(addr)?,
+            worker_args,
+            files: Vec::new(),
+            build_id: build_id()?,
+            pass_timeout: DEFAULT_PASS_TIMEOUT,
+        })
+    }
+
+    /// Sets how long a worker gets to send back a pass. A worker that doesn't answer in time, like a machine that
+    /// lost power or a network link that dropped without closing the connection, is treated as lost
+    pub fn with_pass_timeout(mut self, pass_timeout: Duration) -> Self {
+        self.pass_timeout = pass_timeout;
+        self
+    }
+
+    /// Sends files the command line loads along with it, so the workers don't need their own copies.
+    /// Each file is the path as it is written in the command line, and its contents
+    pub fn with_files(mut self, files: Vec<(String, Vec<u8>)>) -> Self {
+        self.files = files;
+        self
+    }
+
+    /// returns the address the workers connect to
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Starts handing out the passes to every worker that connects. A pass that a worker doesn't
+    /// send back, because it died or lost the connection, is given to another worker.
+    /// The film size is the width and height of the films the workers render, which they are checked against
+    pub fn start(self, seed: u64, passes: Range<i64>, film_size: (i64, i64)) -> Farm {
+        let render = Arc::new(WorkerRender {
+            build_id: self.build_id,
+            seed,
+            args: self.worker_args,
+            files: self.files,
+            data_len: (film_size.0 * film_size.1) as u64 * VALUES_PR_PIXEL as u64,
+            pass_timeout: self.pass_timeout,
+        });
+        let workers = Arc::new(Mutex::new(Workers {
+            connected: 0,
+            none_since: Some(Instant::now()),
+        }));
+        let queue = Arc::new(JobQueue {
+            passes: Mutex::new(Some(passes.collect())),
+            changed: Condvar::new(),
+        });
+        let (results, receiver) = mpsc::channel();
+        let (messages, message_receiver) = mpsc::channel();
+
+        let accept_queue = queue.clone();
+        let accept_workers = workers.clone();
+        let listener = self.listener;
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else {
+                    continue;
+                };
+                let render = render.clone();
+                let queue = accept_queue.clone();
+                let workers = accept_workers.clone();
+                let results = results.clone();
+                let messages = messages.clone();
+                std::thread::spawn(move || {
+                    serve_worker(stream, &render, &queue, &workers, results, messages)
+                });
+            }
+        });
+
+        Farm {
+            queue,
+            workers,
+            pass_timeout: self.pass_timeout,
+            receiver,
+            messages: message_receiver,
+        }
+    }
+}
+
+/// The passes that haven't been given to a worker yet
+struct JobQueue {
+    passes: Mutex<Option<VecDeque<i64>>>, // None once the render is over
+    changed: Condvar,
+}
+
+impl JobQueue {
+    /// Waits for a pass to render. Returns None when the render is over
+    fn take(&self) -> Option<i64> {
+        let mut passes = self.passes.lock().expect("job queue lock poisoned");
+        loop {
+            match passes.as_mut()?.pop_front() {
+                Some(pass) => return Some(pass),
+                None => passes = self.changed.wait(passes).expect("job queue lock poisoned"),
+            }
+        }
+    }
+
+    /// Puts back a pass that a worker didn't finish
+    fn give_back(&self, pass: i64) {
+        let mut passes = self.passes.lock().expect("job queue lock poisoned");
+        if let Some(passes) = passes.as_mut() {
+            passes.push_front(pass);
+        }
+        self.changed.notify_one();
+    }
+
+    fn stop(&self) {
+        *self.passes.lock().expect("job queue lock poisoned") = None;
+        self.changed.notify_all();
+    }
+}
+
+/// How many workers are rendering passes
+struct Workers {
+    connected: usize,
+    none_since: Option<Instant>, // When the last worker went away, or None while there are workers
+}
+
+/// Counts a worker as connected for as long as it lives
+struct ConnectedWorker<'a>(&'a Mutex<Workers>);
+
+impl<'a> ConnectedWorker<'a> {
+    fn new(workers: &'a Mutex<Workers>) -> Self {
+        let mut counts = workers.lock().expect("worker count lock poisoned");
+        counts.connected += 1;
+        counts.none_since = None;
+        Self(workers)
+    }
+}
+
+impl Drop for ConnectedWorker<'_> {
+    fn drop(&mut self) {
+        let mut counts = self.0.lock().expect("worker count lock poisoned");
+        counts.connected -= 1;
+        if counts.connected == 0 {
+            counts.none_since = Some(Instant::now());
+        }
+    }
+}
+
+/// Sends passes to one worker until the render is over or the worker goes away.
+/// What happens to the worker is told through the messages
+fn serve_worker(
+    stream: TcpStream,
+    render: &WorkerRender,
+    queue: &JobQueue,
+    workers: &Mutex<Workers>,
+    results: Sender<PassResult>,
+    messages: Sender<String>,
+) {
+    let addr = stream
+        .peer_addr()
+        .map_or("unknown".to_string(), |addr| addr.to_string());
+
+    // a worker that goes away without closing the connection never sends anything again,
+    // so waiting for it has to give up at some point
+    let setup = stream
+        .set_read_timeout(Some(render.pass_timeout))
+        .and_then(|_| stream.set_write_timeout(Some(render.pass_timeout)))
+        .and_then(|_| stream.try_clone());
+    let read_half = match setup {
+        Ok(read_half) => read_half,
+        Err(err) => {
+            let _ = messages.send(format!("couldn't set up worker {}: {}", addr, err));
+            return;
+        }
+    };
+    let mut reader = BufReader::new(read_half);
+    let mut writer = BufWriter::new(stream);
+
+    match send_hello(&mut writer, render, &mut reader) {
+        Ok(build_id) if build_id == render.build_id => {}
+        Ok(_) => {
+            let _ = messages.send(format!(
+                "worker {} runs another build of the renderer, and isn't used",
+                addr
+            ));
+            return;
+        }
+        Err(err) => {
+            let _ = messages.send(format!("couldn't set up worker {}: {}", addr, err));
+            return;
+        }
+    }
+
+    let _connected = ConnectedWorker::new(workers);
+    while let Some(pass) = queue.take() {
+        match render_on_worker(&mut reader, &mut writer, pass, render.data_len) {
+            Ok(result) => {
+                if results.send(result).is_err() {
+                    break;
+                }
+            }
+            Err(err) => {
+                let reason = match err.kind() {
+                    ErrorKind::WouldBlock | ErrorKind::TimedOut => {
+                        format!("no answer in {} seconds", render.pass_timeout.as_secs_f64())
+                    }
+                    _ => err.to_string(),
+                };
+                let _ = messages.send(format!(
+                    "lost worker {} ({}), pass {} goes to another worker",
+                    addr, reason, pass
+                ));
+                queue.give_back(pass);
+                return;
+            }
+        }
+    }
+
+    // the render is over, so the worker can quit. It doesn't matter if it is already gone
+    let _ = writer.write_all(&[SHUTDOWN]).and_then(|_| writer.flush());
+}
+
+/// Tells the worker what to render, and returns the build the worker answers with
+fn send_hello(
+    writer: &mut impl Write,
+    render: &WorkerRender,
+    reader: &mut impl Read,
+) -> std::io::Result<u64> {
+    writer.write_all(MAGIC)?;
+    write_u64(writer, render.build_id)?;
+    write_u64(writer, render.seed)?;
+    write_u64(writer, render.args.len() as u64)?;
+    for arg in &render.args {
+        write_bytes(writer, arg.as_bytes())?;
+    }
+    write_u64(writer, render.files.len() as u64)?;
+    for (path, contents) in &render.files {
+        write_bytes(writer, path.as_bytes())?;
+        write_bytes(writer, contents)?;
+    }
+    writer.flush()?;
+
+    read_magic(reader, "not a render worker")?;
+    read_u64(reader)
+}
+
+/// Has the worker render a pass. A worker that sends back something else than a film of the
+/// expected size, because it runs another build or its stream got garbled, is treated as lost
+fn render_on_worker(
+    reader: &mut impl Read,
+    writer: &mut impl Write,
+    pass: i64,
+    data_len: u64,
+) -> std::io::Result<PassResult> {
+    writer.write_all(&[JOB])?;
+    write_u64(writer, pass as u64)?;
+    writer.flush()?;
+
+    let returned_pass = read_u64(reader)? as i64;
+    if returned_pass != pass {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "the worker sent back the wrong pass",
+        ));
+    }
+
+    let mut values = [0; 8];
+    for value in values.iter_mut() {
+        *value = read_u64(reader)?;
+    }
+
+    let len = read_u64(reader)?;
+    if len != data_len {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "the worker sent a film with {} values instead of {}",
+                len, data_len
+            ),
+        ));
+    }
+    let mut bytes = vec![0; len as usize * 8];
+    reader.read_exact(&mut bytes)?;
+    let data = bytes
+        .chunks_exact(8)
+        .map(|c| f64::from_le_bytes(c.try_into().expect("chunks are 8 bytes")))
+        .collect();
+
+    Ok(PassResult {
+        pass,
+        data,
+        stats: RenderStats::from_values(values),
+    })
+}
+
+/// The passes of a render that are being rendered by the workers
+pub struct Farm {
+    queue: Arc<JobQueue>,
+    workers: Arc<Mutex<Workers>>,
+    pass_timeout: Duration,
+    receiver: Receiver<PassResult>,
+    messages: Receiver<String>,
+}
+
+impl Farm {
+    /// Waits a little while for a worker to finish a pass. Returns None if none did
+    pub fn next_result(&self, timeout: Duration) -> Option<PassResult> {
+        self.receiver.recv_timeout(timeout).ok()
+    }
+
+    /// Fails when no worker has been connected for as long as a worker gets to render a pass,
+    /// since then nothing is rendering the passes that are left
+    pub fn check_workers(&self) -> error::Result<()> {
+        let workers = self.workers.lock().expect("worker count lock poisoned");
+        match workers.none_since {
+            Some(since) if since.elapsed() >= self.pass_timeout => {
+                Err(error::Error::InvalidSettings(format!(
+                    "no worker has been connected to the coordinator for {} seconds",
+                    self.pass_timeout.as_secs_f64()
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// What happened to the workers since the last time, like workers that were lost
+    pub fn messages(&self) -> Vec<String> {
+        self.messages.try_iter().collect()
+    }
+
+    /// Stops handing out passes, and tells the workers to quit when they are done with the pass they have
+    pub fn stop(&self) {
+        self.queue.stop();
+    }
+}
+
+/// A pass rendered by a worker
+pub struct PassResult {
+    pass: i64,
+    data: Vec<f64>, // The film of the pass, in the format of Film::data
+    stats: RenderStats,
+}
+
+impl PassResult {
+    /// returns the pass that was rendered
+    pub fn pass(&self) -> i64 {
+        self.pass
+    }
+
+    /// returns the film data of the pass
+    pub fn data(&self) -> &[f64] {
+        &self.data
+    }
+
+    /// returns the stats of the worker for the pass
+    pub fn stats(&self) -> &RenderStats {
+        &self.stats
+    }
+}
+
+/// A worker process that renders the passes a coordinator gives it
+pub struct Worker {
+    reader: BufReader<TcpStream>,
+    writer: BufWriter<TcpStream>,
+    seed: u64,
+    args: Vec<String>,
+    files_dir: Option<PathBuf>, // Where the files sent by the coordinator are kept while the worker runs
+}
+
+impl Worker {
+    /// Connects to a coordinator and receives the render it is working on
+    pub fn connect(addr: impl ToSocketAddrs) -> std::io::Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = BufWriter::new(stream);
+
+        read_magic(&mut reader, "not a render coordinator")?;
+        let coordinator_build = read_u64(&mut reader)?;
+
+        // the coordinator is told which build this is either way, so it can tell why the worker isn't used
+        let own_build = build_id()?;
+        writer.write_all(MAGIC)?;
+        write_u64(&mut writer, own_build)?;
+        writer.flush()?;
+        if coordinator_build != own_build {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "the coordinator runs another build of the renderer",
+            ));
+        }
+
+        let seed = read_u64(&mut reader)?;
+        let args: Vec<String> = (0..read_u64(&mut reader)?)
+            .map(|_| read_string(&mut reader))
+            .collect::<std::io::Result<_>>()?;
+
+        // the files are saved here, and the command line is pointed at them instead of the paths on the coordinator
+        let files_count = read_u64(&mut reader)?;
+        let files_dir = (files_count > 0)
+            .then(|| std::env::temp_dir().join(format!("raytracer-worker-{}", std::process::id())));
+        let mut files = Vec::new();
+        if let Some(dir) = &files_dir {
+            fs::create_dir_all(dir)?;
+            for i in 0..files_count {
+                let path = read_string(&mut reader)?;
+                let contents = read_bytes(&mut reader)?;
+                let local = dir.join(i.to_string());
+                fs::write(&local, contents)?;
+                files.push((path, local));
+            }
+        }
+        let args = args.into_iter().map(|arg| local_arg(arg, &files)).collect();
+
+        Ok(Self {
+            reader,
+            writer,
+            seed,
+            args,
+            files_dir,
+        })
+    }
+
+    /// returns the seed of the render
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// returns the command line of the render, without the program name
+    pub fn args(&self) -> &[String] {
+        &self.args
+    }
+
+    /// Renders the passes the coordinator sends with the given function, until the coordinator says the render is over.
+    /// A pass that fails to render ends the serving, and the coordinator hands the pass to another worker
+    pub fn serve(
+        mut self,
+        mut render_pass: impl FnMut(i64) -> error::Result<(Vec<f64>, RenderStats)>,
+    ) -> error::Result<()> {
+        loop {
+            let mut message = [0; 1];
+            self.reader.read_exact(&mut message)?;
+            if message[0] != JOB {
+                return Ok(());
+            }
+
+            let pass = read_u64(&mut self.reader)?;
+            let (data, stats) = render_pass(pass as i64)?;
+
+            write_u64(&mut self.writer, pass)?;
+            for value in stats.values() {
+                write_u64(&mut self.writer, value)?;
+            }
+            write_u64(&mut self.writer, data.len() as u64)?;
+            for value in data {
+                self.writer.write_all(&value.to_le_bytes())?;
+            }
+            self.writer.flush()?;
+        }
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        if let Some(dir) = &self.files_dir {
+            let _ = fs::remove_dir_all(dir);
+        }
+    }
+}
+
+/// Replaces a path the coordinator sent a file for with where the worker saved it.
+/// A path is either a whole argument, or follows a colon like in "mask:<path>"
+fn local_arg(arg: String, files: &[(String, PathBuf)]) -> String {
+    for (path, local) in files {
+        if let Some(prefix) = arg.strip_suffix(path.as_str()) {
+            if prefix.is_empty() || prefix.ends_with(':') {
+                return format!("{}{}", prefix, local.display());
+            }
+        }
+    }
+    arg
+}
+
+/// A hash of the executable, which tells apart builds that could render different images from the same command line
+fn build_id() -> std::io::Result<u64> {
+    static BUILD_ID: OnceLock<u64> = OnceLock::new();
+    if let Some(build_id) = BUILD_ID.get() {
+        return Ok(*build_id);
+    }
+
+    // 64 bit FNV-1a
+    let executable = std::fs::read(std::env::current_exe()?)?;
+    let hash = executable
+        .iter()
+        .fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+            (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+        });
+    Ok(*BUILD_ID.get_or_init(|| hash))
+}
+
+fn read_magic(reader: &mut impl Read, message: &str) -> std::io::Result<()> {
+    let mut magic = [0; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(Error::new(ErrorKind::InvalidData, message.to_string()));
+    }
+    Ok(())
+}
+
+fn write_u64(writer: &mut impl Write, value: u64) -> std::io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn write_bytes(writer: &mut impl Write, bytes: &[u8]) -> std::io::Result<()> {
+    write_u64(writer, bytes.len() as u64)?;
+    writer.write_all(bytes)
+}
+
+fn read_bytes(reader: &mut impl Read) -> std::io::Result<Vec<u8>> {
+    let len = read_u64(reader)?;
+    let mut bytes = Vec::new();
+    // the length comes from the other side, so it is only trusted as far as there are bytes to read
+    reader.take(len).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != len {
+        return Err(Error::new(ErrorKind::UnexpectedEof, "message ended early"));
+    }
+    Ok(bytes)
+}
+
+fn read_string(reader: &mut impl Read) -> std::io::Result<String> {
+    String::from_utf8(read_bytes(reader)?).map_err(|err| Error::new(ErrorKind::InvalidData, err))
+}
+
+fn read_u64(reader: &mut impl Read) -> std::io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn worker_gets_the_render_and_sends_back_its_passes() {
+        let args = vec!["--heightmap".to_string(), "/hills.pgm".to_string()];
+        let coordinator = Coordinator::bind("127.0.0.1:0", args)
+            .unwrap()
+            .with_files(vec![("/hills.pgm".to_string(), b"P5 hills".to_vec())]);
+        let addr = coordinator.local_addr().unwrap();
+        let farm = coordinator.start(99, 0..2, (2, 1));
+
+        let worker = std::thread::spawn(move || {
+            let worker = Worker::connect(addr).unwrap();
+            assert_eq!(worker.seed(), 99);
+            assert_eq!(worker.args()[0], "--heightmap");
+            // the heightmap is read from where the worker saved it
+            assert_eq!(fs::read(&worker.args()[1]).unwrap(), b"P5 hills");
+            worker
+                .serve(|pass| {
+                    Ok((
+                        vec![pass as f64; 2 * VALUES_PR_PIXEL],
+                        RenderStats::default(),
+                    ))
+                })
+                .unwrap();
+        });
+
+        let mut passes = Vec::new();
+        while passes.len() < 2 {
+            if let Some(result) = farm.next_result(Duration::from_secs(5)) {
+                assert_eq!(
+                    result.data(),
+                    vec![result.pass() as f64; 2 * VALUES_PR_PIXEL]
+                );
+                passes.push(result.pass());
+            }
+        }
+        farm.stop();
+        worker.join().unwrap();
+        passes.sort();
+        assert_eq!(passes, [0, 1]);
+    }
+
+    #[test]
+    fn film_of_the_wrong_size_is_rejected() {
+        let mut answer = Vec::new();
+        write_u64(&mut answer, 5).unwrap();
+        for value in RenderStats::default().values() {
+            write_u64(&mut answer, value).unwrap();
+        }
+        write_u64(&mut answer, 3).unwrap();
+        answer.extend_from_slice(&[0; 3 * 8]);
+
+        let result = render_on_worker(&mut answer.as_slice(), &mut Vec::new(), 5, 4);
+        assert_eq!(
+            result.err().map(|err| err.kind()),
+            Some(ErrorKind::InvalidData)
+        );
+    }
+
+    #[test]
+    fn render_without_workers_gives_up() {
+        let farm = Coordinator::bind("127.0.0.1:0", Vec::new())
+            .unwrap()
+            .with_pass_timeout(Duration::from_millis(20))
+            .start(0, 0..1, (1, 1));
+        assert!(farm.next_result(Duration::from_millis(50)).is_none());
+        assert!(farm.check_workers().is_err());
+    }
+
+    #[test]
+    fn only_whole_paths_are_pointed_at_the_saved_files() {
+        let files = [("a.pgm".to_string(), PathBuf::from("/tmp/0"))];
+        assert_eq!(local_arg("a.pgm".to_string(), &files), "/tmp/0");
+        assert_eq!(local_arg("mask:a.pgm".to_string(), &files), "mask:/tmp/0");
+        assert_eq!(local_arg("data.pgm".to_string(), &files), "data.pgm");
+    }
+}
@@ -0,0 +1,68 @@
+use std::{any::Any, fmt, ops::Range};
+
+/// Everything that can go wrong while rendering and writing an image
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error), // Reading or writing a file failed, like when the disk is full
+    InvalidSettings(String), // The settings of the camera or of an object don't make sense, with the reason
+    ThreadPanicked {
+        pass: i64,        // The pass the thread was rendering
+        rows: Range<i64>, // The tile of the image the thread was rendering, as the rows it had
+        message: String,  // What the thread panicked with
+    },
+}
+
+/// Result with the error of the crate
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// Creates the error for a render thread that panicked, from what it panicked with
+    pub fn thread_panicked(pass: i64, rows: Range<i64>, payload: Box<dyn Any + Send>) -> Self {
+        // panic!() with a message gives a &str or a String, anything else can't be shown
+        let message = match payload.downcast::<String>() {
+            Ok(message) => *message,
+            Err(payload) => match payload.downcast::<&str>() {
+                Ok(message) => message.to_string(),
+                Err(_) => "unknown panic".to_string(),
+            },
+        };
+        Error::ThreadPanicked {
+            pass,
+            rows,
+            message,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "i/o error: {}", err),
+            Error::InvalidSettings(reason) => write!(f, "invalid settings: {}", reason),
+            Error::ThreadPanicked {
+                pass,
+                rows,
+                message,
+            } => write!(
+                f,
+                "the thread rendering rows {}..{} of pass {} panicked: {}",
+                rows.start, rows.end, pass, message
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::Io(err)
+    }
+}
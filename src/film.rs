@@ -0,0 +1,476 @@
+use std::{
+    f64::consts::PI,
+    fs::File,
+    io::{BufWriter, Write},
+    ops::Range,
+};
+
+use crate::error::{self, Error};
+use crate::point3::Color;
+
+/// The reconstruction filter that decides how much each sample counts towards the pixels around it.
+/// Chosen with `--filter <name>`, optionally followed by the radius in pixels after a colon, like "gaussian:2"
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Filter {
+    /// Every sample inside the radius counts the same
+    Box { radius: f64 },
+    /// Weights fall off linearly from the center
+    Tent { radius: f64 },
+    /// A smooth falloff. Higher alpha makes it narrower
+    Gaussian { radius: f64, alpha: f64 },
+    /// The Mitchell-Netravali cubic, which sharpens a little with its small negative lobes
+    Mitchell { radius: f64, b: f64, c: f64 },
+    /// A windowed sinc. The sharpest of the filters, but it can ring around edges
+    Lanczos { radius: f64 },
+}
+
+impl Filter {
+    /// Finds a filter from its name on the command line. The radius after the colon is optional
+    pub fn from_name(name: &str) -> Option<Filter> {
+        let (name, radius) = match name.split_once(':') {
+            Some((name, radius)) => (name, Some(radius.parse().ok()?)),
+            None => (name, None),
+        };
+
+        match name {
+            "box" => Some(Filter::Box {
+                radius: radius.unwrap_or(0.5),
+            }),
+            "tent" => Some(Filter::Tent {
+                radius: radius.unwrap_or(1.0),
+            }),
+            "gaussian" => Some(Filter::Gaussian {
+                radius: radius.unwrap_or(1.5),
+                alpha: 2.0,
+            }),
+            "mitchell" => Some(Filter::Mitchell {
+                radius: radius.unwrap_or(2.0),
+                b: 1.0 / 3.0,
+                c: 1.0 / 3.0,
+            }),
+            "lanczos" => Some(Filter::Lanczos {
+                radius: radius.unwrap_or(3.0),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Checks that the filter reaches the center of the pixel every sample is in.
+    /// A smaller radius would leave samples that don't count towards any pixel
+    pub fn validate(&self) -> Result<(), String> {
+        if self.radius().is_nan() || self.radius() < 0.5 {
+            return Err(format!(
+                "the filter radius has to be at least half a pixel, got {}",
+                self.radius()
+            ));
+        }
+        Ok(())
+    }
+
+    /// The kind of the filter and its settings as numbers, so it can be saved
+    pub fn to_values(self) -> (u64, [f64; 3]) {
+        match self {
+            Filter::Box { radius } => (0, [radius, 0.0, 0.0]),
+            Filter::Tent { radius } => (1, [radius, 0.0, 0.0]),
+            Filter::Gaussian { radius, alpha } => (2, [radius, alpha, 0.0]),
+            Filter::Mitchell { radius, b, c } => (3, [radius, b, c]),
+            Filter::Lanczos { radius } => (4, [radius, 0.0, 0.0]),
+        }
+    }
+
+    /// The filter saved with to_values(), or None if the kind isn't a filter
+    pub fn from_values(kind: u64, [radius, a, b]: [f64; 3]) -> Option<Filter> {
+        match kind {
+            0 => Some(Filter::Box { radius }),
+            1 => Some(Filter::Tent { radius }),
+            2 => Some(Filter::Gaussian { radius, alpha: a }),
+            3 => Some(Filter::Mitchell { radius, b: a, c: b }),
+            4 => Some(Filter::Lanczos { radius }),
+            _ => None,
+        }
+    }
+
+    /// How far from a sample the filter reaches, in pixels
+    pub fn radius(&self) -> f64 {
+        match *self {
+            Filter::Box { radius }
+            | Filter::Tent { radius }
+            | Filter::Gaussian { radius, .. }
+            | Filter::Mitchell { radius, .. }
+            | Filter::Lanczos { radius } => radius,
+        }
+    }
+
+    /// The weight of a sample that is (dx, dy) pixels away from the pixel center
+    pub fn evaluate(&self, dx: f64, dy: f64) -> f64 {
+        self.evaluate_1d(dx) * self.evaluate_1d(dy)
+    }
+
+    fn evaluate_1d(&self, x: f64) -> f64 {
+        let x = x.abs();
+        match *self {
+            Filter::Box { radius } => {
+                if x <= radius {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            Filter::Tent { radius } => (radius - x).max(0.0),
+            Filter::Gaussian { radius, alpha } => {
+                // shifted down so it reaches 0 at the radius instead of being cut off
+                ((-alpha * x * x).exp() - (-alpha * radius * radius).exp()).max(0.0)
+            }
+            Filter::Mitchell { radius, b, c } => {
+                // the cubic is defined from 0 to 2, so it is stretched over the radius
+                let x = 2.0 * x / radius;
+                if x >= 2.0 {
+                    0.0
+                } else if x >= 1.0 {
+                    ((-b - 6.0 * c) * x * x * x
+                        + (6.0 * b + 30.0 * c) * x * x
+                        + (-12.0 * b - 48.0 * c) * x
+                        + (8.0 * b + 24.0 * c))
+                        / 6.0
+                } else {
+                    ((12.0 - 9.0 * b - 6.0 * c) * x * x * x
+                        + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+                        + (6.0 - 2.0 * b))
+                        / 6.0
+                }
+            }
+            Filter::Lanczos { radius } => {
+                if x >= radius {
+                    0.0
+                } else {
+                    sinc(x) * sinc(x / radius)
+                }
+            }
+        }
+    }
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Filter::Box { radius: 0.5 }
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x < 1e-5 {
+        return 1.0;
+    }
+    (PI * x).sin() / (PI * x)
+}
+
+/// The amount of values pr pixel in the data of a film
+pub const VALUES_PR_PIXEL: usize = 8;
+
+/// The image being rendered. Samples are splatted onto the pixels around them, weighted by the filter,
+/// and each pixel is the weighted average of the samples that reached it
+pub struct Film {
+    width: i64,
+    height: i64,
+    first_row: i64, // The row of the image the film starts at, which is 0 unless the film is a band of the image
+    // Samples only reach pixels inside the same view, so stereo views don't bleed into each other
+    view_width: i64,
+    view_height: i64,
+    filter: Filter,
+    colors: Vec<Color>, // The sum of the weighted samples of each pixel
+    weights: Vec<f64>,  // The sum of the weights of each pixel
+    // The sum and count of the samples inside each pixel, without the filter. The negative lobes of some filters
+    // can cancel out all the weight of a pixel, which then shows these instead
+    box_colors: Vec<Color>,
+    box_counts: Vec<f64>,
+}
+
+impl Film {
+    pub fn new(width: i64, height: i64, filter: Filter) -> Self {
+        Self::band(width, height, 0..height, filter)
+    }
+
+    /// A film for some of the rows of an image of the given size. Samples only reach the pixels in those rows,
+    /// so a thread that renders part of the image can use a film of the rows its samples reach
+    pub fn band(width: i64, height: i64, rows: Range<i64>, filter: Filter) -> Self {
+        let rows = rows.start.max(0)..rows.end.min(height);
+        let pixels = (width * (rows.end - rows.start)) as usize;
+        Self {
+            width,
+            height: rows.end - rows.start,
+            first_row: rows.start,
+            view_width: width,
+            view_height: height,
+            filter,
+            colors: vec![Color::new(); pixels],
+            weights: vec![0.0; pixels],
+            box_colors: vec![Color::new(); pixels],
+            box_counts: vec![0.0; pixels],
+        }
+    }
+
+    /// Splits the film into views of the given size, which samples don't cross
+    pub fn with_views(mut self, view_width: i64, view_height: i64) -> Self {
+        self.view_width = view_width;
+        self.view_height = view_height;
+        self
+    }
+
+    /// Adds a sample at the position (x, y) of the image, where the pixel (i, j) covers i to i + 1 and j to j + 1
+    pub fn add_sample(&mut self, x: f64, y: f64, color: Color) {
+        let radius = self.filter.radius();
+
+        // the view the sample belongs to
+        let view_x = (x as i64 / self.view_width) * self.view_width;
+        let view_y = (y as i64 / self.view_height) * self.view_height;
+
+        let x0 = ((x - 0.5 - radius).ceil() as i64).max(view_x);
+        let x1 = ((x - 0.5 + radius).floor() as i64).min(view_x + self.view_width - 1);
+        let y0 = ((y - 0.5 - radius).ceil() as i64)
+            .max(view_y)
+            .max(self.first_row);
+        let y1 = ((y - 0.5 + radius).floor() as i64)
+            .min(view_y + self.view_height - 1)
+            .min(self.first_row + self.height - 1);
+
+        for py in y0..=y1 {
+            for px in x0..=x1 {
+                let weight = self
+                    .filter
+                    .evaluate(px as f64 + 0.5 - x, py as f64 + 0.5 - y);
+                if weight == 0.0 {
+                    continue;
+                }
+
+                let index = ((py - self.first_row) * self.width + px) as usize;
+                self.colors[index] = self.colors[index] + color * weight;
+                self.weights[index] += weight;
+            }
+        }
+
+        let (px, py) = (x.floor() as i64, y.floor() as i64);
+        let rows = self.first_row..self.first_row + self.height;
+        if (0..self.width).contains(&px) && rows.contains(&py) {
+            let index = ((py - self.first_row) * self.width + px) as usize;
+            self.box_colors[index] = self.box_colors[index] + color;
+            self.box_counts[index] += 1.0;
+        }
+    }
+
+    /// returns the width and height of the film
+    pub fn size(&self) -> (i64, i64) {
+        (self.width, self.height)
+    }
+
+    /// returns the filter the samples are spread over the pixels with
+    pub fn filter(&self) -> Filter {
+        self.filter
+    }
+
+    /// The weighted color sums and weights of all the pixels, followed by their unfiltered color sums and sample
+    /// counts, VALUES_PR_PIXEL values pr pixel. Used to save the film
+    pub fn data(&self) -> Vec<f64> {
+        (0..self.weights.len())
+            .flat_map(|i| {
+                let (color, box_color) = (self.colors[i], self.box_colors[i]);
+                [
+                    color.r(),
+                    color.g(),
+                    color.b(),
+                    self.weights[i],
+                    box_color.r(),
+                    box_color.g(),
+                    box_color.b(),
+                    self.box_counts[i],
+                ]
+            })
+            .collect()
+    }
+
+    /// Replaces the samples of the film with data saved from a film of the same size
+    pub fn set_data(&mut self, data: &[f64]) -> error::Result<()> {
+        if data.len() != self.weights.len() * VALUES_PR_PIXEL {
+            return Err(Error::InvalidSettings(format!(
+                "film data has {} values, but the film needs {}",
+                data.len(),
+                self.weights.len() * VALUES_PR_PIXEL
+            )));
+        }
+        for (i, pixel) in data.chunks_exact(VALUES_PR_PIXEL).enumerate() {
+            self.colors[i] = Color::from_rgb(pixel[0], pixel[1], pixel[2]);
+            self.weights[i] = pixel[3];
+            self.box_colors[i] = Color::from_rgb(pixel[4], pixel[5], pixel[6]);
+            self.box_counts[i] = pixel[7];
+        }
+        Ok(())
+    }
+
+    /// Adds all the samples of another film of the same width to this one.
+    /// The other film can be a band of the rows of this one
+    pub fn merge(&mut self, other: &Film) {
+        let start = ((other.first_row - self.first_row) * self.width) as usize;
+        let pixels = start..start + other.weights.len();
+        for (color, other_color) in self.colors[pixels.clone()].iter_mut().zip(&other.colors) {
+            *color = *color + *other_color;
+        }
+        for (weight, other_weight) in self.weights[pixels.clone()].iter_mut().zip(&other.weights) {
+            *weight += other_weight;
+        }
+        let box_colors = self.box_colors[pixels.clone()].iter_mut();
+        for (color, other_color) in box_colors.zip(&other.box_colors) {
+            *color = *color + *other_color;
+        }
+        for (count, other_count) in self.box_counts[pixels].iter_mut().zip(&other.box_counts) {
+            *count += other_count;
+        }
+    }
+
+    /// Removes all the samples, so the film can be used again
+    pub fn clear(&mut self) {
+        self.colors.fill(Color::new());
+        self.weights.fill(0.0);
+        self.box_colors.fill(Color::new());
+        self.box_counts.fill(0.0);
+    }
+
+    /// The final color of a pixel. Negative filter lobes can make it slightly negative, so it is clamped to 0.
+    /// If they cancel out all the weight of the pixel, the samples inside the pixel are averaged without the filter
+    pub fn pixel(&self, x: i64, y: i64) -> Color {
+        let index = ((y - self.first_row) * self.width + x) as usize;
+        let weight = self.weights[index];
+        let count = self.box_counts[index];
+        let color = if weight > 0.0 {
+            self.colors[index] * (1.0 / weight)
+        } else if count > 0.0 {
+            self.box_colors[index] * (1.0 / count)
+        } else {
+            return Color::new();
+        };
+        Color::from_rgb(color.r().max(0.0), color.g().max(0.0), color.b().max(0.0))
+    }
+
+    /// The gamma corrected 8 bit rgb values of all the pixels, row by row from the top left corner
+    pub fn rgb8(&self) -> Vec<u8> {
+        (self.first_row..self.first_row + self.height)
+            .flat_map(|y| (0..self.width).flat_map(move |x| self.pixel(x, y).rgb8()))
+            .collect()
+    }
+
+    /// Writes the film as a ppm image
+    pub fn write_ppm(&self, file: &mut BufWriter<File>) -> std::io::Result<()> {
+        file.write_all(format!("P3\n{} {}\n255\n", self.width, self.height).as_bytes())?;
+
+        for y in self.first_row..self.first_row + self.height {
+            for x in 0..self.width {
+                file.write_all(format!("\n{}", self.pixel(x, y).write_color()).as_bytes())?;
+            }
+        }
+        file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filter_weights_peak_at_the_center_and_end_at_the_radius() {
+        for name in ["box", "tent", "gaussian", "mitchell", "lanczos"] {
+            let filter = Filter::from_name(name).unwrap();
+            let radius = filter.radius();
+            assert!(filter.evaluate(0.0, 0.0) > 0.0, "{}", name);
+            assert!(
+                filter.evaluate(0.0, 0.0) >= filter.evaluate(0.3, 0.2),
+                "{}",
+                name
+            );
+            assert_eq!(filter.evaluate(radius + 0.01, 0.0), 0.0, "{}", name);
+            assert_eq!(filter.evaluate(0.0, -radius - 0.01), 0.0, "{}", name);
+        }
+    }
+
+    #[test]
+    fn filters_smaller_than_half_a_pixel_are_rejected() {
+        assert!(Filter::Box { radius: 0.5 }.validate().is_ok());
+        assert!(Filter::Box { radius: 0.4 }.validate().is_err());
+        assert!(Filter::Tent { radius: 0.0 }.validate().is_err());
+        assert!(Filter::Lanczos { radius: f64::NAN }.validate().is_err());
+    }
+
+    #[test]
+    fn box_filtered_pixel_is_the_average_of_its_samples() {
+        let mut film = Film::new(2, 2, Filter::default());
+        film.add_sample(0.2, 0.3, Color::from_rgb(1, 0, 0));
+        film.add_sample(0.7, 0.9, Color::from_rgb(0, 1, 0));
+        film.add_sample(1.5, 1.5, Color::from_rgb(0, 0, 1));
+
+        assert!(film.pixel(0, 0) == Color::from_rgb(0.5, 0.5, 0));
+        assert!(film.pixel(1, 1) == Color::from_rgb(0, 0, 1));
+        assert!(film.pixel(1, 0) == Color::new());
+    }
+
+    #[test]
+    fn pixel_whose_weight_cancels_out_falls_back_to_its_own_samples() {
+        let mut film = Film::new(3, 1, Filter::Lanczos { radius: 3.0 });
+        film.add_sample(1.5, 0.5, Color::from_rgb(0.2, 0.4, 0.6));
+        // samples in the negative lobe of pixel 1, one and a half pixels from its center
+        for _ in 0..10 {
+            film.add_sample(0.0, 0.5, Color::from_rgb(1, 1, 1));
+        }
+
+        assert!(film.weights[1] < 0.0);
+        assert!(film.pixel(1, 0) == Color::from_rgb(0.2, 0.4, 0.6));
+    }
+
+    #[test]
+    fn bands_merge_into_the_same_image_as_one_film() {
+        let filter = Filter::from_name("gaussian").unwrap();
+        let mut whole = Film::new(5, 6, filter);
+        // the rows the bands render, with room for the samples that reach past them
+        let mut top = Film::band(5, 6, -2..5, filter);
+        let mut bottom = Film::band(5, 6, 1..8, filter);
+        for i in 0..60 {
+            let i = i as f64;
+            let (x, y) = ((i * 0.37) % 5.0, (i * 0.61) % 6.0);
+            let color = Color::from_rgb(i, 1, 0.5);
+            whole.add_sample(x, y, color);
+            if y < 3.0 {
+                top.add_sample(x, y, color);
+            } else {
+                bottom.add_sample(x, y, color);
+            }
+        }
+
+        let mut merged = Film::new(5, 6, filter);
+        merged.merge(&top);
+        merged.merge(&bottom);
+        for (a, b) in merged.data().iter().zip(whole.data()) {
+            assert!((a - b).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn data_restores_the_same_pixels() {
+        let mut film = Film::new(3, 2, Filter::from_name("mitchell").unwrap());
+        for i in 0..20 {
+            let i = i as f64;
+            film.add_sample(
+                (i * 0.37) % 3.0,
+                (i * 0.61) % 2.0,
+                Color::from_rgb(i, 1, 0.5),
+            );
+        }
+
+        let mut restored = Film::new(3, 2, Filter::from_name("mitchell").unwrap());
+        restored.set_data(&film.data()).unwrap();
+        assert_eq!(restored.data(), film.data());
+
+        assert!(restored.set_data(&film.data()[1..]).is_err());
+
+        // merging a film with itself doubles the sums, but not the average
+        restored.merge(&film);
+        for (x, y) in [(0, 0), (1, 1), (2, 0)] {
+            let (a, b) = (film.pixel(x, y), restored.pixel(x, y));
+            assert!((a.r() - b.r()).abs() < 1e-9 && (a.g() - b.g()).abs() < 1e-9);
+        }
+    }
+}
@@ -0,0 +1,300 @@
+use std::{
+    fs,
+    io::{Error, ErrorKind},
+    path::Path,
+};
+
+use crate::aabb::Aabb;
+use crate::error;
+use crate::hitable::{HitRecord, Hitable};
+use crate::material::Material;
+use crate::pgm::read_pgm;
+use crate::point3::{Point3, Vec3};
+use crate::ray::Ray;
+
+/// Terrain made from a grid of heights, stretched over a box in world space. Each cell between four samples
+/// is split into two triangles, and rays walk through the cells they pass over instead of testing all of them
+pub struct Heightfield<M: Material> {
+    nx: usize, // The amount of samples along x
+    nz: usize, // The amount of samples along z
+    heights: Vec<f64>,
+    normals: Vec<Vec3>, // Smooth normals at each sample, interpolated across the triangles
+    min: Point3,        // A height of 0 is at min.y()
+    max: Point3,        // A height of 1 is at max.y()
+    bbox: Aabb,         // Only reaches from the lowest to the highest height
+    material: M,
+}
+
+impl<M: Material> Heightfield<M> {
+    /// Creates a heightfield from heights stored with x changing fastest, then z.
+    /// Heights from 0 to 1 go from the bottom to the top of the box
+    pub fn from_values(
+        dimensions: (usize, usize),
+        values: Vec<f32>,
+        min: Point3,
+        max: Point3,
+        material: M,
+    ) -> error::Result<Self> {
+        let (nx, nz) = dimensions;
+        if nx < 2 || nz < 2 {
+            return Err(error::Error::InvalidSettings(
+                "a heightfield needs at least 2x2 samples".to_string(),
+            ));
+        }
+        if nx.checked_mul(nz) != Some(values.len()) {
+            return Err(error::Error::InvalidSettings(
+                "heightfield has the wrong amount of values".to_string(),
+            ));
+        }
+
+        let bbox = Aabb::new(min, max).corners();
+        let (min, max) = (bbox[0], bbox[7]);
+        let heights: Vec<f64> = values
+            .iter()
+            .map(|h| min.y() + *h as f64 * (max.y() - min.y()))
+            .collect();
+
+        // the box is padded, so flat terrain isn't infinitely thin
+        let (low, high) = heights
+            .iter()
+            .fold((f64::INFINITY, f64::NEG_INFINITY), |(low, high), h| {
+                (low.min(*h), high.max(*h))
+            });
+        let bbox = Aabb::new(
+            Point3::from_xyz(min.x(), low, min.z()),
+            Point3::from_xyz(max.x(), high, max.z()),
+        )
+        .pad(0.0001);
+
+        let mut heightfield = Self {
+            nx,
+            nz,
+            heights,
+            normals: Vec::new(),
+            min,
+            max,
+            bbox,
+            material,
+        };
+        heightfield.normals = heightfield.sample_normals();
+        Ok(heightfield)
+    }
+
+    /// Loads a heightfield from a file. Grayscale pgm images with 8 or 16 bits are supported, where black is the bottom
+    /// of the box and white is the top. Any other file is read as a square grid of little endian f32 heights
+    pub fn load(
+        path: impl AsRef<Path>,
+        min: Point3,
+        max: Point3,
+        material: M,
+    ) -> std::io::Result<Self> {
+        let bytes = fs::read(path)?;
+        let (dimensions, values) = if bytes.starts_with(b"P5") || bytes.starts_with(b"P2") {
+            read_pgm(&bytes)?
+        } else {
+            read_raw(&bytes)?
+        };
+        if dimensions.0 < 2 || dimensions.1 < 2 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "a heightfield needs at least 2x2 samples",
+            ));
+        }
+
+        Heightfield::from_values(dimensions, values, min, max, material)
+            .map_err(|err| Error::new(ErrorKind::InvalidData, err.to_string()))
+    }
+
+    fn height(&self, x: usize, z: usize) -> f64 {
+        self.heights[x + self.nx * z]
+    }
+
+    /// The world space position of a sample
+    fn vertex(&self, x: usize, z: usize) -> Point3 {
+        let (cell_x, cell_z) = self.cell_size();
+        Point3::from_xyz(
+            self.min.x() + x as f64 * cell_x,
+            self.height(x, z),
+            self.min.z() + z as f64 * cell_z,
+        )
+    }
+
+    fn cell_size(&self) -> (f64, f64) {
+        (
+            (self.max.x() - self.min.x()) / (self.nx - 1) as f64,
+            (self.max.z() - self.min.z()) / (self.nz - 1) as f64,
+        )
+    }
+
+    /// Finds the normal at each sample from the slope to its neighbours
+    fn sample_normals(&self) -> Vec<Vec3> {
+        let (cell_x, cell_z) = self.cell_size();
+        let mut normals = Vec::with_capacity(self.nx * self.nz);
+        for z in 0..self.nz {
+            for x in 0..self.nx {
+                let (x0, x1) = (x.saturating_sub(1), (x + 1).min(self.nx - 1));
+                let (z0, z1) = (z.saturating_sub(1), (z + 1).min(self.nz - 1));
+                let slope_x =
+                    (self.height(x1, z) - self.height(x0, z)) / ((x1 - x0) as f64 * cell_x);
+                let slope_z =
+                    (self.height(x, z1) - self.height(x, z0)) / ((z1 - z0) as f64 * cell_z);
+                normals.push(Vec3::from_xyz(-slope_x, 1, -slope_z).unit_vec());
+            }
+        }
+        normals
+    }
+
+    /// Tests the two triangles of a cell. Returns the t of the hit and its interpolated normal
+    fn hit_cell(&self, x: usize, z: usize, r: &Ray, t_min: f64, t_max: f64) -> Option<(f64, Vec3)> {
+        let corners = [(x, z), (x + 1, z), (x, z + 1), (x + 1, z + 1)];
+        let mut closest: Option<(f64, Vec3)> = None;
+
+        for triangle in [
+            [corners[0], corners[2], corners[1]],
+            [corners[1], corners[2], corners[3]],
+        ] {
+            let [a, b, c] = triangle.map(|(x, z)| self.vertex(x, z));
+            let t_max = closest.map_or(t_max, |(t, _)| t);
+            if let Some((t, u, v)) = hit_triangle(a, b, c, r, t_min, t_max) {
+                let [na, nb, nc] = triangle.map(|(x, z)| self.normals[x + self.nx * z]);
+                let normal = (na * (1.0 - u - v) + nb * u + nc * v).unit_vec();
+                closest = Some((t, normal));
+            }
+        }
+
+        closest
+    }
+}
+
+impl<M: Material> Hitable for Heightfield<M> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let (t_enter, t_exit) = self.bbox.hit_interval(r, t_min, t_max)?;
+
+        // walk through the cells the ray passes over with a 2d dda, in grid coordinates
+        let (cell_x, cell_z) = self.cell_size();
+        let start = r.at(t_enter);
+        let grid_x = (start.x() - self.min.x()) / cell_x;
+        let grid_z = (start.z() - self.min.z()) / cell_z;
+        let mut x = (grid_x.floor().max(0.0) as usize).min(self.nx - 2);
+        let mut z = (grid_z.floor().max(0.0) as usize).min(self.nz - 2);
+
+        // how far along the ray it is to the next cell border, and between borders, on each axis
+        let axis = |position: f64, cell: usize, direction: f64, size: f64| {
+            if direction.abs() < 1e-12 {
+                return (f64::INFINITY, f64::INFINITY);
+            }
+            let border = if direction > 0.0 { cell + 1 } else { cell } as f64;
+            let t_next = t_enter + (border - position) * size / direction;
+            (t_next, size / direction.abs())
+        };
+        let (mut t_next_x, t_delta_x) = axis(grid_x, x, r.direction().x(), cell_x);
+        let (mut t_next_z, t_delta_z) = axis(grid_z, z, r.direction().z(), cell_z);
+
+        let mut t_cell_start = t_enter;
+        loop {
+            let t_cell_end = t_next_x.min(t_next_z).min(t_exit);
+
+            // skip the cell if the ray is above all of its corners the whole way through it
+            let ray_low = r.at(t_cell_start).y().min(r.at(t_cell_end).y());
+            let cell_top = [(x, z), (x + 1, z), (x, z + 1), (x + 1, z + 1)]
+                .iter()
+                .fold(f64::NEG_INFINITY, |top, (x, z)| {
+                    top.max(self.height(*x, *z))
+                });
+
+            if ray_low <= cell_top {
+                if let Some((t, normal)) = self.hit_cell(x, z, r, t_min, t_max) {
+                    let point = r.at(t);
+                    let u = (point.x() - self.min.x()) / (self.max.x() - self.min.x());
+                    let v = (point.z() - self.min.z()) / (self.max.z() - self.min.z());
+                    return Some(HitRecord::new(point, t, normal, &self.material).with_uv(u, v));
+                }
+            }
+
+            if t_cell_end >= t_exit {
+                return None;
+            }
+
+            // step into the next cell
+            t_cell_start = t_cell_end;
+            if t_next_x < t_next_z {
+                if r.direction().x() > 0.0 {
+                    x += 1;
+                } else {
+                    x = x.checked_sub(1)?;
+                }
+                t_next_x += t_delta_x;
+            } else {
+                if r.direction().z() > 0.0 {
+                    z += 1;
+                } else {
+                    z = z.checked_sub(1)?;
+                }
+                t_next_z += t_delta_z;
+            }
+            if x > self.nx - 2 || z > self.nz - 2 {
+                return None;
+            }
+        }
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.bbox)
+    }
+}
+
+/// Möller–Trumbore ray triangle intersection. Returns the t of the hit,
+/// and the barycentric coordinates of the hit along the edges to b and c
+fn hit_triangle(
+    a: Point3,
+    b: Point3,
+    c: Point3,
+    r: &Ray,
+    t_min: f64,
+    t_max: f64,
+) -> Option<(f64, f64, f64)> {
+    let (edge1, edge2) = (b - a, c - a);
+    let p = r.direction().cross_product(&edge2);
+    let determinant = edge1.dot_product(&p);
+    if determinant.abs() < 1e-12 {
+        return None;
+    }
+
+    let inv_determinant = 1.0 / determinant;
+    let offset = r.origin() - a;
+    let u = offset.dot_product(&p) * inv_determinant;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+
+    let q = offset.cross_product(&edge1);
+    let v = r.direction().dot_product(&q) * inv_determinant;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+
+    let t = edge2.dot_product(&q) * inv_determinant;
+    if t <= t_min || t_max <= t {
+        return None;
+    }
+
+    Some((t, u, v))
+}
+
+/// Reads a square grid of little endian f32 heights
+fn read_raw(bytes: &[u8]) -> std::io::Result<((usize, usize), Vec<f32>)> {
+    let count = bytes.len() / 4;
+    let side = (count as f64).sqrt().round() as usize;
+    if !bytes.len().is_multiple_of(4) || side * side != count {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "raw heightfield has to be a square grid of f32 values",
+        ));
+    }
+
+    let values = bytes
+        .chunks_exact(4)
+        .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+        .collect();
+    Ok(((side, side), values))
+}
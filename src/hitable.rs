@@ -1,15 +1,61 @@
-use crate::{material::Material, Point3, Ray, Vec3};
+use std::sync::Arc;
+
+use crate::{aabb::Aabb, material::Material, stats, Point3, Ray, Vec3};
 
 pub trait Hitable: Send + Sync {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>>;
+
+    /// The box that surrounds the whole object. Objects that go on forever return None
+    fn bounding_box(&self) -> Option<Aabb>;
+
+    /// The probability density (pr solid angle) that random_direction() picks the direction from the origin.
+    /// Used when the object is sampled as a light. Objects that can't be sampled return 0
+    fn pdf_value(&self, _origin: Point3, _direction: Vec3) -> f64 {
+        0.0
+    }
+
+    /// A random direction from the origin towards a point on the object
+    fn random_direction(&self, _origin: Point3) -> Vec3 {
+        Vec3::from_xyz(1, 0, 0)
+    }
+
+    /// Every part of the ray's line that is inside the object, as pairs of where it enters and leaves, sorted along the ray.
+    /// Used for constructive solid geometry, so the object has to be closed. By default the hits are found one after
+    /// another, starting from far behind the ray origin
+    fn hit_intervals(&self, r: &Ray) -> Vec<(HitRecord<'_>, HitRecord<'_>)> {
+        let mut hits = Vec::new();
+        let mut t_min = f64::NEG_INFINITY;
+        while let Some(rec) = self.hit(r, t_min, f64::INFINITY) {
+            t_min = rec.t() + 0.0001;
+            hits.push(rec);
+
+            // stop following rays that graze along a surface forever
+            if hits.len() >= MAX_INTERVAL_HITS {
+                break;
+            }
+        }
+
+        let mut hits = hits.into_iter();
+        let mut intervals = Vec::new();
+        while let (Some(enter), Some(exit)) = (hits.next(), hits.next()) {
+            intervals.push((enter, exit));
+        }
+        intervals
+    }
 }
 
+/// The most hits hit_intervals() follows a ray through
+const MAX_INTERVAL_HITS: usize = 64;
+
 /// Struct for keeping track info regarding ray intersections with objects
 pub struct HitRecord<'a> {
     point: Point3,              //point of the intersection
     normal: Vec3,               //The normal of the point
     t: f64,                     // Distance from the camera to the point
     material: &'a dyn Material, // The material of the object that was hit
+    u: f64,                     // Surface coordinates of the point, between 0 and 1
+    v: f64,
+    object_ray: Option<Ray>, // The ray in the space of the object, when the object was hit through a transform
 }
 
 impl<'a> HitRecord<'a> {
@@ -21,11 +67,32 @@ impl<'a> HitRecord<'a> {
             t,
             normal,
             material,
+            u: 0.0,
+            v: 0.0,
+            object_ray: None,
         }
     }
 
+    /// Sets the surface coordinates of the hit, used for putting patterns on objects
+    pub fn with_uv(mut self, u: f64, v: f64) -> Self {
+        self.u = u;
+        self.v = v;
+        self
+    }
+
+    /// Sets the ray in the space of the object that was hit, for hits that are moved out of a transformed object
+    pub fn with_object_ray(mut self, object_ray: Ray) -> Self {
+        self.object_ray = Some(object_ray);
+        self
+    }
+
+    /// returns the ray in the space of the object that was hit. It is the ray itself unless the object is transformed
+    pub fn object_ray(&self, r: &Ray) -> Ray {
+        self.object_ray.unwrap_or(*r)
+    }
+
     /// returns a refrence to the objects material
-    pub fn material(&self) -> &dyn Material {
+    pub fn material(&self) -> &'a dyn Material {
         self.material
     }
 
@@ -39,19 +106,40 @@ impl<'a> HitRecord<'a> {
         self.normal
     }
 
+    /// Flips the normal, so the inside of the object becomes the outside
+    pub fn flip_normal(mut self) -> Self {
+        self.normal = -self.normal;
+        self
+    }
+
+    /// returns the normal flipped to point against the ray, so it is on the same side as the ray came from
+    pub fn face_normal(&self, r: &Ray) -> Vec3 {
+        if r.direction().dot_product(&self.normal) > 0.0 {
+            -self.normal
+        } else {
+            self.normal
+        }
+    }
+
     /// returns a refrence to the distance between intersection and camera
     pub fn t(&self) -> f64 {
         self.t
     }
+
+    /// returns the surface coordinates (u, v) of the hit
+    pub fn uv(&self) -> (f64, f64) {
+        (self.u, self.v)
+    }
 }
 
 /// Impl Hitable for list of hitable objects
 impl Hitable for Vec<Box<dyn Hitable>> {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let mut temp_rec = None;
         let mut closest_so_far = t_max;
 
         for object in self.iter() {
+            stats::count_primitive_test();
             if let Some(hit) = object.hit(r, t_min, closest_so_far) {
                 closest_so_far = hit.t();
                 temp_rec = Some(hit);
@@ -60,4 +148,57 @@ impl Hitable for Vec<Box<dyn Hitable>> {
 
         temp_rec
     }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let mut objects = self.iter();
+        let first = objects.next()?.bounding_box()?;
+        objects.try_fold(first, |bbox, object| {
+            Some(bbox.surrounding(&object.bounding_box()?))
+        })
+    }
+}
+
+/// Lets many objects share the same geometry, for example when instancing with transforms
+impl<H: Hitable + ?Sized> Hitable for Arc<H> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.as_ref().hit(r, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.as_ref().bounding_box()
+    }
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        self.as_ref().pdf_value(origin, direction)
+    }
+
+    fn random_direction(&self, origin: Point3) -> Vec3 {
+        self.as_ref().random_direction(origin)
+    }
+
+    fn hit_intervals(&self, r: &Ray) -> Vec<(HitRecord<'_>, HitRecord<'_>)> {
+        self.as_ref().hit_intervals(r)
+    }
+}
+
+impl<H: Hitable + ?Sized> Hitable for Box<H> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.as_ref().hit(r, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.as_ref().bounding_box()
+    }
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        self.as_ref().pdf_value(origin, direction)
+    }
+
+    fn random_direction(&self, origin: Point3) -> Vec3 {
+        self.as_ref().random_direction(origin)
+    }
+
+    fn hit_intervals(&self, r: &Ray) -> Vec<(HitRecord<'_>, HitRecord<'_>)> {
+        self.as_ref().hit_intervals(r)
+    }
 }
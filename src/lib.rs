@@ -0,0 +1,65 @@
+//! A path tracer. Worlds are built from hitables with materials, and rendered by a camera.
+//! The quickest way to get an image is render(), which renders a world with a camera on all the cores
+
+#[macro_use]
+extern crate lazy_static;
+
+mod aabb;
+mod bokeh;
+mod bvh;
+mod camera;
+mod cancel;
+mod checkpoint;
+mod csg;
+mod density;
+mod distributed;
+mod error;
+mod film;
+mod heightfield;
+mod hitable;
+mod material;
+mod medium;
+mod pgm;
+mod planar;
+mod png;
+mod point3;
+mod preview;
+mod progress;
+mod projection;
+mod random;
+mod ray;
+mod region;
+mod render;
+mod scene;
+mod sdf;
+mod shapes;
+mod spectrum;
+mod sphere;
+mod stats;
+mod stereo;
+mod transform;
+
+pub use aabb::Aabb;
+pub use bokeh::{ApertureMask, ApertureShape, Bokeh};
+pub use bvh::Bvh;
+pub use camera::{Camera, CameraBuilder};
+pub use cancel::CancelToken;
+pub use checkpoint::{Checkpoint, CheckpointSettings};
+pub use distributed::{Coordinator, Worker};
+pub use error::{Error, Result};
+pub use film::{Film, Filter};
+pub use hitable::{HitRecord, Hitable};
+pub use material::{Dielectric, DiffuseLight, Lambertian, Material, Metal};
+pub use medium::Fog;
+pub use point3::{Color, Point3, Vec3};
+pub use preview::PreviewServer;
+pub use progress::{Progress, ProgressBar, ProgressSink, Quiet};
+pub use projection::Projection;
+pub use ray::Ray;
+pub use region::{PreviousRender, Region};
+pub use render::{render, Image};
+pub use scene::Scene;
+pub use spectrum::{SampledSpectrum, SampledWavelengths};
+pub use sphere::Sphere;
+pub use stats::RenderStats;
+pub use stereo::{Eye, Stereo, StereoLayout};
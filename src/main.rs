@@ -1,132 +1,414 @@
+//! The command line of the raytracer binary
+
 use std::io::BufWriter;
-use std::{fs, sync::Arc};
+use std::process::{Child, Command, Stdio};
+use std::{fs, net::SocketAddr, sync::Arc, sync::OnceLock, time::Duration};
 
 #[macro_use]
 extern crate lazy_static;
 
-mod camera;
-mod hitable;
-mod material;
-mod point3;
-mod ray;
-mod sphere;
-
-use material::Materials;
-use point3::{Color, Point3, Vec3};
-use ray::Ray;
-use sphere::Sphere;
-
-use crate::camera::Camera;
-use crate::hitable::Hitable;
-use crate::material::{Dielectric, Lambertian, Metal};
+use raytracer::{
+    ApertureMask, ApertureShape, Bokeh, Bvh, Camera, CancelToken, Checkpoint, CheckpointSettings,
+    Color, Coordinator, Error, Filter, Fog, PreviewServer, PreviousRender, ProgressBar,
+    ProgressSink, Projection, Quiet, Region, RenderStats, Scene, Stereo, StereoLayout, Worker,
+};
 
+/// Renders the scene chosen on the command line, or serves a coordinator as a worker
 fn main() {
-    // Create the new file for the image to be written too
-    let mut file1 = BufWriter::new(
-        fs::File::create("Images/finalNormalRender.ppm").expect("couldn't create file"),
-    );
+    //3D camera
+    lazy_static! {
+        // Cancelled by Ctrl-C, which stops the render and writes the image from the samples taken so far
+        static ref CANCEL: CancelToken = CancelToken::new();
 
-    let mut file2 = BufWriter::new(
-        fs::File::create("Images/finalRenderThreads.ppm").expect("couldn't create file"),
-    );
+        static ref CAMERA: Camera = {
+            let mut cam = selected_scene().camera();
+            // Passing --focus <distance> overrides where the scene camera focuses
+            if let Some(distance) = arg_value("--focus") {
+                cam = cam.focus_distance(distance.parse().expect("focus distance should be a number"));
+            }
 
-    //world
-    let mut world: Vec<Box<dyn Hitable>> = Vec::new(); //HitableList::default();
+            // Passing --projection <name> changes how pixels are mapped to rays, like "fisheye:180"
+            if let Some(name) = arg_value("--projection") {
+                cam = cam.projection(Projection::from_name(&name).expect("unknown projection"));
+            }
 
-    // Add hitable objects to the world
-    for a in -11..11 {
-        for b in -11..11 {
-            let center = Point3::from_xyz(
-                a as f64 + 0.9 * rand::random::<f64>(),
-                0.2,
-                b as f64 + 0.9 * rand::random::<f64>(),
+            // Passing --stereo <layout> renders both eyes into one image, either side-by-side or top-bottom.
+            // --interocular <distance> and --convergence <distance> set up the eyes
+            if let Some(layout) = arg_value("--stereo") {
+                let layout = StereoLayout::from_name(&layout).expect("unknown stereo layout");
+                let interocular = arg_value("--interocular").map_or(0.065, |distance| {
+                    distance.parse().expect("interocular distance should be a number")
+                });
+                let mut stereo = Stereo::new(interocular, layout);
+                if let Some(distance) = arg_value("--convergence") {
+                    stereo = stereo.with_convergence(
+                        distance.parse().expect("convergence distance should be a number"),
+                    );
+                }
+                cam = cam.stereo(Some(stereo));
+            }
+
+            // Passing --aperture-shape <shape> changes the shape of out of focus highlights, like "polygon:6:15"
+            // for 6 blades rotated 15 degrees, or "mask:<path>" for a pgm image of the aperture.
+            // --squeeze, --cats-eye and --chromatic-aberration add the other lens effects
+            let shape = match arg_value("--aperture-shape") {
+                Some(name) => match name.strip_prefix("mask:") {
+                    Some(path) => ApertureShape::Mask(
+                        exit_on_io_error(ApertureMask::load(path), "couldn't load the aperture mask"),
+                    ),
+                    None => ApertureShape::from_name(&name).expect("unknown aperture shape"),
+                },
+                None => ApertureShape::Disk,
+            };
+            let number = |flag: &str, default: f64| {
+                arg_value(flag).map_or(default, |value| {
+                    value.parse().expect("lens settings should be numbers")
+                })
+            };
+            cam = cam.bokeh(
+                Bokeh::new(shape)
+                    .with_squeeze(number("--squeeze", 1.0))
+                    .with_cats_eye(number("--cats-eye", 0.0))
+                    .with_chromatic_aberration(number("--chromatic-aberration", 0.0)),
             );
-            let material = rand::random::<Materials>();
-
-            if (center - Point3::from_xyz(4, 0.2, 9)).length() > 0.9 {
-                match material {
-                    Materials::Rough(mat) => world.push(Box::new(
-                        Sphere::from_center_radius_material(center, 0.3, mat),
-                    )),
-                    Materials::Reflective(mat) => world.push(Box::new(
-                        Sphere::from_center_radius_material(center, 0.3, mat),
-                    )),
-                    Materials::Glass(mat) => {
-                        world.push(Box::new(Sphere::from_center_radius_material(
-                            center, 0.3, mat,
-                        )));
-                        if rand::random::<f64>() >= 0.5 {
-                            world.push(Box::new(Sphere::from_center_radius_material(
-                                center, -0.2, mat,
-                            )));
-                        }
-                    }
+
+            // Passing --filter <name> changes how samples are spread over the pixels, like "mitchell" or "gaussian:2"
+            if let Some(name) = arg_value("--filter") {
+                cam = cam.filter(Filter::from_name(&name).expect("unknown filter"));
+            }
+
+            // Passing --region x,y,width,height only renders that rectangle of the image.
+            // With --composite <ppm> the rectangle is pasted into a previous render of the whole image
+            if let Some(name) = arg_value("--region") {
+                let mut region = Region::from_name(&name).expect("region should be x,y,width,height");
+                if let Some(path) = arg_value("--composite") {
+                    region = region.composite_over(
+                        exit_on_io_error(PreviousRender::load(path), "couldn't load the previous render"),
+                    );
                 }
+                cam = cam.region(Some(region));
             }
+
+            // Passing --samples <n> overrides the samples pr pixel of the scene
+            if let Some(samples) = arg_value("--samples") {
+                cam = cam.samples(samples.parse().expect("samples should be a number"));
+            }
+
+            // Passing --checkpoint <path> saves the progress of the render every --checkpoint-interval <seconds>,
+            // 60 by default. --resume <path> continues a render from a checkpoint, and keeps saving to it.
+            // Resuming a finished render with a higher --samples adds more samples to it
+            let checkpoint_path = arg_value("--checkpoint").or(arg_value("--resume"));
+            if let Some(path) = checkpoint_path {
+                let interval = arg_value("--checkpoint-interval").map_or(60.0, |seconds| {
+                    seconds.parse().expect("checkpoint interval should be a number")
+                });
+                cam = cam.checkpoint(Some(
+                    CheckpointSettings::new(path, Duration::from_secs_f64(interval))
+                        .with_scene_seed(scene_seed()),
+                ));
+            }
+            cam = cam.resume(resumed_checkpoint());
+
+            // Passing --preview <address> shows the image in a browser at http://<address> while it renders
+            let sink = progress_sink();
+            let preview = arg_value("--preview").map(|addr| {
+                let server = exit_on_io_error(
+                    PreviewServer::start(addr, sink.clone()),
+                    "couldn't start the preview server",
+                );
+                if !quiet() {
+                    println!("preview at http://{}", server.addr());
+                }
+                server
+            });
+
+            exit_on_error(
+                cam.cancel_token(CANCEL.clone())
+                    .progress(match &preview {
+                        Some(server) => server.clone(),
+                        None => sink,
+                    })
+                    .preview(preview)
+                    // Passing --time-limit <seconds> stops the render after that long, with the samples taken so far
+                    .time_budget(arg_value("--time-limit").map(|seconds| {
+                        Duration::from_secs_f64(seconds.parse().expect("time limit should be a number"))
+                    }))
+                    // Passing --spectral renders with wavelengths, so the glass disperses light
+                    .spectral(args().iter().any(|arg| arg == "--spectral"))
+                    // Passing --fog <density> fills the scene with fog
+                    .fog(arg_value("--fog").map(|density| {
+                        exit_on_error(Fog::new(
+                            density.parse().expect("fog density should be a number"),
+                            Color::from_rgb(0.8, 0.8, 0.85),
+                        ))
+                    }))
+                    .build(),
+            )
+        };
+    }
+
+    // Passing --worker <address> renders passes for the coordinator at the address, instead of a render of its own.
+    // The coordinator sends the command line to render with
+    if let Some(addr) = arg_value("--worker") {
+        let worker = exit_on_io_error(
+            Worker::connect(&addr),
+            "couldn't connect to the coordinator",
+        );
+        let mut worker_args = vec![std::env::args().next().unwrap_or_default()];
+        worker_args.extend_from_slice(worker.args());
+        ARGS.set(worker_args)
+            .expect("the command line is only set once");
+
+        let world = Bvh::new(exit_on_error(selected_scene().world(scene_seed())));
+        let seed = worker.seed();
+        let served = worker.serve(|pass| {
+            let (film, stats) = CAMERA.render_pass(&world, seed, pass)?;
+            Ok((film.data(), stats))
+        });
+        if let Err(err) = served {
+            eprintln!("stopped serving the coordinator: {}", err);
         }
+        return;
     }
 
-    // Add some bigger spheres in the center, aswell as a large sphere that acts as a ground
-    let ground_material = Lambertian::from_color(Color::from_rgb(0.5, 0.5, 0.5));
-    let center_material = Lambertian::from_color(Color::from_rgb(0.1, 0.2, 0.5));
-    let left_material = Dielectric::from_ir(1.5);
-    let right_material = Metal::from_color(Color::from_rgb(0.8, 0.6, 0.2), 1.0);
-
-    world.push(Box::new(Sphere::from_center_radius_material(
-        Point3::from_xyz(-4, 1, 0),
-        1,
-        center_material,
-    )));
-    world.push(Box::new(Sphere::from_center_radius_material(
-        Point3::from_xyz(0, 1, 0),
-        1,
-        left_material,
-    )));
-    world.push(Box::new(Sphere::from_center_radius_material(
-        Point3::from_xyz(0, 1, 0),
-        -0.7,
-        left_material,
-    )));
-    world.push(Box::new(Sphere::from_center_radius_material(
-        Point3::from_xyz(4, 1, 0),
-        1,
-        right_material,
-    )));
-    world.push(Box::new(Sphere::from_center_radius_material(
-        Point3::from_xyz(0, -1000, -1),
-        1000,
-        ground_material,
-    )));
+    // The first Ctrl-C stops the render and still writes the image, a second one quits right away
+    ctrlc::set_handler(|| {
+        if CANCEL.is_cancelled() {
+            std::process::exit(130);
+        }
+        eprintln!("\nStopping the render, press Ctrl-C again to quit without saving");
+        CANCEL.cancel();
+    })
+    .expect("couldn't set the Ctrl-C handler");
 
-    //3D camera
-    lazy_static! {
-        static ref CAMERA: Camera = {
-            let mut cam = Camera::default();
-            cam.set_img_dimensions(16.0 / 9.0, 600);
-            cam.set_camera_settings(
-                Point3::from_xyz(13, 2, 3),
-                Point3::from_xyz(0, 0, 0),
-                20.0,
-                10,
-                10,
-                0.6,
-                10.0,
-            );
-            cam
-        };
+    // the camera settings are checked before any workers are started or files are written
+    lazy_static::initialize(&CAMERA);
+
+    // Passing --coordinator <address> hands the passes of the render out to the workers that connect to the address,
+    // and --spawn-workers <n> starts n workers on this machine. --pass-timeout <seconds> is how long a worker gets
+    // to render a pass before its pass goes to another worker, and how long the render waits without any workers
+    if let Some(addr) = arg_value("--coordinator") {
+        let mut file = BufWriter::new(exit_on_error(
+            fs::File::create("Images/finalDistributedRender.ppm").map_err(Error::from),
+        ));
+
+        let mut coordinator = exit_on_io_error(
+            Coordinator::bind(&addr, worker_args()),
+            "couldn't listen for workers",
+        );
+        if let Some(seconds) = arg_value("--pass-timeout") {
+            coordinator = coordinator.with_pass_timeout(Duration::from_secs_f64(
+                seconds.parse().expect("pass timeout should be a number"),
+            ));
+        }
+        coordinator = coordinator.with_files(worker_files());
+        let addr = exit_on_io_error(
+            coordinator.local_addr(),
+            "couldn't get the coordinator address",
+        );
+        let workers = arg_value("--spawn-workers").map_or(0, |n| {
+            n.parse().expect("the amount of workers should be a number")
+        });
+        let mut children = spawn_workers(addr, workers);
+
+        if !quiet() {
+            println!("starting distributed render, workers connect to {}", addr);
+        }
+        let instant = std::time::Instant::now();
+        let stats = exit_on_error(CAMERA.render_distributed(coordinator, &mut file));
+        let time = instant.elapsed();
+        if !quiet() {
+            println!("Time taken: {:#?}", time);
+        }
+        report_stats(&stats);
+
+        // the workers quit when they are told the render is over
+        for child in &mut children {
+            let _ = child.wait();
+        }
+        return;
     }
 
-    // println!("starting normal render");
-    // let instant = std::time::Instant::now();
-    // CAMERA.render(&world, &mut file1);
-    // let time = instant.elapsed();
-    // println!("Time taken: {:#?}", time);
+    let world = Bvh::new(exit_on_error(selected_scene().world(scene_seed())));
 
-    println!("starting render with threads");
+    let mut file = BufWriter::new(exit_on_error(
+        fs::File::create("Images/finalRenderThreads.ppm").map_err(Error::from),
+    ));
+
+    if !quiet() {
+        println!("starting render with threads");
+    }
     let instant = std::time::Instant::now();
-    CAMERA.render_with_threads(Arc::new(world), &mut file2);
+    let stats = exit_on_error(CAMERA.render_with_threads(&world, &mut file));
     let time = instant.elapsed();
-    println!("Time taken: {:#?}", time);
-    // println!("Starting parralel render");
-    // camera.par_render(&Arc::new(world), &mut file);
+    if !quiet() {
+        println!("Time taken: {:#?}", time);
+    }
+    report_stats(&stats);
+}
+
+/// Reports a failed render and exits, since there is no image to write
+fn exit_on_error<T>(result: raytracer::Result<T>) -> T {
+    result.unwrap_or_else(|err| {
+        eprintln!("\nrender failed: {}", err);
+        std::process::exit(1);
+    })
+}
+
+/// Reports a failed file or network operation and exits, saying what was being done
+fn exit_on_io_error<T>(result: std::io::Result<T>, doing: &str) -> T {
+    exit_on_error(result.map_err(|err| {
+        Error::Io(std::io::Error::new(
+            err.kind(),
+            format!("{}: {}", doing, err),
+        ))
+    }))
+}
+
+/// Passing --stats prints the render stats, and --stats-json <path> writes them to a json file
+fn report_stats(stats: &RenderStats) {
+    if args().iter().any(|arg| arg == "--stats") {
+        println!("{}", stats.summary());
+    }
+    if let Some(path) = arg_value("--stats-json") {
+        exit_on_error(stats.write_json(path).map_err(Error::from));
+    }
+}
+
+/// Passing --quiet renders without a progress bar or any other output
+fn quiet() -> bool {
+    args().iter().any(|arg| arg == "--quiet")
+}
+
+/// Where the progress of the render is shown
+fn progress_sink() -> Arc<dyn ProgressSink> {
+    if quiet() {
+        Arc::new(Quiet)
+    } else {
+        Arc::new(ProgressBar::new())
+    }
+}
+
+// The command line a worker got from its coordinator, which is used instead of its own
+static ARGS: OnceLock<Vec<String>> = OnceLock::new();
+
+/// The command line of the render
+fn args() -> Vec<String> {
+    match ARGS.get() {
+        Some(args) => args.clone(),
+        None => std::env::args().collect(),
+    }
+}
+
+/// Returns the value following a flag on the command line, like the 5 in "--samples 5"
+fn arg_value(flag: &str) -> Option<String> {
+    let mut args = args().into_iter().skip_while(|arg| arg != flag);
+    args.next();
+    args.next()
+}
+
+/// The checkpoint passed with --resume. It is loaded once, and shared by the camera and the scene seed
+fn resumed_checkpoint() -> Option<Arc<Checkpoint>> {
+    static RESUMED: OnceLock<Option<Arc<Checkpoint>>> = OnceLock::new();
+    RESUMED
+        .get_or_init(|| {
+            arg_value("--resume")
+                .map(|path| Arc::new(exit_on_error(Checkpoint::load(path).map_err(Error::from))))
+        })
+        .clone()
+}
+
+/// The seed of the random parts of the scene, like the placement of the random spheres.
+/// Chosen with --scene-seed <seed>, or taken from the checkpoint of a resumed render, or at random.
+/// A resumed render with another --scene-seed is refused when the camera is built
+fn scene_seed() -> u64 {
+    static SCENE_SEED: OnceLock<u64> = OnceLock::new();
+    *SCENE_SEED.get_or_init(|| match arg_value("--scene-seed") {
+        Some(seed) => seed.parse().expect("scene seed should be a number"),
+        None => {
+            resumed_checkpoint().map_or_else(rand::random, |checkpoint| checkpoint.scene_seed())
+        }
+    })
+}
+
+// Flags that only matter to the coordinator, which are left out of the command line sent to the workers
+const COORDINATOR_FLAGS: [&str; 11] = [
+    "--coordinator",
+    "--spawn-workers",
+    "--pass-timeout",
+    "--checkpoint",
+    "--checkpoint-interval",
+    "--resume",
+    "--composite",
+    "--time-limit",
+    "--stats-json",
+    "--scene-seed",
+    "--preview",
+];
+const COORDINATOR_SWITCHES: [&str; 2] = ["--stats", "--quiet"];
+
+/// The command line the workers render with. They all get the same scene seed, so they build the same scene
+fn worker_args() -> Vec<String> {
+    let mut worker_args = Vec::new();
+    let mut args = args().into_iter().skip(1);
+    while let Some(arg) = args.next() {
+        if COORDINATOR_FLAGS.contains(&arg.as_str()) {
+            args.next();
+        } else if !COORDINATOR_SWITCHES.contains(&arg.as_str()) {
+            worker_args.push(arg);
+        }
+    }
+    worker_args.push("--scene-seed".to_string());
+    worker_args.push(scene_seed().to_string());
+    worker_args
+}
+
+/// The files the command line of the workers loads, with their contents, so workers on other machines
+/// don't need their own copies
+fn worker_files() -> Vec<(String, Vec<u8>)> {
+    let mask = arg_value("--aperture-shape")
+        .and_then(|name| name.strip_prefix("mask:").map(str::to_string));
+    [arg_value("--volume"), arg_value("--heightmap"), mask]
+        .into_iter()
+        .flatten()
+        .map(|path| {
+            let contents =
+                exit_on_io_error(fs::read(&path), "couldn't read a file for the workers");
+            (path, contents)
+        })
+        .collect()
+}
+
+/// Starts worker processes on this machine that connect to the coordinator
+fn spawn_workers(addr: SocketAddr, workers: usize) -> Vec<Child> {
+    let exe = exit_on_io_error(
+        std::env::current_exe(),
+        "couldn't find the raytracer executable",
+    );
+    (0..workers)
+        .map(|_| {
+            exit_on_io_error(
+                Command::new(&exe)
+                    .arg("--worker")
+                    .arg(addr.to_string())
+                    .stdout(Stdio::null())
+                    .spawn(),
+                "couldn't start a worker",
+            )
+        })
+        .collect()
+}
+
+/// The scene chosen with --scene <name>. Defaults to the random spheres
+fn selected_scene() -> Scene {
+    match arg_value("--scene") {
+        Some(name) => match Scene::from_name(&name).expect("unknown scene") {
+            // --volume <path> loads the voxel grid of the volumes scene from a file
+            Scene::Volumes(_) => Scene::Volumes(arg_value("--volume")),
+            // --heightmap <path> loads the terrain of the terrain scene from an image
+            Scene::Terrain(_) => Scene::Terrain(arg_value("--heightmap")),
+            scene => scene,
+        },
+        None => Scene::RandomSpheres,
+    }
 }
@@ -1,11 +1,13 @@
 use rand::{
     distributions::{Distribution, Standard},
-    thread_rng, Rng,
+    Rng,
 };
+use std::f64::consts::PI;
 
 use crate::{
     hitable::HitRecord,
     point3::{Color, Vec3},
+    random::{self, RenderRng},
     ray::Ray,
 };
 
@@ -19,17 +21,13 @@ impl Distribution<Materials> for Standard {
     fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Materials {
         match rng.gen_range(0..=2) {
             0 => Materials::Rough(Lambertian::from_color(Color::from_rgb(
-                rand::random::<f64>(),
-                rand::random::<f64>(),
-                rand::random::<f64>(),
+                rng.gen::<f64>(),
+                rng.gen::<f64>(),
+                rng.gen::<f64>(),
             ))),
             1 => Materials::Reflective(Metal::from_color(
-                Color::from_rgb(
-                    rand::random::<f64>(),
-                    rand::random::<f64>(),
-                    rand::random::<f64>(),
-                ),
-                rand::random::<f64>() / 2.0,
+                Color::from_rgb(rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>()),
+                rng.gen::<f64>() / 2.0,
             )),
             _ => Materials::Glass(Dielectric::from_ir(1.5)),
         }
@@ -38,6 +36,45 @@ impl Distribution<Materials> for Standard {
 
 pub trait Material: Sync + Send {
     fn scatter(&self, r: &Ray, rec: &HitRecord) -> Option<(Ray, Color)>;
+
+    /// The light the material itself gives off at the hit. Most materials dont emit any light
+    fn emitted(&self, _r: &Ray, _rec: &HitRecord) -> Color {
+        Color::new()
+    }
+
+    /// Whether the direction of scattered rays depends on the wavelength of the ray.
+    /// In spectral mode only the hero wavelength can follow a path through such a material
+    fn is_dispersive(&self) -> bool {
+        false
+    }
+
+    /// The probability density (pr solid angle) that scatter() sends the ray in the scattered direction.
+    /// Diffuse materials return it, so the camera can send some of their rays towards the lights instead.
+    /// Materials that scatter in a few fixed directions, like glass and metal, return 0
+    fn scattering_pdf(&self, _r: &Ray, _rec: &HitRecord, _scattered: &Ray) -> f64 {
+        0.0
+    }
+}
+
+/// The pdf of cosine weighted scattering around the normal, which is what diffuse materials do
+fn cosine_pdf(r: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+    let cos_theta = rec
+        .face_normal(r)
+        .dot_product(&scattered.direction().unit_vec());
+    (cos_theta / PI).max(0.0)
+}
+
+/// Picks a cosine weighted direction around the normal, on the side the ray came from
+fn cosine_direction(r: &Ray, rec: &HitRecord) -> Vec3 {
+    let normal = rec.face_normal(r);
+    let scatter_direction = normal + Vec3::random_unit_vec();
+
+    // makes sure the scatter direction doesnt potentially mess stuff up
+    if scatter_direction.near_zero() {
+        normal
+    } else {
+        scatter_direction
+    }
 }
 
 fn reflect(v_in: &Vec3, normal: &Vec3) -> Vec3 {
@@ -45,7 +82,7 @@ fn reflect(v_in: &Vec3, normal: &Vec3) -> Vec3 {
 }
 
 /// Material for diffuse objects. Simply scatters the light
-#[derive(Clone, Copy)]
+#[derive(Default, Clone, Copy)]
 pub struct Lambertian {
     albedo: Color,
 }
@@ -63,20 +100,75 @@ impl Lambertian {
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, _r: &Ray, rec: &HitRecord) -> Option<(Ray, Color)> {
-        let mut scatter_direction = rec.normal() + Vec3::random_unit_vec();
+    fn scatter(&self, r: &Ray, rec: &HitRecord) -> Option<(Ray, Color)> {
+        Some((Ray::new(rec.point(), cosine_direction(r, rec)), self.albedo))
+    }
 
-        // makes sure the scatter direction doesnt potentially mess stuff up
-        if scatter_direction.near_zero() {
-            scatter_direction = rec.normal();
-        }
+    fn scattering_pdf(&self, r: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        cosine_pdf(r, rec, scattered)
+    }
+}
+
+/// Diffuse material with a checkerboard pattern, laid out in the surface coordinates of the object
+#[derive(Clone, Copy)]
+pub struct Checker {
+    even: Color,
+    odd: Color,
+    scale: f64, // The amount of squares along each surface coordinate
+}
 
-        Some((Ray::new(rec.point(), scatter_direction), self.albedo))
+impl Checker {
+    pub fn from_colors(even: Color, odd: Color, scale: f64) -> Self {
+        Self { even, odd, scale }
     }
 }
 
-/// Material for metal-like objects that reflect light
+impl Material for Checker {
+    fn scatter(&self, r: &Ray, rec: &HitRecord) -> Option<(Ray, Color)> {
+        let (u, v) = rec.uv();
+        let square = (u * self.scale).floor() as i64 + (v * self.scale).floor() as i64;
+        let albedo = if square.rem_euclid(2) == 0 {
+            self.even
+        } else {
+            self.odd
+        };
+
+        Some((Ray::new(rec.point(), cosine_direction(r, rec)), albedo))
+    }
+
+    fn scattering_pdf(&self, r: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        cosine_pdf(r, rec, scattered)
+    }
+}
+
+/// Material for lamps and other glowing surfaces. Light is only given off on the side the normal points to
 #[derive(Clone, Copy)]
+pub struct DiffuseLight {
+    emission: Color,
+}
+
+impl DiffuseLight {
+    pub fn from_color(emission: Color) -> Self {
+        Self { emission }
+    }
+}
+
+impl Material for DiffuseLight {
+    fn scatter(&self, _r: &Ray, _rec: &HitRecord) -> Option<(Ray, Color)> {
+        None
+    }
+
+    fn emitted(&self, r: &Ray, rec: &HitRecord) -> Color {
+        if r.direction().dot_product(&rec.normal()) < 0.0 {
+            self.emission
+        } else {
+            Color::new()
+        }
+    }
+}
+
+/// Material for metal-like objects that reflect light
+#[derive(Default, Clone, Copy)]
 pub struct Metal {
     albedo: Color,
     fuzz: f64,
@@ -97,9 +189,10 @@ impl Metal {
 
 impl Material for Metal {
     fn scatter(&self, r: &Ray, rec: &HitRecord) -> Option<(Ray, Color)> {
-        let reflected = reflect(&r.direction().unit_vec(), &rec.normal());
+        let normal = rec.face_normal(r);
+        let reflected = reflect(&r.direction().unit_vec(), &normal);
 
-        if reflected.dot_product(&rec.normal()) > 0.0 {
+        if reflected.dot_product(&normal) > 0.0 {
             Some((
                 Ray::new(
                     rec.point(),
@@ -113,69 +206,325 @@ impl Material for Metal {
     }
 }
 
+/// Phase function for participating media, that scatters light equally in all directions
+#[derive(Clone, Copy)]
+pub struct Isotropic {
+    albedo: Color,
+}
+
+impl Isotropic {
+    pub fn from_color(albedo: Color) -> Self {
+        Self { albedo }
+    }
+}
+
+impl Material for Isotropic {
+    fn scatter(&self, _r: &Ray, rec: &HitRecord) -> Option<(Ray, Color)> {
+        Some((Ray::new(rec.point(), Vec3::random_unit_vec()), self.albedo))
+    }
+}
+
+/// Phase function for participating media, that prefers scattering light forwards (g > 0) or backwards (g < 0)
+#[derive(Clone, Copy)]
+pub struct HenyeyGreenstein {
+    albedo: Color,
+    g: f64, // The asymmetry parameter, between -1 and 1. 0 is the same as isotropic
+}
+
+impl HenyeyGreenstein {
+    pub fn from_color(albedo: Color, g: f64) -> Self {
+        Self {
+            albedo,
+            g: g.clamp(-0.99, 0.99),
+        }
+    }
+}
+
+impl Material for HenyeyGreenstein {
+    fn scatter(&self, r: &Ray, rec: &HitRecord) -> Option<(Ray, Color)> {
+        let mut rng = random::rng();
+        let u1: f64 = rng.gen();
+        let u2: f64 = rng.gen();
+
+        // sample the cosine of the angle between the old and the new direction
+        let cos_theta = if self.g.abs() < 1e-3 {
+            1.0 - 2.0 * u1
+        } else {
+            let square = (1.0 - self.g.powi(2)) / (1.0 - self.g + 2.0 * self.g * u1);
+            (1.0 + self.g.powi(2) - square.powi(2)) / (2.0 * self.g)
+        };
+        let sin_theta = (1.0 - cos_theta.powi(2)).max(0.0).sqrt();
+        let phi = 2.0 * PI * u2;
+
+        let forward = r.direction().unit_vec();
+        let (tangent, bitangent) = forward.orthonormal_basis();
+        let direction = tangent * (sin_theta * phi.cos())
+            + bitangent * (sin_theta * phi.sin())
+            + forward * cos_theta;
+
+        Some((Ray::new(rec.point(), direction), self.albedo))
+    }
+}
+
+/// The fresnel term a dielectric uses to decide between reflecting and refracting a ray
+#[derive(Clone, Copy)]
+pub enum Fresnel {
+    Schlick, // Schlicks approximation. Cheap and close enough for most glass
+    Exact,   // The full fresnel equations for unpolarised light
+}
+
+/// The wavelength (in nanometers) used for the index of refraction, when rays don't carry a wavelength.
+/// This is the sodium D-line, which is what refractive indices are usually listed at
+const DEFAULT_WAVELENGTH: f64 = 589.3;
+
+/// Models for how the index of refraction of a material depends on the wavelength of the light
+#[derive(Clone, Copy)]
+pub enum RefractiveIndex {
+    Constant(f64),
+    Cauchy { a: f64, b: f64 }, // n = a + b / λ², with λ in micrometers
+    Sellmeier { b: [f64; 3], c: [f64; 3] }, // n² = 1 + Σ b * λ² / (λ² - c), with λ in micrometers
+}
+
+impl RefractiveIndex {
+    /// Borosilicate crown glass, the most common optical glass
+    pub const BK7: RefractiveIndex = RefractiveIndex::Sellmeier {
+        b: [1.03961212, 0.231792344, 1.01046945],
+        c: [0.00600069867, 0.0200179144, 103.560653],
+    };
+
+    /// Diamond, which has very strong dispersion
+    pub const DIAMOND: RefractiveIndex = RefractiveIndex::Sellmeier {
+        b: [0.3306, 4.3356, 0.0],
+        c: [0.030625, 0.011236, 0.0],
+    };
+
+    /// Returns the index of refraction at a wavelength given in nanometers
+    pub fn at(&self, wavelength: f64) -> f64 {
+        let micrometers = wavelength / 1000.0;
+        let squared = micrometers.powi(2);
+
+        match *self {
+            RefractiveIndex::Constant(n) => n,
+            RefractiveIndex::Cauchy { a, b } => a + b / squared,
+            RefractiveIndex::Sellmeier { b, c } => {
+                let sum: f64 = b
+                    .iter()
+                    .zip(c.iter())
+                    .map(|(b, c)| b * squared / (squared - c))
+                    .sum();
+                (1.0 + sum).sqrt()
+            }
+        }
+    }
+
+    /// Whether the index of refraction changes with the wavelength
+    pub fn is_dispersive(&self) -> bool {
+        !matches!(self, RefractiveIndex::Constant(_))
+    }
+}
+
 /// Material for glass-like objects, where light can enter the object with an index of refraction
 #[derive(Clone, Copy)]
 pub struct Dielectric {
-    index_of_refraction: f64,
+    index_of_refraction: RefractiveIndex,
+    fresnel: Fresnel,
+    absorption: Color, // How much of each color channel is absorbed pr unit of distance inside the object
+    roughness: f64,    // The roughness of the surface. 0 is perfectly smooth glass
 }
 
 impl Dielectric {
     /// Returns a glasslike material from an index of refraction
     pub fn from_ir(index_of_refraction: f64) -> Self {
+        Dielectric::from_refractive_index(RefractiveIndex::Constant(index_of_refraction))
+    }
+
+    /// Returns a dispersive glasslike material following Cauchy's equation: n = a + b / λ², with λ in micrometers
+    pub fn from_cauchy(a: f64, b: f64) -> Self {
+        Dielectric::from_refractive_index(RefractiveIndex::Cauchy { a, b })
+    }
+
+    /// Returns a dispersive glasslike material following the Sellmeier equation: n² = 1 + Σ b * λ² / (λ² - c),
+    /// with λ in micrometers
+    pub fn from_sellmeier(b: [f64; 3], c: [f64; 3]) -> Self {
+        Dielectric::from_refractive_index(RefractiveIndex::Sellmeier { b, c })
+    }
+
+    /// Returns a glasslike material from any model of the index of refraction
+    pub fn from_refractive_index(index_of_refraction: RefractiveIndex) -> Self {
         Dielectric {
             index_of_refraction,
+            fresnel: Fresnel::Schlick,
+            absorption: Color::new(),
+            roughness: 0.0,
         }
     }
+
+    /// Sets which fresnel term is used for deciding between reflection and refraction
+    pub fn with_fresnel(mut self, fresnel: Fresnel) -> Self {
+        self.fresnel = fresnel;
+        self
+    }
+
+    /// Makes the glass tinted. Light travelling through the object is absorbed following Beer-Lambert's law,
+    /// so each color channel is scaled by e^(-absorption * distance)
+    pub fn with_absorption(mut self, absorption: Color) -> Self {
+        self.absorption = absorption;
+        self
+    }
+
+    /// Makes the glass rough (frosted). The roughness is the alpha of a GGX microfacet distribution,
+    /// where 0 is perfectly smooth and 1 is very rough
+    pub fn with_roughness(mut self, roughness: f64) -> Self {
+        self.roughness = roughness.clamp(0.0, 1.0);
+        self
+    }
 }
 
 impl Material for Dielectric {
     fn scatter(&self, r: &Ray, rec: &HitRecord) -> Option<(Ray, Color)> {
-        let attenuation = Color::from_rgb(1, 1, 1);
+        let mut rng = random::rng();
+        let unit_direction = r.direction().unit_vec();
+
+        let index_of_refraction = self
+            .index_of_refraction
+            .at(r.wavelength().unwrap_or(DEFAULT_WAVELENGTH));
 
         // calculates different values depending on whether or not the ray hits a frontface or a backface
-        let (normal, refraction_ratio) = if r.direction().dot_product(&rec.normal()) > 0.0 {
-            (-rec.normal(), self.index_of_refraction)
+        let front_face = unit_direction.dot_product(&rec.normal()) < 0.0;
+        let (normal, refraction_ratio) = if front_face {
+            (rec.normal(), 1.0 / index_of_refraction)
         } else {
-            (rec.normal(), 1.0 / self.index_of_refraction)
+            (-rec.normal(), index_of_refraction)
         };
 
-        let scattered = if let Some(refracted) = Dielectric::refract(&r, &normal, refraction_ratio)
-        {
-            Ray::new(rec.point(), refracted)
+        // if the ray hits a backface, it has travelled through the object and some of the light is absorbed
+        let mut attenuation = if front_face {
+            Color::from_rgb(1, 1, 1)
         } else {
-            let reflected = reflect(&r.direction(), &rec.normal());
-            Ray::new(rec.point(), reflected)
+            self.transmittance(rec.t() * r.direction().length())
         };
 
-        Some((scattered, attenuation))
+        // rough glass scatters around a microfacet normal instead of the geometric normal
+        let microfacet_normal = if self.roughness > 0.0 {
+            sample_ggx_normal(&normal, self.roughness, &mut rng)
+        } else {
+            normal
+        };
+
+        let cos_theta = microfacet_normal
+            .dot_product(&-unit_direction)
+            .clamp(0.0, 1.0);
+        let reflectance = self.reflectance(cos_theta, refraction_ratio);
+
+        let (direction, refracted) = match Dielectric::refract(
+            &unit_direction,
+            &microfacet_normal,
+            cos_theta,
+            refraction_ratio,
+        ) {
+            Some(refracted) if rng.gen::<f64>() >= reflectance => (refracted, true),
+            _ => (reflect(&unit_direction, &microfacet_normal), false),
+        };
+
+        if self.roughness > 0.0 {
+            let cos_out = direction.dot_product(&normal);
+            let cos_in = -unit_direction.dot_product(&normal);
+            let cos_m = microfacet_normal.dot_product(&normal);
+
+            // A microfacet can send the ray to the wrong side of the surface. That light is lost
+            if cos_in <= 0.0 || cos_m <= 0.0 || (cos_out < 0.0) != refracted {
+                return None;
+            }
+
+            // weight = G(i, o, m) * |i.m| / (|i.n| * |m.n|), when m is sampled proportional to D(m) * |m.n|
+            let weight = smith_g1(cos_in, self.roughness)
+                * smith_g1(cos_out.abs(), self.roughness)
+                * cos_theta
+                / (cos_in * cos_m);
+            attenuation = attenuation * weight;
+        }
+
+        Some((Ray::new(rec.point(), direction), attenuation))
+    }
+
+    fn is_dispersive(&self) -> bool {
+        self.index_of_refraction.is_dispersive()
     }
 }
 
 impl Dielectric {
-    /// Function that calculates the refraction of the ray, IF the ray refracts.
-    /// Else function returns None
-    fn refract(r: &Ray, n: &Vec3, refraction_ratio: f64) -> Option<Vec3> {
-        let cos_theta = n.dot_product(&-r.direction().unit_vec()).min(1.0);
+    /// Function that calculates the refraction of the ray, IF the ray can refract.
+    /// Else function returns None (total internal reflection)
+    fn refract(
+        unit_direction: &Vec3,
+        n: &Vec3,
+        cos_theta: f64,
+        refraction_ratio: f64,
+    ) -> Option<Vec3> {
         let sin_theta = (1.0 - cos_theta.powi(2)).sqrt();
 
-        let uv = r.direction().unit_vec();
-
-        // checks if the ball can refract, and returns None if it cant
-        if sin_theta * refraction_ratio > 1.0
-            || Dielectric::reflectance(cos_theta, refraction_ratio)
-        {
-            return None;
+        if sin_theta * refraction_ratio > 1.0 {
+            None
         } else {
-            let r_out_perp = (uv + *n * cos_theta) * refraction_ratio;
+            let r_out_perp = (*unit_direction + *n * cos_theta) * refraction_ratio;
             let r_out_par = *n * -(1.0 - r_out_perp.length_squared()).abs().sqrt();
-            return Some(r_out_perp + r_out_par);
+            Some(r_out_perp + r_out_par)
+        }
+    }
+
+    /// The fraction of light that is reflected at the given angle
+    fn reflectance(&self, cosine: f64, refraction_ratio: f64) -> f64 {
+        match self.fresnel {
+            Fresnel::Schlick => {
+                let r0 = ((1.0 - refraction_ratio) / (1.0 + refraction_ratio)).powi(2);
+                r0 + (1.0 - r0) * (1.0 - cosine).powi(5)
+            }
+            Fresnel::Exact => {
+                let sin_t_squared = refraction_ratio.powi(2) * (1.0 - cosine.powi(2));
+                if sin_t_squared >= 1.0 {
+                    return 1.0;
+                }
+                let cos_t = (1.0 - sin_t_squared).sqrt();
+
+                // reflectance for s- and p-polarised light, divided through by the index of the far side
+                let r_s = (refraction_ratio * cosine - cos_t) / (refraction_ratio * cosine + cos_t);
+                let r_p = (cosine - refraction_ratio * cos_t) / (cosine + refraction_ratio * cos_t);
+                (r_s.powi(2) + r_p.powi(2)) / 2.0
+            }
         }
     }
 
-    /// uses Schlicks approximation to figure out whether or not an object should reflect
-    fn reflectance(cosine: f64, refraction_ratio: f64) -> bool {
-        let mut r0 = (1.0 - refraction_ratio) / (1.0 + refraction_ratio);
-        r0 = r0.powi(2);
-        return r0 + (1.0 - r0) * (1.0 - cosine).powi(5) > thread_rng().gen();
+    /// How much light survives after travelling the given distance inside the object
+    fn transmittance(&self, distance: f64) -> Color {
+        Color::from_rgb(
+            (-self.absorption.r() * distance).exp(),
+            (-self.absorption.g() * distance).exp(),
+            (-self.absorption.b() * distance).exp(),
+        )
+    }
+}
+
+/// Samples a microfacet normal around n from the GGX distribution, proportional to D(m) * |m.n|
+fn sample_ggx_normal(n: &Vec3, alpha: f64, rng: &mut RenderRng) -> Vec3 {
+    let u1: f64 = rng.gen();
+    let u2: f64 = rng.gen();
+
+    let tan_theta_squared = alpha.powi(2) * u1 / (1.0 - u1);
+    let cos_theta = 1.0 / (1.0 + tan_theta_squared).sqrt();
+    let sin_theta = (1.0 - cos_theta.powi(2)).max(0.0).sqrt();
+    let phi = 2.0 * PI * u2;
+
+    let (tangent, bitangent) = n.orthonormal_basis();
+
+    (tangent * (sin_theta * phi.cos()) + bitangent * (sin_theta * phi.sin()) + *n * cos_theta)
+        .unit_vec()
+}
+
+/// Smiths shadowing-masking term for the GGX distribution
+fn smith_g1(cosine: f64, alpha: f64) -> f64 {
+    if cosine <= 0.0 {
+        return 0.0;
     }
+    let tan_theta_squared = (1.0 - cosine.powi(2)) / cosine.powi(2);
+    2.0 / (1.0 + (1.0 + alpha.powi(2) * tan_theta_squared).sqrt())
 }
@@ -0,0 +1,286 @@
+use rand::Rng;
+
+use crate::aabb::Aabb;
+use crate::density::{sample_free_flight, DensityField};
+use crate::error::{self, Error};
+use crate::hitable::{HitRecord, Hitable};
+use crate::material::Material;
+use crate::point3::{Color, Vec3};
+use crate::random;
+use crate::ray::Ray;
+
+/// A volume of smoke, fog or mist with the same density everywhere inside a boundary object.
+/// Rays that enter the volume travel a random distance, and then scatter following the phase function
+pub struct ConstantMedium<H: Hitable, M: Material> {
+    boundary: H,
+    neg_inv_density: f64,
+    phase_function: M,
+}
+
+impl<H: Hitable, M: Material> ConstantMedium<H, M> {
+    /// Fills the boundary object with a medium of the given density. The boundary has to be a closed object,
+    /// and the phase function is the material that decides how light scatters inside the medium.
+    /// Fails if the density is negative or NaN
+    pub fn new(boundary: H, density: f64, phase_function: M) -> error::Result<Self> {
+        if density.is_nan() || density < 0.0 {
+            return Err(Error::InvalidSettings(format!(
+                "the density of a medium can't be negative, got {}",
+                density
+            )));
+        }
+
+        Ok(Self {
+            boundary,
+            neg_inv_density: -1.0 / density,
+            phase_function,
+        })
+    }
+}
+
+impl<H: Hitable, M: Material> Hitable for ConstantMedium<H, M> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let (t_enter, t_exit) = segment_inside(&self.boundary, r, t_min, t_max)?;
+
+        let ray_length = r.direction().length();
+        let distance_inside = (t_exit - t_enter) * ray_length;
+        let hit_distance = self.neg_inv_density * random::rng().gen::<f64>().ln();
+
+        if hit_distance > distance_inside {
+            return None;
+        }
+
+        // The normal is meaningless inside a volume, so it is set to something arbitrary
+        let t = t_enter + hit_distance / ray_length;
+        Some(HitRecord::new(
+            r.at(t),
+            t,
+            Vec3::from_xyz(1, 0, 0),
+            &self.phase_function,
+        ))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.boundary.bounding_box()
+    }
+}
+
+/// A volume where the density changes from point to point, like clouds or smoke plumes.
+/// Collisions are found with delta tracking, and at each collision the light scatters following the phase function
+pub struct HeterogeneousMedium<H: Hitable, D: DensityField, M: Material> {
+    boundary: H,
+    density: D,
+    phase_function: M,
+    emission: Color, // Light given off pr unit of density, relative to the max density
+}
+
+impl<H: Hitable, D: DensityField, M: Material> HeterogeneousMedium<H, D, M> {
+    /// Fills the boundary object with a medium following the density field. The boundary has to be a closed object
+    pub fn new(boundary: H, density: D, phase_function: M) -> Self {
+        Self {
+            boundary,
+            density,
+            phase_function,
+            emission: Color::new(),
+        }
+    }
+
+    /// Makes the medium glow. Denser parts of the medium give off more light
+    pub fn with_emission(mut self, emission: Color) -> Self {
+        self.emission = emission;
+        self
+    }
+}
+
+impl<H: Hitable, D: DensityField, M: Material> Hitable for HeterogeneousMedium<H, D, M> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let (t_enter, t_exit) = segment_inside(&self.boundary, r, t_min, t_max)?;
+
+        let majorant = self.density.max_density();
+        if majorant <= 0.0 {
+            return None;
+        }
+
+        // delta tracking: step through the medium as if it had the max density everywhere, and accept each
+        // tentative collision with the probability density / majorant. The rejected ones are null collisions
+        let mut rng = random::rng();
+        let ray_length = r.direction().length();
+        let mut t = t_enter;
+        loop {
+            t += sample_free_flight(majorant, &mut rng) / ray_length;
+            if t >= t_exit {
+                return None;
+            }
+
+            let point = r.at(t);
+            if rng.gen::<f64>() * majorant < self.density.density(point) {
+                return Some(HitRecord::new(point, t, Vec3::from_xyz(1, 0, 0), self));
+            }
+        }
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.boundary.bounding_box()
+    }
+}
+
+impl<H: Hitable, D: DensityField, M: Material> Material for HeterogeneousMedium<H, D, M> {
+    fn scatter(&self, r: &Ray, rec: &HitRecord) -> Option<(Ray, Color)> {
+        self.phase_function.scatter(r, rec)
+    }
+
+    fn emitted(&self, r: &Ray, rec: &HitRecord) -> Color {
+        // the density is in the space of the medium, which isn't world space when the medium is transformed
+        let point = rec.object_ray(r).at(rec.t());
+        self.emission * (self.density.density(point) / self.density.max_density())
+    }
+}
+
+/// A glowing volume that absorbs light but doesn't scatter it, like fire or a hot gas.
+/// Rays pass straight through it, and the transmittance and glow along the way are estimated with ratio tracking.
+/// Objects inside the volume are not dimmed by it
+pub struct EmissiveMedium<H: Hitable, D: DensityField> {
+    boundary: H,
+    density: D,
+    emission: Color, // Light given off pr unit of density
+}
+
+impl<H: Hitable, D: DensityField> EmissiveMedium<H, D> {
+    /// Fills the boundary object with a glowing medium. The boundary has to be a closed object
+    pub fn new(boundary: H, density: D, emission: Color) -> Self {
+        Self {
+            boundary,
+            density,
+            emission,
+        }
+    }
+
+    /// Ratio tracking from t_enter to t_exit along the ray. Returns the estimated transmittance,
+    /// and the estimated light emitted towards the ray origin
+    fn ratio_tracking(&self, r: &Ray, t_enter: f64, t_exit: f64) -> (f64, Color) {
+        let majorant = self.density.max_density();
+        if majorant <= 0.0 {
+            return (1.0, Color::new());
+        }
+
+        let mut rng = random::rng();
+        let ray_length = r.direction().length();
+        let mut transmittance = 1.0;
+        let mut emitted = Color::new();
+        let mut t = t_enter;
+        loop {
+            t += sample_free_flight(majorant, &mut rng) / ray_length;
+            if t >= t_exit {
+                return (transmittance, emitted);
+            }
+
+            // each tentative collision adds its glow, dimmed by what is in front of it,
+            // and then removes its share of the light instead of stopping the ray
+            let ratio = self.density.density(r.at(t)) / majorant;
+            emitted = emitted + self.emission * (transmittance * ratio);
+            transmittance *= 1.0 - ratio;
+        }
+    }
+
+    /// The part of the ray that is inside the volume and in front of the hit.
+    /// The ray has to be in the space of the medium, like the ray of the hit
+    fn segment_before(&self, r: &Ray, rec: &HitRecord) -> (f64, f64) {
+        let t_enter = self
+            .boundary
+            .hit(r, f64::NEG_INFINITY, f64::INFINITY)
+            .map_or(0.0, |entry| entry.t().max(0.0));
+        (t_enter, rec.t())
+    }
+}
+
+impl<H: Hitable, D: DensityField> Hitable for EmissiveMedium<H, D> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let (_, t_exit) = segment_inside(&self.boundary, r, t_min, f64::INFINITY)?;
+
+        // something inside the volume is closer than where the ray leaves it
+        if t_exit >= t_max {
+            return None;
+        }
+
+        // The ray is recorded as hitting the point where it leaves the volume
+        Some(HitRecord::new(
+            r.at(t_exit),
+            t_exit,
+            Vec3::from_xyz(1, 0, 0),
+            self,
+        ))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.boundary.bounding_box()
+    }
+}
+
+impl<H: Hitable, D: DensityField> Material for EmissiveMedium<H, D> {
+    fn scatter(&self, r: &Ray, rec: &HitRecord) -> Option<(Ray, Color)> {
+        // the boundary and the density are in the space of the medium, which isn't world space when it is transformed
+        let object_ray = rec.object_ray(r);
+        let (t_enter, t_exit) = self.segment_before(&object_ray, rec);
+        let (transmittance, _) = self.ratio_tracking(&object_ray, t_enter, t_exit);
+
+        // the light continues in a straight line
+        Some((
+            Ray::new(rec.point(), r.direction()),
+            Color::from_rgb(transmittance, transmittance, transmittance),
+        ))
+    }
+
+    fn emitted(&self, r: &Ray, rec: &HitRecord) -> Color {
+        let object_ray = rec.object_ray(r);
+        let (t_enter, t_exit) = self.segment_before(&object_ray, rec);
+        self.ratio_tracking(&object_ray, t_enter, t_exit).1
+    }
+}
+
+/// Finds the part of the ray between t_min and t_max that is inside the boundary,
+/// even if the ray starts inside it. Returns None if the ray misses the inside
+fn segment_inside(boundary: &dyn Hitable, r: &Ray, t_min: f64, t_max: f64) -> Option<(f64, f64)> {
+    let entry = boundary.hit(r, f64::NEG_INFINITY, f64::INFINITY)?;
+    let exit = boundary.hit(r, entry.t() + 0.0001, f64::INFINITY)?;
+
+    let t_enter = entry.t().max(t_min).max(0.0);
+    let t_exit = exit.t().min(t_max);
+    if t_enter >= t_exit {
+        return None;
+    }
+
+    Some((t_enter, t_exit))
+}
+
+/// Fog that fills the entire scene. Light travelling through it is attenuated, and replaced by the color of the fog
+#[derive(Clone, Copy)]
+pub struct Fog {
+    density: f64,
+    color: Color,
+}
+
+impl Fog {
+    /// Fails if the density is negative or NaN
+    pub fn new(density: f64, color: Color) -> error::Result<Self> {
+        if density.is_nan() || density < 0.0 {
+            return Err(Error::InvalidSettings(format!(
+                "the density of the fog can't be negative, got {}",
+                density
+            )));
+        }
+        Ok(Self { density, color })
+    }
+
+    /// The fraction of light that makes it through the given distance of fog. The distance can be infinite
+    pub fn transmittance(&self, distance: f64) -> f64 {
+        // without fog nothing is absorbed, even over an infinite distance, where the product would be NaN
+        if self.density == 0.0 {
+            return 1.0;
+        }
+        (-self.density * distance).exp()
+    }
+
+    /// returns the color of the fog
+    pub fn color(&self) -> Color {
+        self.color
+    }
+}
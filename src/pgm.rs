@@ -0,0 +1,69 @@
+use std::io::{Error, ErrorKind};
+
+/// Reads a binary (P5) or ascii (P2) pgm image, and scales the values to between 0 and 1
+pub fn read_pgm(bytes: &[u8]) -> std::io::Result<((usize, usize), Vec<f32>)> {
+    let invalid = |message: &str| Error::new(ErrorKind::InvalidData, message.to_string());
+
+    // the header is four whitespace separated fields, and comments start with #
+    let mut fields = Vec::new();
+    let mut position = 0;
+    while fields.len() < 4 {
+        while position < bytes.len() && bytes[position].is_ascii_whitespace() {
+            position += 1;
+        }
+        if bytes.get(position) == Some(&b'#') {
+            while position < bytes.len() && bytes[position] != b'\n' {
+                position += 1;
+            }
+            continue;
+        }
+
+        let start = position;
+        while position < bytes.len() && !bytes[position].is_ascii_whitespace() {
+            position += 1;
+        }
+        if start == position {
+            return Err(invalid("missing pgm header"));
+        }
+        fields.push(String::from_utf8_lossy(&bytes[start..position]).to_string());
+    }
+
+    let number = |field: &str| -> std::io::Result<usize> {
+        field.parse().map_err(|_| invalid("invalid pgm header"))
+    };
+    let (width, height, max_value) = (
+        number(&fields[1])?,
+        number(&fields[2])?,
+        number(&fields[3])?,
+    );
+    if max_value == 0 || max_value > 65535 {
+        return Err(invalid("invalid pgm max value"));
+    }
+
+    let raw: Vec<usize> = if fields[0] == "P2" {
+        String::from_utf8_lossy(&bytes[position..])
+            .split_whitespace()
+            .map(number)
+            .collect::<std::io::Result<_>>()?
+    } else {
+        // a single whitespace separates the header from the data. 16 bit values are big endian
+        let data = &bytes[(position + 1).min(bytes.len())..];
+        if max_value < 256 {
+            data.iter().map(|b| *b as usize).collect()
+        } else {
+            data.chunks_exact(2)
+                .map(|c| u16::from_be_bytes([c[0], c[1]]) as usize)
+                .collect()
+        }
+    };
+
+    if raw.len() < width * height {
+        return Err(invalid("pgm data doesn't match its dimensions"));
+    }
+
+    let values = raw[..width * height]
+        .iter()
+        .map(|v| *v as f32 / max_value as f32)
+        .collect();
+    Ok(((width, height), values))
+}
@@ -0,0 +1,339 @@
+use rand::Rng;
+use std::f64::consts::PI;
+
+use crate::aabb::Aabb;
+use crate::hitable::{HitRecord, Hitable};
+use crate::material::Material;
+use crate::point3::{Point3, Vec3};
+use crate::random;
+use crate::ray::Ray;
+
+/// How much flat objects' bounding boxes are grown, so they don't have zero thickness
+const FLAT_PADDING: f64 = 0.0001;
+
+/// Returns the t where the ray crosses the plane through the point with the given normal,
+/// if it is between t_min and t_max
+fn hit_plane(point: Point3, normal: Vec3, r: &Ray, t_min: f64, t_max: f64) -> Option<f64> {
+    let denominator = normal.dot_product(&r.direction());
+
+    // the ray is parallel to the plane
+    if denominator.abs() < 1e-8 {
+        return None;
+    }
+
+    let t = normal.dot_product(&(point - r.origin())) / denominator;
+    if t <= t_min || t_max <= t {
+        return None;
+    }
+
+    Some(t)
+}
+
+/// The pdf (pr solid angle) of picking the direction, when points are picked uniformly on a flat
+/// object with the given area. Converts from area to solid angle with distance² / cosine
+fn flat_pdf(object: &dyn Hitable, area: f64, origin: Point3, direction: Vec3) -> f64 {
+    let Some(rec) = object.hit(&Ray::new(origin, direction), 0.001, f64::INFINITY) else {
+        return 0.0;
+    };
+
+    let distance_squared = rec.t().powi(2) * direction.length_squared();
+    let cosine = (direction.dot_product(&rec.normal()) / direction.length()).abs();
+    if cosine < 1e-8 {
+        return 0.0;
+    }
+
+    distance_squared / (cosine * area)
+}
+
+/// A flat surface that goes on forever. The surface coordinates are the distance along the plane,
+/// so patterns repeat instead of being stretched
+pub struct Plane<M: Material> {
+    point: Point3, // Any point on the plane
+    normal: Vec3,
+    tangent: Vec3,   // The direction of u along the plane
+    bitangent: Vec3, // The direction of v along the plane
+    material: M,
+}
+
+impl<M: Material> Plane<M> {
+    /// Construct a plane going through the point, facing in the direction of the normal
+    pub fn from_point_normal_material(point: Point3, normal: Vec3, material: M) -> Self {
+        let normal = normal.unit_vec();
+        let (tangent, bitangent) = normal.orthonormal_basis();
+
+        Self {
+            point,
+            normal,
+            tangent,
+            bitangent,
+            material,
+        }
+    }
+}
+
+impl<M: Material> Hitable for Plane<M> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let t = hit_plane(self.point, self.normal, r, t_min, t_max)?;
+        let point = r.at(t);
+        let offset = point - self.point;
+
+        Some(
+            HitRecord::new(point, t, self.normal, &self.material).with_uv(
+                offset.dot_product(&self.tangent),
+                offset.dot_product(&self.bitangent),
+            ),
+        )
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        None
+    }
+}
+
+/// A parallelogram, spanned by two edges going out from a corner
+pub struct Quad<M: Material> {
+    corner: Point3,
+    u: Vec3, // The first edge
+    v: Vec3, // The second edge
+    normal: Vec3,
+    w: Vec3, // Used to find the surface coordinates of points on the plane
+    area: f64,
+    material: M,
+}
+
+impl<M: Material> Quad<M> {
+    /// Construct a quad from a corner and two edges. The normal points towards the side where u to v is counterclockwise
+    pub fn from_corner_edges_material(corner: Point3, u: Vec3, v: Vec3, material: M) -> Self {
+        let n = u.cross_product(&v);
+
+        Self {
+            corner,
+            u,
+            v,
+            normal: n.unit_vec(),
+            w: n / n.length_squared(),
+            area: n.length(),
+            material,
+        }
+    }
+}
+
+impl<M: Material> Hitable for Quad<M> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let t = hit_plane(self.corner, self.normal, r, t_min, t_max)?;
+        let point = r.at(t);
+
+        // express the point in the coordinates of the edges, and check that it's inside the quad
+        let offset = point - self.corner;
+        let alpha = self.w.dot_product(&offset.cross_product(&self.v));
+        let beta = self.w.dot_product(&self.u.cross_product(&offset));
+        if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) {
+            return None;
+        }
+
+        Some(HitRecord::new(point, t, self.normal, &self.material).with_uv(alpha, beta))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let opposite = self.corner + self.u + self.v;
+        let diagonal = Aabb::new(self.corner + self.u, self.corner + self.v);
+        Some(
+            Aabb::new(self.corner, opposite)
+                .surrounding(&diagonal)
+                .pad(FLAT_PADDING),
+        )
+    }
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        flat_pdf(self, self.area, origin, direction)
+    }
+
+    fn random_direction(&self, origin: Point3) -> Vec3 {
+        let mut rng = random::rng();
+        self.corner + self.u * rng.gen::<f64>() + self.v * rng.gen::<f64>() - origin
+    }
+}
+
+/// A flat circle
+pub struct Disk<M: Material> {
+    center: Point3,
+    normal: Vec3,
+    tangent: Vec3,
+    bitangent: Vec3,
+    radius: f64,
+    material: M,
+}
+
+impl<M: Material> Disk<M> {
+    /// Construct a disk around the center, facing in the direction of the normal
+    pub fn from_center_normal_radius_material(
+        center: Point3,
+        normal: Vec3,
+        radius: impl Into<f64>,
+        material: M,
+    ) -> Self {
+        let normal = normal.unit_vec();
+        let (tangent, bitangent) = normal.orthonormal_basis();
+
+        Self {
+            center,
+            normal,
+            tangent,
+            bitangent,
+            radius: radius.into(),
+            material,
+        }
+    }
+}
+
+impl<M: Material> Hitable for Disk<M> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let t = hit_plane(self.center, self.normal, r, t_min, t_max)?;
+        let point = r.at(t);
+
+        let offset = point - self.center;
+        let distance = offset.length();
+        if distance > self.radius {
+            return None;
+        }
+
+        // u goes around the disk, and v goes from the center to the edge
+        let angle = offset
+            .dot_product(&self.bitangent)
+            .atan2(offset.dot_product(&self.tangent));
+        Some(
+            HitRecord::new(point, t, self.normal, &self.material)
+                .with_uv(0.5 + angle / (2.0 * PI), distance / self.radius),
+        )
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        // how far the disk reaches along each axis
+        let reach =
+            |axis: usize| self.radius * (1.0 - self.normal.axis(axis).powi(2)).max(0.0).sqrt();
+        let extent = Vec3::from_xyz(reach(0), reach(1), reach(2));
+        Some(Aabb::new(self.center - extent, self.center + extent).pad(FLAT_PADDING))
+    }
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        flat_pdf(self, PI * self.radius.powi(2), origin, direction)
+    }
+
+    fn random_direction(&self, origin: Point3) -> Vec3 {
+        let p = Vec3::random_in_unit_circle(&mut random::rng()) * self.radius;
+        self.center + self.tangent * p.x() + self.bitangent * p.y() - origin
+    }
+}
+
+/// A rectangle lying in a plane where one of the coordinates is constant, like the walls and lamps of a
+/// Cornell box. Cheaper to hit than a quad
+pub struct AxisAlignedRect<M: Material> {
+    axis: usize,     // The axis the rectangle faces along. 0 is x, 1 is y and 2 is z
+    k: f64,          // Where the rectangle is along the axis
+    min: (f64, f64), // The smallest corner, in the two other coordinates
+    max: (f64, f64), // The largest corner, in the two other coordinates
+    flipped: bool,   // Whether the normal points towards the negative axis
+    material: M,
+}
+
+impl<M: Material> AxisAlignedRect<M> {
+    /// A rectangle in the plane z = k, between (x0, y0) and (x1, y1)
+    pub fn xy((x0, x1): (f64, f64), (y0, y1): (f64, f64), k: f64, material: M) -> Self {
+        AxisAlignedRect::from_axis(2, (x0, x1), (y0, y1), k, material)
+    }
+
+    /// A rectangle in the plane y = k, between (x0, z0) and (x1, z1)
+    pub fn xz((x0, x1): (f64, f64), (z0, z1): (f64, f64), k: f64, material: M) -> Self {
+        AxisAlignedRect::from_axis(1, (x0, x1), (z0, z1), k, material)
+    }
+
+    /// A rectangle in the plane x = k, between (y0, z0) and (y1, z1)
+    pub fn yz((y0, y1): (f64, f64), (z0, z1): (f64, f64), k: f64, material: M) -> Self {
+        AxisAlignedRect::from_axis(0, (y0, y1), (z0, z1), k, material)
+    }
+
+    fn from_axis(axis: usize, a: (f64, f64), b: (f64, f64), k: f64, material: M) -> Self {
+        Self {
+            axis,
+            k,
+            min: (a.0.min(a.1), b.0.min(b.1)),
+            max: (a.0.max(a.1), b.0.max(b.1)),
+            flipped: false,
+            material,
+        }
+    }
+
+    /// Makes the normal point towards the negative axis instead. Lights only shine on the side of the normal
+    pub fn flip_normal(mut self) -> Self {
+        self.flipped = !self.flipped;
+        self
+    }
+
+    /// The two axes the rectangle lies along
+    fn plane_axes(&self) -> (usize, usize) {
+        match self.axis {
+            0 => (1, 2),
+            1 => (0, 2),
+            _ => (0, 1),
+        }
+    }
+
+    /// Builds a point from its coordinate on the axis and on the two plane axes
+    fn point(&self, k: f64, a: f64, b: f64) -> Point3 {
+        let (axis_a, axis_b) = self.plane_axes();
+        let mut coordinates = [0.0; 3];
+        coordinates[self.axis] = k;
+        coordinates[axis_a] = a;
+        coordinates[axis_b] = b;
+        Point3::from_xyz(coordinates[0], coordinates[1], coordinates[2])
+    }
+
+    fn area(&self) -> f64 {
+        (self.max.0 - self.min.0) * (self.max.1 - self.min.1)
+    }
+}
+
+impl<M: Material> Hitable for AxisAlignedRect<M> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let t = (self.k - r.origin().axis(self.axis)) / r.direction().axis(self.axis);
+        if !t.is_finite() || t <= t_min || t_max <= t {
+            return None;
+        }
+
+        let (axis_a, axis_b) = self.plane_axes();
+        let point = r.at(t);
+        let (a, b) = (point.axis(axis_a), point.axis(axis_b));
+        if a < self.min.0 || a > self.max.0 || b < self.min.1 || b > self.max.1 {
+            return None;
+        }
+
+        let direction = if self.flipped { -1.0 } else { 1.0 };
+        Some(
+            HitRecord::new(point, t, self.point(direction, 0.0, 0.0), &self.material).with_uv(
+                (a - self.min.0) / (self.max.0 - self.min.0),
+                (b - self.min.1) / (self.max.1 - self.min.1),
+            ),
+        )
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(
+            Aabb::new(
+                self.point(self.k, self.min.0, self.min.1),
+                self.point(self.k, self.max.0, self.max.1),
+            )
+            .pad(FLAT_PADDING),
+        )
+    }
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        flat_pdf(self, self.area(), origin, direction)
+    }
+
+    fn random_direction(&self, origin: Point3) -> Vec3 {
+        let mut rng = random::rng();
+        let a = rng.gen_range(self.min.0..=self.max.0);
+        let b = rng.gen_range(self.min.1..=self.max.1);
+        self.point(self.k, a, b) - origin
+    }
+}
@@ -1,6 +1,8 @@
-use rand::{rngs::ThreadRng, Rng};
+use rand::Rng;
 use std::ops::{Add, Div, Mul, Neg, Sub};
 
+use crate::random;
+
 pub type Vec3 = Point3;
 
 /// Struct containing 3 f64 values and vector functions
@@ -45,6 +47,15 @@ impl Point3 {
         self.z
     }
 
+    /// returns the coordinate along an axis, where 0 is x, 1 is y and 2 is z
+    pub fn axis(&self, axis: usize) -> f64 {
+        match axis {
+            0 => self.x,
+            1 => self.y,
+            _ => self.z,
+        }
+    }
+
     /// Returns the distance between this point and another
     pub fn distance(&self, other: &Point3) -> f64 {
         let dx = self.x - other.x();
@@ -85,7 +96,7 @@ impl Point3 {
 
     /// creates a random vec with random values from min to (but not including) max
     pub fn random_vec_from_to(min: f64, max: f64) -> Vec3 {
-        let mut rng = rand::thread_rng();
+        let mut rng = random::rng();
         Self {
             x: rng.gen_range(min..max),
             y: rng.gen_range(min..max),
@@ -112,11 +123,24 @@ impl Point3 {
     /// Checks if Self is close to a null vec
     pub fn near_zero(&self) -> bool {
         let num = 1e-10;
-        (self.x < num) && (self.y < num) && (self.z < num)
+        (self.x.abs() < num) && (self.y.abs() < num) && (self.z.abs() < num)
+    }
+
+    /// Returns two unit vectors that together with this (unit) vector form an orthonormal basis
+    pub fn orthonormal_basis(&self) -> (Vec3, Vec3) {
+        let helper = if self.x.abs() > 0.9 {
+            Vec3::from_xyz(0, 1, 0)
+        } else {
+            Vec3::from_xyz(1, 0, 0)
+        };
+        let tangent = helper.cross_product(self).unit_vec();
+        let bitangent = self.cross_product(&tangent);
+
+        (tangent, bitangent)
     }
 
     /// Generates a random 2 dimensional vector (z = 0) with a lenght less than one
-    pub fn random_in_unit_circle(rng: &mut ThreadRng) -> Vec3 {
+    pub fn random_in_unit_circle(rng: &mut impl Rng) -> Vec3 {
         loop {
             let p = Vec3::from_xyz(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), 0);
             if p.length_squared() < 1.0 {
@@ -150,6 +174,18 @@ impl Sub for Point3 {
     }
 }
 
+impl Add<f64> for Point3 {
+    type Output = Point3;
+
+    fn add(self, other: f64) -> Self {
+        Point3 {
+            x: self.x + other,
+            y: self.y + other,
+            z: self.z + other,
+        }
+    }
+}
+
 impl Sub<f64> for Point3 {
     type Output = Point3;
 
@@ -228,13 +264,13 @@ impl PartialEq for Point3 {
     }
 }
 
-impl Into<Color> for Point3 {
-    fn into(self) -> Color {
-        Color::from_rgb(self.x, self.y, self.z)
+impl From<Point3> for Color {
+    fn from(p: Point3) -> Color {
+        Color::from_rgb(p.x, p.y, p.z)
     }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Default, Copy, Clone)]
 pub struct Color {
     r: f64,
     g: f64,
@@ -274,25 +310,17 @@ impl Color {
     }
 
     /// Returns a string containing the color values of the color.
+    /// The color should already be the average of the samples of its pixel.
     /// The values are scaled by 255.999 and rounded down.
     /// The string returned looks like this: "{r} {g} {b}", so just the color values and no "\n" or anything
-    pub fn write_color(&self, samples_pr_pixel: f64) -> String {
-        let mut r = self.r;
-        let mut g = self.g;
-        let mut b = self.b;
-
-        let scale = 1.0 / samples_pr_pixel;
-
-        r *= scale;
-        g *= scale;
-        b *= scale;
+    pub fn write_color(&self) -> String {
+        let [r, g, b] = self.rgb8();
+        format!("{} {} {}", r, g, b)
+    }
 
-        return format!(
-            "{} {} {}",
-            (256.0 * clamp(r.sqrt(), 0.0, 0.999)) as i32,
-            (256.0 * clamp(g.sqrt(), 0.0, 0.999)) as i32,
-            (256.0 * clamp(b.sqrt(), 0.0, 0.999)) as i32
-        );
+    /// The gamma corrected color values from 0 to 255, the same values write_color writes
+    pub fn rgb8(&self) -> [u8; 3] {
+        [self.r, self.g, self.b].map(|value| (256.0 * clamp(value.sqrt(), 0.0, 0.999)) as u8)
     }
 }
 
@@ -386,16 +414,6 @@ impl PartialEq for Color {
     }
 }
 
-trait IsBetween {
-    fn is_between(&self, a: f64, b: f64) -> bool;
-}
-
-impl IsBetween for f64 {
-    fn is_between(&self, a: f64, b: f64) -> bool {
-        a <= *self && *self <= b
-    }
-}
-
 #[inline]
 fn clamp(num: f64, min: f64, max: f64) -> f64 {
     if num < min {
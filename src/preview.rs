@@ -0,0 +1,220 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use crate::{
+    film::Film,
+    png::encode_png,
+    progress::{Progress, ProgressSink},
+};
+
+/// A small http server that shows the image while it is being rendered, so it can be watched from a browser.
+/// The page at / reloads itself every few seconds, /image.png is the image so far and /status.json tells how far
+/// the render has come. The progress is passed on to another sink, so the progress bar still works
+pub struct PreviewServer {
+    addr: SocketAddr,
+    inner: Arc<dyn ProgressSink>,
+    state: Mutex<PreviewState>,
+}
+
+#[derive(Default)]
+struct PreviewState {
+    width: i64,
+    height: i64,
+    rgb: Vec<u8>, // The image after the last finished pass. Empty until the first pass is done
+    passes: i64,
+    samples_pr_pixel: i64,
+    progress: Option<Progress>,
+    done: bool,
+}
+
+const REFRESH_SECONDS: u32 = 2;
+
+// connections are answered one at a time, so a browser that stops reading or writing can only hold up the others this long
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(5);
+
+impl PreviewServer {
+    /// Starts serving on the address in the background
+    pub fn start(
+        addr: impl ToSocketAddrs,
+        inner: Arc<dyn ProgressSink>,
+    ) -> std::io::Result<Arc<Self>> {
+        let listener = TcpListener::bind(addr)?;
+        let server = Arc::new(Self {
+            addr: listener.local_addr()?,
+            inner,
+            state: Mutex::new(PreviewState::default()),
+        });
+
+        let serving = server.clone();
+        std::thread::spawn(move || {
+            // a browser that goes away in the middle of a response doesn't matter
+            for stream in listener.incoming().flatten() {
+                let _ = serving.respond(stream);
+            }
+        });
+
+        Ok(server)
+    }
+
+    /// returns the address the server listens on
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Shows the film, which has the given amount of finished passes
+    pub fn publish(&self, film: &Film, passes: i64, samples_pr_pixel: i64) {
+        let rgb = film.rgb8();
+        let (width, height) = film.size();
+
+        let mut state = self.state.lock().expect("preview lock poisoned");
+        state.width = width;
+        state.height = height;
+        state.rgb = rgb;
+        state.passes = passes;
+        state.samples_pr_pixel = samples_pr_pixel;
+    }
+
+    fn respond(&self, stream: TcpStream) -> std::io::Result<()> {
+        stream.set_read_timeout(Some(CONNECTION_TIMEOUT))?;
+        stream.set_write_timeout(Some(CONNECTION_TIMEOUT))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut request = String::new();
+        reader.read_line(&mut request)?;
+
+        // the headers aren't needed, but are read so the browser doesn't see the connection reset
+        let mut header = String::new();
+        while reader.read_line(&mut header)? > 2 {
+            header.clear();
+        }
+
+        let path = request
+            .split_whitespace()
+            .nth(1)
+            .unwrap_or("/")
+            .split('?')
+            .next()
+            .unwrap_or("/");
+
+        let (status, content_type, body) = match path {
+            "/" => (
+                "200 OK",
+                "text/html; charset=utf-8",
+                self.page().into_bytes(),
+            ),
+            "/status.json" => ("200 OK", "application/json", self.status().into_bytes()),
+            "/image.png" => match self.png() {
+                Some(png) => ("200 OK", "image/png", png),
+                None => (
+                    "503 Service Unavailable",
+                    "text/plain",
+                    b"the first pass isn't done yet".to_vec(),
+                ),
+            },
+            _ => ("404 Not Found", "text/plain", b"not found".to_vec()),
+        };
+
+        let mut stream = stream;
+        stream.write_all(
+            format!(
+                "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+                status,
+                content_type,
+                body.len()
+            )
+            .as_bytes(),
+        )?;
+        stream.write_all(&body)?;
+        stream.flush()
+    }
+
+    fn png(&self) -> Option<Vec<u8>> {
+        let state = self.state.lock().expect("preview lock poisoned");
+        if state.rgb.is_empty() {
+            return None;
+        }
+        Some(encode_png(
+            state.width as u32,
+            state.height as u32,
+            &state.rgb,
+        ))
+    }
+
+    /// The page that shows the image. It stops reloading when the render is done
+    fn page(&self) -> String {
+        let state = self.state.lock().expect("preview lock poisoned");
+        let refresh = if state.done {
+            String::new()
+        } else {
+            format!(
+                "<meta http-equiv=\"refresh\" content=\"{}\">",
+                REFRESH_SECONDS
+            )
+        };
+        let eta = state
+            .progress
+            .and_then(|progress| progress.eta())
+            .map_or("--".to_string(), |eta| format!("{}s", eta.as_secs()));
+        let status = if state.done {
+            "done".to_string()
+        } else {
+            format!(
+                "{:.1}%, about {} left",
+                state.progress.map_or(0.0, |progress| progress.fraction()) * 100.0,
+                eta
+            )
+        };
+
+        format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n{}\n<title>Render preview</title>\n\
+             <style>body {{ background: #222; color: #ddd; font-family: sans-serif; }} \
+             img {{ max-width: 100%; }}</style>\n</head>\n<body>\n\
+             <p>{} of {} samples pr pixel, {}</p>\n<img src=\"/image.png\" alt=\"the first pass isn't done yet\">\n\
+             </body>\n</html>\n",
+            refresh, state.passes, state.samples_pr_pixel, status
+        )
+    }
+
+    fn status(&self) -> String {
+        let state = self.state.lock().expect("preview lock poisoned");
+        let progress = state.progress;
+        format!(
+            "{{\"done\": {}, \"width\": {}, \"height\": {}, \"passes\": {}, \"samples_pr_pixel\": {}, \
+             \"progress\": {}, \"rays\": {}, \"elapsed_seconds\": {}, \"eta_seconds\": {}}}\n",
+            state.done,
+            state.width,
+            state.height,
+            state.passes,
+            state.samples_pr_pixel,
+            progress.map_or(0.0, |progress| progress.fraction()),
+            progress.map_or(0, |progress| progress.rays()),
+            progress.map_or(0.0, |progress| progress.elapsed().as_secs_f64()),
+            progress
+                .and_then(|progress| progress.eta())
+                .map_or("null".to_string(), |eta| eta.as_secs_f64().to_string())
+        )
+    }
+}
+
+impl ProgressSink for PreviewServer {
+    fn update(&self, progress: &Progress) {
+        self.state.lock().expect("preview lock poisoned").progress = Some(*progress);
+        self.inner.update(progress);
+    }
+
+    fn finish(&self, progress: &Progress) {
+        {
+            let mut state = self.state.lock().expect("preview lock poisoned");
+            state.progress = Some(*progress);
+            state.done = true;
+        }
+        self.inner.finish(progress);
+    }
+
+    fn message(&self, message: &str) {
+        self.inner.message(message);
+    }
+}
@@ -0,0 +1,212 @@
+use std::{
+    io::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// Something that shows how far a render has come, like a progress bar or a window
+pub trait ProgressSink: Send + Sync {
+    /// Called every now and then while the render is running, from any of the render threads
+    fn update(&self, progress: &Progress);
+
+    /// Called once when the render is done or has been stopped
+    fn finish(&self, _progress: &Progress) {}
+
+    /// Called with something worth telling about the render, like a checkpoint that couldn't be saved
+    fn message(&self, _message: &str) {}
+}
+
+/// How far a render has come. A tile is one row of pixels in one pass, and only the
+/// work of this render is counted, so a resumed render doesn't count the passes from its checkpoint
+#[derive(Clone, Copy)]
+pub struct Progress {
+    tiles_done: u64,
+    tiles_total: u64,
+    pixels_done: u64, // The amount of samples taken, one pr pixel pr pass
+    pixels_total: u64,
+    rays: u64, // Every ray sent into the scene, both from the camera and bounced
+    elapsed: Duration,
+}
+
+impl Progress {
+    /// returns the amount of finished tiles
+    pub fn tiles_done(&self) -> u64 {
+        self.tiles_done
+    }
+
+    /// returns the amount of tiles in the render
+    pub fn tiles_total(&self) -> u64 {
+        self.tiles_total
+    }
+
+    /// How much of the render is done, from 0 to 1
+    pub fn fraction(&self) -> f64 {
+        if self.pixels_total == 0 {
+            return 1.0;
+        }
+        self.pixels_done as f64 / self.pixels_total as f64
+    }
+
+    /// returns the amount of rays that have been cast
+    pub fn rays(&self) -> u64 {
+        self.rays
+    }
+
+    /// returns the time since the render started
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    /// The estimated time left, assuming the rest of the render goes as fast as it has so far
+    pub fn eta(&self) -> Option<Duration> {
+        if self.pixels_done == 0 {
+            return None;
+        }
+        let remaining = self.pixels_total.saturating_sub(self.pixels_done) as f64;
+        Some(self.elapsed.mul_f64(remaining / self.pixels_done as f64))
+    }
+}
+
+/// Counts the finished tiles of a render from all the threads, and passes the progress on to a sink
+pub struct ProgressTracker {
+    sink: Arc<dyn ProgressSink>,
+    start: Instant,
+    tiles_total: u64,
+    pixels_total: u64,
+    tiles_done: AtomicU64,
+    pixels_done: AtomicU64,
+    rays: AtomicU64,
+    last_update: Mutex<Instant>, // The sink is only updated a few times a second
+}
+
+const UPDATE_INTERVAL: Duration = Duration::from_millis(100);
+
+impl ProgressTracker {
+    pub fn new(sink: Arc<dyn ProgressSink>, tiles_total: u64, pixels_total: u64) -> Self {
+        let start = Instant::now();
+        Self {
+            sink,
+            start,
+            tiles_total,
+            pixels_total,
+            tiles_done: AtomicU64::new(0),
+            pixels_done: AtomicU64::new(0),
+            rays: AtomicU64::new(0),
+            last_update: Mutex::new(start),
+        }
+    }
+
+    /// Adds finished tiles with the given amount of pixels, and the rays that were cast for them
+    pub fn tiles_done(&self, tiles: u64, pixels: u64, rays: u64) {
+        self.tiles_done.fetch_add(tiles, Ordering::Relaxed);
+        self.pixels_done.fetch_add(pixels, Ordering::Relaxed);
+        self.rays.fetch_add(rays, Ordering::Relaxed);
+
+        // the other threads don't wait while one of them updates the sink
+        let Ok(mut last_update) = self.last_update.try_lock() else {
+            return;
+        };
+        if last_update.elapsed() >= UPDATE_INTERVAL {
+            *last_update = Instant::now();
+            self.sink.update(&self.progress());
+        }
+    }
+
+    /// Tells the sink that the render is over
+    pub fn finish(&self) {
+        self.sink.finish(&self.progress());
+    }
+
+    fn progress(&self) -> Progress {
+        Progress {
+            tiles_done: self.tiles_done.load(Ordering::Relaxed),
+            tiles_total: self.tiles_total,
+            pixels_done: self.pixels_done.load(Ordering::Relaxed),
+            pixels_total: self.pixels_total,
+            rays: self.rays.load(Ordering::Relaxed),
+            elapsed: self.start.elapsed(),
+        }
+    }
+}
+
+/// A progress bar on one line of stderr, with the amount of rays pr second and the time left
+#[derive(Default)]
+pub struct ProgressBar {}
+
+const BAR_WIDTH: usize = 30;
+
+impl ProgressBar {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn draw(&self, progress: &Progress) {
+        let filled = ((progress.fraction() * BAR_WIDTH as f64) as usize).min(BAR_WIDTH);
+        let seconds = progress.elapsed().as_secs_f64();
+        let rays_pr_second = if seconds > 0.0 {
+            progress.rays() as f64 / seconds
+        } else {
+            0.0
+        };
+        let eta = progress.eta().map_or("--".to_string(), format_duration);
+
+        // clear the line and go back to the start of it before drawing the bar again.
+        // A bar that can't be drawn, like when stderr is closed, doesn't stop the render
+        let mut stderr = std::io::stderr();
+        let _ = stderr.write_all(
+            format!(
+                "\x1b[2K\r[{}{}] {:5.1}% {}/{} tiles {:.2}M rays/s ETA {}",
+                "#".repeat(filled),
+                " ".repeat(BAR_WIDTH - filled),
+                progress.fraction() * 100.0,
+                progress.tiles_done(),
+                progress.tiles_total(),
+                rays_pr_second / 1e6,
+                eta
+            )
+            .as_bytes(),
+        );
+        let _ = stderr.flush();
+    }
+}
+
+impl ProgressSink for ProgressBar {
+    fn update(&self, progress: &Progress) {
+        self.draw(progress);
+    }
+
+    fn finish(&self, progress: &Progress) {
+        self.draw(progress);
+        let _ = writeln!(std::io::stderr());
+    }
+
+    /// Writes the message over the bar, which is drawn again below it on the next update
+    fn message(&self, message: &str) {
+        let _ = writeln!(std::io::stderr(), "\x1b[2K\r{}", message);
+    }
+}
+
+/// Shows nothing. Chosen with --quiet
+pub struct Quiet;
+
+impl ProgressSink for Quiet {
+    fn update(&self, _progress: &Progress) {}
+}
+
+/// Formats a duration as minutes and seconds, or hours, minutes and seconds for long renders
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    if seconds >= 3600 {
+        format!(
+            "{}:{:02}:{:02}",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        )
+    } else {
+        format!("{}:{:02}", seconds / 60, seconds % 60)
+    }
+}
@@ -0,0 +1,107 @@
+use std::f64::consts::PI;
+
+use crate::point3::Vec3;
+
+/// How the camera maps pixels to rays. Chosen with `--projection <name>`, optionally followed by its parameter
+/// after a colon, like "fisheye:220"
+#[derive(Clone, Copy)]
+pub enum Projection {
+    Perspective,                  // A pinhole or thin lens camera, using the fov and aperture
+    Orthographic { height: f64 }, // Parallel rays through a view that is this many units tall
+    Fisheye { fov: f64 },         // An equidistant fisheye with a round image, fov degrees across
+    Equirectangular,              // A full 360 by 180 degree panorama
+    Cylindrical { hfov: f64 },    // A panorama covering hfov degrees around the up direction
+}
+
+impl Projection {
+    /// Finds a projection from its name on the command line. The parameter after the colon is optional
+    pub fn from_name(name: &str) -> Option<Projection> {
+        let (name, parameter) = match name.split_once(':') {
+            Some((name, parameter)) => (name, Some(parameter.parse().ok()?)),
+            None => (name, None),
+        };
+
+        match name {
+            "perspective" => Some(Projection::Perspective),
+            "orthographic" => Some(Projection::Orthographic {
+                height: parameter.unwrap_or(10.0),
+            }),
+            "fisheye" => Some(Projection::Fisheye {
+                fov: parameter.unwrap_or(180.0),
+            }),
+            "equirectangular" => Some(Projection::Equirectangular),
+            "cylindrical" => Some(Projection::Cylindrical {
+                hfov: parameter.unwrap_or(360.0),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Checks that the parameters of the projection make sense
+    pub fn validate(&self) -> Result<(), String> {
+        match *self {
+            Projection::Orthographic { height } if height <= 0.0 => Err(format!(
+                "the orthographic view has to be taller than 0, got {}",
+                height
+            )),
+            Projection::Fisheye { fov } if !(fov > 0.0 && fov <= 360.0) => Err(format!(
+                "the fisheye field of view has to be between 0 and 360 degrees, got {}",
+                fov
+            )),
+            Projection::Cylindrical { hfov } if !(hfov > 0.0 && hfov <= 360.0) => Err(format!(
+                "the cylindrical field of view has to be between 0 and 360 degrees, got {}",
+                hfov
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// The direction of the ray through the point (s, t) of the image, where (0, 0) is the top left corner
+    /// and (1, 1) the bottom right. The direction is relative to the camera, with x to the right, y up and
+    /// z forward. Returns None for points outside the image circle of a fisheye.
+    /// Perspective and orthographic rays are made by the camera itself, since they depend on the lens
+    pub fn panorama_direction(&self, s: f64, t: f64, aspect_ratio: f64) -> Option<Vec3> {
+        match *self {
+            Projection::Perspective | Projection::Orthographic { .. } => None,
+            Projection::Fisheye { fov } => {
+                // distance from the image center, where 1 is the edge of the image circle along the shorter side
+                let (x, y) = (2.0 * s - 1.0, 1.0 - 2.0 * t);
+                let (x, y) = if aspect_ratio >= 1.0 {
+                    (x * aspect_ratio, y)
+                } else {
+                    (x, y / aspect_ratio)
+                };
+                let r = (x * x + y * y).sqrt();
+                if r > 1.0 {
+                    return None;
+                }
+
+                // in an equidistant fisheye the angle from the view direction grows linearly with r
+                let theta = r * fov.to_radians() / 2.0;
+                let phi = y.atan2(x);
+                Some(Vec3::from_xyz(
+                    theta.sin() * phi.cos(),
+                    theta.sin() * phi.sin(),
+                    theta.cos(),
+                ))
+            }
+            Projection::Equirectangular => {
+                let longitude = (s - 0.5) * 2.0 * PI;
+                let latitude = (0.5 - t) * PI;
+                Some(Vec3::from_xyz(
+                    latitude.cos() * longitude.sin(),
+                    latitude.sin(),
+                    latitude.cos() * longitude.cos(),
+                ))
+            }
+            Projection::Cylindrical { hfov } => {
+                // the image is wrapped around a cylinder with radius 1, so the height of the image
+                // is the arc length of its width scaled down to the aspect ratio
+                let hfov = hfov.to_radians();
+                let longitude = (s - 0.5) * hfov;
+                let height = (0.5 - t) * hfov / aspect_ratio;
+                Some(Vec3::from_xyz(longitude.sin(), height, longitude.cos()))
+            }
+        }
+    }
+}
@@ -0,0 +1,40 @@
+use rand::{rngs::StdRng, RngCore, SeedableRng};
+use std::cell::RefCell;
+
+thread_local! {
+    // The random numbers that materials, media and lights draw from while a ray bounces around the scene.
+    // The camera seeds it at the start of every row, so a pass takes the same samples on any thread or worker
+    static RNG: RefCell<StdRng> = RefCell::new(StdRng::from_entropy());
+}
+
+/// Seeds the random numbers of this thread
+pub fn seed(seed: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = StdRng::seed_from_u64(seed));
+}
+
+/// A handle to the random numbers of this thread. Used like rand::thread_rng(), but can be seeded
+#[derive(Clone, Copy)]
+pub struct RenderRng;
+
+/// returns a handle to the random numbers of this thread
+pub fn rng() -> RenderRng {
+    RenderRng
+}
+
+impl RngCore for RenderRng {
+    fn next_u32(&mut self) -> u32 {
+        RNG.with(|rng| rng.borrow_mut().next_u32())
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        RNG.with(|rng| rng.borrow_mut().next_u64())
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        RNG.with(|rng| rng.borrow_mut().fill_bytes(dest))
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        RNG.with(|rng| rng.borrow_mut().try_fill_bytes(dest))
+    }
+}
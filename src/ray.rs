@@ -2,14 +2,34 @@ use crate::point3::{Point3, Vec3};
 
 /// Simple ray struct
 /// Represents a straight line in 3d space
+#[derive(Clone, Copy)]
 pub struct Ray {
     origin: Point3,
     direction: Vec3,
+    wavelength: Option<f64>, // The hero wavelength in nanometers, when rendering in spectral mode
+    time: f64,               // The moment in time the ray exists at, used for motion blur
 }
 
 impl Ray {
     pub fn new(origin: Point3, direction: Vec3) -> Self {
-        Self { origin, direction }
+        Self {
+            origin,
+            direction,
+            wavelength: None,
+            time: 0.0,
+        }
+    }
+
+    /// Sets the moment in time the ray exists at
+    pub fn with_time(mut self, time: f64) -> Self {
+        self.time = time;
+        self
+    }
+
+    /// Sets the wavelength the ray carries. None means the ray carries rgb light
+    pub fn with_wavelength(mut self, wavelength: Option<f64>) -> Self {
+        self.wavelength = wavelength;
+        self
     }
 
     /// Get a point on the ray
@@ -26,4 +46,14 @@ impl Ray {
     pub fn direction(&self) -> Vec3 {
         self.direction
     }
+
+    /// Get the hero wavelength of the ray in nanometers. Is None when not rendering in spectral mode
+    pub fn wavelength(&self) -> Option<f64> {
+        self.wavelength
+    }
+
+    /// Get the moment in time the ray exists at
+    pub fn time(&self) -> f64 {
+        self.time
+    }
 }
@@ -0,0 +1,160 @@
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Error, ErrorKind, Write},
+    path::Path,
+};
+
+use crate::film::Film;
+
+/// A rectangle of pixels that is rendered instead of the whole image. Chosen with --region x,y,width,height
+pub struct Region {
+    x: i64,
+    y: i64,
+    width: i64,
+    height: i64,
+    previous: Option<PreviousRender>, // A render of the whole image that the region is pasted into
+}
+
+impl Region {
+    pub fn new(x: i64, y: i64, width: i64, height: i64) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+            previous: None,
+        }
+    }
+
+    /// Finds a region from its description on the command line, like "100,50,200,120"
+    pub fn from_name(name: &str) -> Option<Region> {
+        let values = name
+            .split(',')
+            .map(|value| value.trim().parse().ok())
+            .collect::<Option<Vec<i64>>>()?;
+
+        match values[..] {
+            [x, y, width, height] => Some(Region::new(x, y, width, height)),
+            _ => None,
+        }
+    }
+
+    /// Writes the whole image with the region pasted into a previous render, instead of just the region
+    pub fn composite_over(mut self, previous: PreviousRender) -> Self {
+        self.previous = Some(previous);
+        self
+    }
+
+    /// Checks that the region is inside an image of the given size, and that the previous render has the same size
+    pub fn validate(&self, width: i64, height: i64) -> Result<(), String> {
+        if self.width <= 0 || self.height <= 0 {
+            return Err(format!(
+                "the region has to be at least one pixel, got {}x{}",
+                self.width, self.height
+            ));
+        }
+        if self.x < 0 || self.y < 0 || self.x + self.width > width || self.y + self.height > height
+        {
+            return Err(format!(
+                "the region {}x{} at ({}, {}) is outside the {}x{} image",
+                self.width, self.height, self.x, self.y, width, height
+            ));
+        }
+        if let Some(previous) = &self.previous {
+            if previous.width != width || previous.height != height {
+                return Err(format!(
+                    "the previous render is {}x{}, but the image is {}x{}",
+                    previous.width, previous.height, width, height
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// The first and one past the last pixel of the region, as ((x0, y0), (x1, y1))
+    pub fn bounds(&self) -> ((i64, i64), (i64, i64)) {
+        (
+            (self.x, self.y),
+            (self.x + self.width, self.y + self.height),
+        )
+    }
+
+    fn contains(&self, x: i64, y: i64) -> bool {
+        (self.x..self.x + self.width).contains(&x) && (self.y..self.y + self.height).contains(&y)
+    }
+
+    /// Writes the region of the film as a ppm image. With a previous render the whole image is written,
+    /// and the pixels outside the region come from the previous render
+    pub fn write_ppm(&self, film: &Film, file: &mut BufWriter<File>) -> std::io::Result<()> {
+        let ((x0, y0), (x1, y1)) = match &self.previous {
+            Some(previous) => ((0, 0), (previous.width, previous.height)),
+            None => self.bounds(),
+        };
+
+        file.write_all(format!("P3\n{} {}\n255\n", x1 - x0, y1 - y0).as_bytes())?;
+
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let pixel = match &self.previous {
+                    Some(previous) if !self.contains(x, y) => previous.pixel(x, y),
+                    _ => film.pixel(x, y).write_color(),
+                };
+                file.write_all(format!("\n{}", pixel).as_bytes())?;
+            }
+        }
+        file.flush()
+    }
+}
+
+/// A finished render loaded from a ppm file written by the renderer
+pub struct PreviousRender {
+    width: i64,
+    height: i64,
+    pixels: Vec<[u32; 3]>,
+}
+
+impl PreviousRender {
+    /// Loads an ascii (P3) ppm image with 255 as the max value
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let invalid = |message: &str| Error::new(ErrorKind::InvalidData, message.to_string());
+
+        let text = fs::read_to_string(path)?;
+        let mut fields = text
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or(""))
+            .flat_map(|line| line.split_whitespace());
+
+        if fields.next() != Some("P3") {
+            return Err(invalid("the previous render has to be an ascii ppm image"));
+        }
+
+        let mut number = || -> std::io::Result<u32> {
+            fields
+                .next()
+                .and_then(|field| field.parse().ok())
+                .ok_or_else(|| invalid("invalid ppm data"))
+        };
+        let (width, height, max_value) = (number()? as i64, number()? as i64, number()?);
+        if max_value != 255 {
+            return Err(invalid(
+                "the previous render has to have 255 as the max value",
+            ));
+        }
+
+        let pixels = (0..width * height)
+            .map(|_| Ok([number()?, number()?, number()?]))
+            .collect::<std::io::Result<_>>()?;
+
+        Ok(Self {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    /// The pixel at (x, y), formatted the same way as Color::write_color
+    fn pixel(&self, x: i64, y: i64) -> String {
+        let [r, g, b] = self.pixels[(y * self.width + x) as usize];
+        format!("{} {} {}", r, g, b)
+    }
+}
@@ -0,0 +1,73 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+};
+
+use crate::{
+    camera::Camera, error::Result, film::Film, hitable::Hitable, png::encode_png, point3::Color,
+};
+
+/// A finished render
+pub struct Image {
+    width: i64,
+    height: i64,
+    pixels: Vec<Color>, // Row by row from the top left corner, already averaged over the samples
+}
+
+impl Image {
+    /// Takes the pixels inside the bounds of the film, given as ((x0, y0), (x1, y1)) like Region::bounds
+    pub fn from_film(film: &Film, bounds: ((i64, i64), (i64, i64))) -> Self {
+        let ((x0, y0), (x1, y1)) = bounds;
+        let pixels = (y0..y1)
+            .flat_map(|y| (x0..x1).map(move |x| film.pixel(x, y)))
+            .collect();
+
+        Self {
+            width: x1 - x0,
+            height: y1 - y0,
+            pixels,
+        }
+    }
+
+    /// returns the width of the image
+    pub fn width(&self) -> i64 {
+        self.width
+    }
+
+    /// returns the height of the image
+    pub fn height(&self) -> i64 {
+        self.height
+    }
+
+    /// The linear color of the pixel at (x, y)
+    pub fn pixel(&self, x: i64, y: i64) -> Color {
+        self.pixels[(y * self.width + x) as usize]
+    }
+
+    /// The gamma corrected 8 bit rgb values of all the pixels, row by row from the top left corner
+    pub fn rgb8(&self) -> Vec<u8> {
+        self.pixels.iter().flat_map(|pixel| pixel.rgb8()).collect()
+    }
+
+    /// Encodes the image as a png
+    pub fn png(&self) -> Vec<u8> {
+        encode_png(self.width as u32, self.height as u32, &self.rgb8())
+    }
+
+    /// Writes the image as a ppm image
+    pub fn write_ppm(&self, file: &mut BufWriter<File>) -> std::io::Result<()> {
+        file.write_all(format!("P3\n{} {}\n255\n", self.width, self.height).as_bytes())?;
+
+        for pixel in &self.pixels {
+            file.write_all(format!("\n{}", pixel.write_color()).as_bytes())?;
+        }
+        file.flush()
+    }
+}
+
+/// Renders the world with the camera on all the cores, and returns the image.
+/// Everything about the render, like the samples and the resolution, is set on the camera
+pub fn render(world: &dyn Hitable, camera: &Camera) -> Result<Image> {
+    let (image, _) = camera.render_image(world, true)?;
+    Ok(image)
+}
@@ -0,0 +1,706 @@
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::sync::Arc;
+
+use crate::{
+    bvh::Bvh,
+    camera::{Camera, CameraBuilder},
+    csg::{Difference, Intersection, Union},
+    density::{GridDensity, NoiseDensity, Perlin},
+    error,
+    heightfield::Heightfield,
+    hitable::Hitable,
+    material::{
+        Checker, Dielectric, DiffuseLight, HenyeyGreenstein, Isotropic, Lambertian, Materials,
+        Metal, RefractiveIndex,
+    },
+    medium::{ConstantMedium, EmissiveMedium, HeterogeneousMedium},
+    planar::{AxisAlignedRect, Disk, Plane, Quad},
+    point3::{Color, Point3, Vec3},
+    sdf::{
+        Mandelbulb, Repeat, SdfBox, SdfCapsule, SdfCylinder, SdfObject, SdfSphere, SdfTorus,
+        SmoothSubtract, SmoothUnion, Translate, Twist,
+    },
+    shapes::{BoxShape, Capsule, Cone, Cylinder, Torus},
+    sphere::{MovingSphere, Sphere},
+    transform::{AnimatedTransform, Transform, Transformed},
+};
+
+/// The scenes that can be rendered. Chosen with `--scene <name>`
+pub enum Scene {
+    RandomSpheres,           // The final scene from "Raytracing in one weekend"
+    Smoke,                   // Spheres filled with smoke and mist
+    Volumes(Option<String>), // A cloud, a fire and a voxel grid. The grid can be loaded from a volume file
+    Instances,               // A field of transformed copies of the same object
+    Motion,                  // Bouncing and spinning objects, blurred by the camera shutter
+    CornellBox,              // A closed room lit by a lamp in the ceiling
+    Shapes,                  // One of each of the basic shapes
+    Csg,                     // Objects made by combining shapes
+    Sdf,                     // Blobby, twisted and fractal shapes made from distance fields
+    Terrain(Option<String>), // Hills made from a heightfield. The heights can be loaded from an image
+}
+
+impl Scene {
+    /// Finds a scene from its name on the command line
+    pub fn from_name(name: &str) -> Option<Scene> {
+        match name {
+            "spheres" => Some(Scene::RandomSpheres),
+            "smoke" => Some(Scene::Smoke),
+            "volumes" => Some(Scene::Volumes(None)),
+            "instances" => Some(Scene::Instances),
+            "motion" => Some(Scene::Motion),
+            "cornell" => Some(Scene::CornellBox),
+            "shapes" => Some(Scene::Shapes),
+            "csg" => Some(Scene::Csg),
+            "sdf" => Some(Scene::Sdf),
+            "terrain" => Some(Scene::Terrain(None)),
+            _ => None,
+        }
+    }
+
+    /// Builds the objects of the scene. The random parts of the scene come from the seed,
+    /// so the same seed always gives the same scene. Fails if a file the scene is loaded from can't be read
+    pub fn world(&self, seed: u64) -> error::Result<Vec<Box<dyn Hitable>>> {
+        let rng = &mut StdRng::seed_from_u64(seed);
+        Ok(match self {
+            Scene::RandomSpheres => random_spheres(rng),
+            Scene::Smoke => smoke()?,
+            Scene::Volumes(grid_path) => volumes(grid_path.as_deref(), rng)?,
+            Scene::Instances => instances(rng),
+            Scene::Motion => motion()?,
+            Scene::CornellBox => cornell_box(),
+            Scene::Shapes => shapes(),
+            Scene::Csg => csg(),
+            Scene::Sdf => sdf(),
+            Scene::Terrain(heightmap_path) => terrain(heightmap_path.as_deref(), rng)?,
+        })
+    }
+
+    /// Creates a camera builder that is set up for the scene
+    pub fn camera(&self) -> CameraBuilder {
+        let cam = Camera::builder().resolution(600, 337);
+
+        match self {
+            Scene::RandomSpheres => cam
+                .look_from(Point3::from_xyz(13, 2, 3))
+                .look_at(Point3::from_xyz(0, 0, 0))
+                .vup(Vec3::from_xyz(0, 1, 0))
+                .vfov(20.0)
+                .bounces(10)
+                .samples(10)
+                .aperture(0.6)
+                .focus_distance(10.0),
+            Scene::Smoke | Scene::Volumes(_) | Scene::Shapes | Scene::Csg | Scene::Sdf => cam
+                .look_from(Point3::from_xyz(0, 2, 12))
+                .look_at(Point3::from_xyz(0, 1, 0))
+                .vfov(30.0)
+                .bounces(50)
+                .samples(50),
+            Scene::Instances => cam
+                .look_from(Point3::from_xyz(0, 6, 18))
+                .look_at(Point3::from_xyz(0, 0.5, 0))
+                .vfov(40.0)
+                .bounces(10)
+                .samples(20),
+            Scene::Motion => cam
+                .look_from(Point3::from_xyz(0, 3, 12))
+                .look_at(Point3::from_xyz(0, 1, 0))
+                .vfov(30.0)
+                .bounces(50)
+                .samples(20)
+                .shutter(0.0, 1.0),
+            Scene::Terrain(_) => cam
+                .look_from(Point3::from_xyz(0, 8, 20))
+                .look_at(Point3::from_xyz(0, 2, 0))
+                .vfov(45.0)
+                .bounces(20)
+                .samples(20),
+            Scene::CornellBox => cam
+                .resolution(400, 400)
+                .look_from(Point3::from_xyz(278, 278, -800))
+                .look_at(Point3::from_xyz(278, 278, 0))
+                .vfov(40.0)
+                .bounces(50)
+                .samples(100)
+                .background(Some(Color::new()))
+                .lights(vec![Box::new(cornell_lamp())]),
+        }
+    }
+}
+
+fn random_spheres(rng: &mut impl Rng) -> Vec<Box<dyn Hitable>> {
+    let mut world: Vec<Box<dyn Hitable>> = Vec::new(); //HitableList::default();
+
+    // Add hitable objects to the world
+    for a in -11..11 {
+        for b in -11..11 {
+            let center = Point3::from_xyz(
+                a as f64 + 0.9 * rng.gen::<f64>(),
+                0.2,
+                b as f64 + 0.9 * rng.gen::<f64>(),
+            );
+            let material = rng.gen::<Materials>();
+
+            if (center - Point3::from_xyz(4, 0.2, 9)).length() > 0.9 {
+                match material {
+                    Materials::Rough(mat) => world.push(Box::new(
+                        Sphere::from_center_radius_material(center, 0.3, mat),
+                    )),
+                    Materials::Reflective(mat) => world.push(Box::new(
+                        Sphere::from_center_radius_material(center, 0.3, mat),
+                    )),
+                    Materials::Glass(mat) => {
+                        let sphere = Sphere::from_center_radius_material(center, 0.3, mat);
+                        if rng.gen::<f64>() >= 0.5 {
+                            // a hollow bubble
+                            world.push(Box::new(Difference::new(
+                                sphere,
+                                Sphere::from_center_radius_material(center, 0.2, mat),
+                            )));
+                        } else {
+                            world.push(Box::new(sphere));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // Add some bigger spheres in the center, aswell as a large sphere that acts as a ground
+    let ground_material = Lambertian::from_color(Color::from_rgb(0.5, 0.5, 0.5));
+    let center_material = Lambertian::from_color(Color::from_rgb(0.1, 0.2, 0.5));
+    let left_material = Dielectric::from_refractive_index(RefractiveIndex::BK7);
+    let right_material = Metal::from_color(Color::from_rgb(0.8, 0.6, 0.2), 1.0);
+
+    world.push(Box::new(Sphere::from_center_radius_material(
+        Point3::from_xyz(-4, 1, 0),
+        1,
+        center_material,
+    )));
+    world.push(Box::new(Difference::new(
+        Sphere::from_center_radius_material(Point3::from_xyz(0, 1, 0), 1, left_material),
+        Sphere::from_center_radius_material(Point3::from_xyz(0, 1, 0), 0.7, left_material),
+    )));
+    world.push(Box::new(Sphere::from_center_radius_material(
+        Point3::from_xyz(4, 1, 0),
+        1,
+        right_material,
+    )));
+    world.push(Box::new(Plane::from_point_normal_material(
+        Point3::new(),
+        Vec3::from_xyz(0, 1, 0),
+        ground_material,
+    )));
+
+    world
+}
+
+fn smoke() -> error::Result<Vec<Box<dyn Hitable>>> {
+    Ok(vec![
+        Box::new(Plane::from_point_normal_material(
+            Point3::new(),
+            Vec3::from_xyz(0, 1, 0),
+            Lambertian::from_color(Color::from_rgb(0.5, 0.5, 0.5)),
+        )),
+        // white smoke that scatters light equally in all directions
+        Box::new(ConstantMedium::new(
+            Sphere::from_center_radius_material(Point3::from_xyz(-2.5, 1, 0), 1, Lambertian::new()),
+            1.5,
+            Isotropic::from_color(Color::from_rgb(0.9, 0.9, 0.9)),
+        )?),
+        // a glass ball with some smoke trapped inside it
+        Box::new(Sphere::from_center_radius_material(
+            Point3::from_xyz(0, 1, 0),
+            1,
+            Dielectric::from_ir(1.5),
+        )),
+        Box::new(ConstantMedium::new(
+            Sphere::from_center_radius_material(Point3::from_xyz(0, 1, 0), 0.9, Lambertian::new()),
+            0.8,
+            Isotropic::from_color(Color::from_rgb(0.2, 0.4, 0.9)),
+        )?),
+        // orange mist that mostly scatters light forwards
+        Box::new(ConstantMedium::new(
+            Sphere::from_center_radius_material(Point3::from_xyz(2.5, 1, 0), 1, Lambertian::new()),
+            2.0,
+            HenyeyGreenstein::from_color(Color::from_rgb(0.9, 0.5, 0.2), 0.7),
+        )?),
+    ])
+}
+
+fn volumes(grid_path: Option<&str>, rng: &mut impl Rng) -> error::Result<Vec<Box<dyn Hitable>>> {
+    let grid_min = Point3::from_xyz(1.5, 0, -1);
+    let grid_max = Point3::from_xyz(3.5, 2, 1);
+    let grid = match grid_path {
+        Some(path) => GridDensity::load(path, grid_min, grid_max)?,
+        None => smoke_ring_grid(grid_min, grid_max)?,
+    };
+
+    Ok(vec![
+        Box::new(Plane::from_point_normal_material(
+            Point3::new(),
+            Vec3::from_xyz(0, 1, 0),
+            Lambertian::from_color(Color::from_rgb(0.5, 0.5, 0.5)),
+        )),
+        // a fluffy cloud
+        Box::new(HeterogeneousMedium::new(
+            Sphere::from_center_radius_material(Point3::from_xyz(-2.5, 1, 0), 1, Lambertian::new()),
+            NoiseDensity::new(rng, 8.0, 2.5, 4, 0.5),
+            HenyeyGreenstein::from_color(Color::from_rgb(0.95, 0.95, 0.95), 0.4),
+        )),
+        // a ball of fire
+        Box::new(EmissiveMedium::new(
+            Sphere::from_center_radius_material(Point3::from_xyz(0, 1, 0), 1, Lambertian::new()),
+            NoiseDensity::new(rng, 6.0, 3.0, 3, 0.45),
+            Color::from_rgb(1.5, 0.6, 0.1),
+        )),
+        // the voxel grid, which is a glowing smoke ring unless another one is loaded
+        Box::new(
+            HeterogeneousMedium::new(
+                Sphere::from_center_radius_material(
+                    (grid_min + grid_max) / 2.0,
+                    (grid_max - grid_min).length() / 2.0,
+                    Lambertian::new(),
+                ),
+                grid,
+                Isotropic::from_color(Color::from_rgb(0.6, 0.8, 0.6)),
+            )
+            .with_emission(Color::from_rgb(0.2, 0.6, 0.3)),
+        ),
+    ])
+}
+
+/// Builds a voxel grid with a ring of smoke inside it
+fn smoke_ring_grid(min: Point3, max: Point3) -> error::Result<GridDensity> {
+    let n = 32;
+    let mut values = Vec::with_capacity(n * n * n);
+    for z in 0..n {
+        for y in 0..n {
+            for x in 0..n {
+                // position in the grid from -1 to 1
+                let p =
+                    Point3::from_xyz(x as f64, y as f64, z as f64) / (n as f64 - 1.0) * 2.0 - 1.0;
+                let ring_distance = ((p.x().powi(2) + p.z().powi(2)).sqrt() - 0.6).hypot(p.y());
+                values.push((10.0 * (1.0 - ring_distance / 0.3)).max(0.0) as f32);
+            }
+        }
+    }
+
+    GridDensity::from_values((n, n, n), values, min, max)
+}
+
+fn instances(rng: &mut impl Rng) -> Vec<Box<dyn Hitable>> {
+    // A helix of small spheres. It is only built once, and shared by all the instances
+    let helix: Vec<Box<dyn Hitable>> = (0..60)
+        .map(|i| {
+            let angle = i as f64 * 0.35;
+            let center = Point3::from_xyz(0.3 * angle.cos(), i as f64 * 0.02, 0.3 * angle.sin());
+            let color = Color::from_rgb(
+                0.5 + 0.5 * angle.cos(),
+                0.5 + 0.5 * (angle + 2.0).cos(),
+                0.5 + 0.5 * (angle + 4.0).cos(),
+            );
+            Box::new(Sphere::from_center_radius_material(
+                center,
+                0.08,
+                Lambertian::from_color(color),
+            )) as Box<dyn Hitable>
+        })
+        .collect();
+    let helix = Arc::new(Bvh::new(helix));
+
+    let mut world: Vec<Box<dyn Hitable>> = vec![
+        Box::new(Plane::from_point_normal_material(
+            Point3::new(),
+            Vec3::from_xyz(0, 1, 0),
+            Lambertian::from_color(Color::from_rgb(0.5, 0.5, 0.5)),
+        )),
+        // a glass ellipsoid, made by scaling a sphere unevenly
+        Box::new(Transformed::new(
+            Sphere::from_center_radius_material(Point3::new(), 1, Dielectric::from_ir(1.5)),
+            Transform::scale(2.5, 1, 1)
+                .then(Transform::rotate_z(15.0))
+                .then(Transform::rotate_y(30.0))
+                .then(Transform::translate(Vec3::from_xyz(0, 1, 8))),
+        )),
+    ];
+
+    for x in -20..20 {
+        for z in -20..10 {
+            let size = 0.6 + 0.6 * rng.gen::<f64>();
+            let transform = Transform::scale(size, size * (0.5 + rng.gen::<f64>()), size)
+                .then(Transform::rotate_x(20.0 * (rng.gen::<f64>() - 0.5)))
+                .then(Transform::rotate_y(360.0 * rng.gen::<f64>()))
+                .then(Transform::translate(Vec3::from_xyz(
+                    x as f64 + 0.5 * rng.gen::<f64>(),
+                    0,
+                    z as f64 + 0.5 * rng.gen::<f64>(),
+                )));
+
+            world.push(Box::new(Transformed::new(helix.clone(), transform)));
+        }
+    }
+
+    world
+}
+
+fn motion() -> error::Result<Vec<Box<dyn Hitable>>> {
+    let mut world: Vec<Box<dyn Hitable>> = vec![Box::new(Plane::from_point_normal_material(
+        Point3::new(),
+        Vec3::from_xyz(0, 1, 0),
+        Lambertian::from_color(Color::from_rgb(0.5, 0.5, 0.5)),
+    ))];
+
+    // a row of spheres bouncing up from the ground at different speeds
+    for i in 0..5 {
+        let x = i as f64 * 1.4 - 2.8;
+        let color = Color::from_rgb(0.2 + 0.15 * i as f64, 0.3, 0.9 - 0.15 * i as f64);
+        world.push(Box::new(MovingSphere::from_centers_radius_material(
+            (Point3::from_xyz(x, 0.5, 0), 0.0),
+            (Point3::from_xyz(x, 0.5 + 0.25 * i as f64, 0), 1.0),
+            0.5,
+            Lambertian::from_color(color),
+        )));
+    }
+
+    // a metal ellipsoid that spins and slides sideways while the shutter is open
+    let ellipsoid = Arc::new(Sphere::from_center_radius_material(
+        Point3::new(),
+        1,
+        Metal::from_color(Color::from_rgb(0.8, 0.6, 0.2), 0.1),
+    ));
+    let spin = |degrees: f64, x: f64| {
+        Transform::scale(1.2, 0.4, 0.4)
+            .then(Transform::rotate_z(degrees))
+            .then(Transform::translate(Vec3::from_xyz(x, 2.6, -1)))
+    };
+    world.push(Box::new(Transformed::animated(
+        ellipsoid,
+        AnimatedTransform::new(vec![
+            (0.0, spin(0.0, -1.0)),
+            (0.5, spin(60.0, 0.0)),
+            (1.0, spin(120.0, 1.0)),
+        ])?,
+    )));
+
+    Ok(world)
+}
+
+fn cornell_box() -> Vec<Box<dyn Hitable>> {
+    let red = Lambertian::from_color(Color::from_rgb(0.65, 0.05, 0.05));
+    let white = Lambertian::from_color(Color::from_rgb(0.73, 0.73, 0.73));
+    let green = Lambertian::from_color(Color::from_rgb(0.12, 0.45, 0.15));
+
+    let quad = |corner: (f64, f64, f64), u: (f64, f64, f64), v: (f64, f64, f64), material| {
+        Box::new(Quad::from_corner_edges_material(
+            Point3::from_xyz(corner.0, corner.1, corner.2),
+            Vec3::from_xyz(u.0, u.1, u.2),
+            Vec3::from_xyz(v.0, v.1, v.2),
+            material,
+        )) as Box<dyn Hitable>
+    };
+
+    vec![
+        Box::new(AxisAlignedRect::yz(
+            (0.0, 555.0),
+            (0.0, 555.0),
+            555.0,
+            green,
+        )),
+        Box::new(AxisAlignedRect::yz((0.0, 555.0), (0.0, 555.0), 0.0, red)),
+        Box::new(AxisAlignedRect::xy(
+            (0.0, 555.0),
+            (0.0, 555.0),
+            555.0,
+            white,
+        )),
+        quad((0.0, 0.0, 0.0), (555.0, 0.0, 0.0), (0.0, 0.0, 555.0), white),
+        quad(
+            (0.0, 555.0, 0.0),
+            (555.0, 0.0, 0.0),
+            (0.0, 0.0, 555.0),
+            white,
+        ),
+        Box::new(cornell_lamp()),
+        // a checkered rug, with a glass ball and a metal ball on top of it
+        Box::new(Disk::from_center_normal_radius_material(
+            Point3::from_xyz(278, 0.5, 300),
+            Vec3::from_xyz(0, 1, 0),
+            200,
+            Checker::from_colors(
+                Color::from_rgb(0.73, 0.73, 0.73),
+                Color::from_rgb(0.2, 0.3, 0.5),
+                8.0,
+            ),
+        )),
+        Box::new(Sphere::from_center_radius_material(
+            Point3::from_xyz(190, 90, 190),
+            90,
+            Dielectric::from_ir(1.5),
+        )),
+        Box::new(Sphere::from_center_radius_material(
+            Point3::from_xyz(380, 110, 380),
+            110,
+            Metal::from_color(Color::from_rgb(0.8, 0.85, 0.88), 0.05),
+        )),
+    ]
+}
+
+/// The lamp in the ceiling of the cornell box. Used both in the scene, and by the camera to sample the light
+fn cornell_lamp() -> AxisAlignedRect<DiffuseLight> {
+    AxisAlignedRect::xz(
+        (213.0, 343.0),
+        (227.0, 332.0),
+        554.0,
+        DiffuseLight::from_color(Color::from_rgb(15, 15, 15)),
+    )
+    .flip_normal()
+}
+
+fn shapes() -> Vec<Box<dyn Hitable>> {
+    vec![
+        Box::new(Plane::from_point_normal_material(
+            Point3::new(),
+            Vec3::from_xyz(0, 1, 0),
+            Checker::from_colors(
+                Color::from_rgb(0.6, 0.6, 0.6),
+                Color::from_rgb(0.3, 0.3, 0.3),
+                1.0,
+            ),
+        )),
+        Box::new(Transformed::new(
+            BoxShape::from_corners_material(
+                Point3::from_xyz(-0.7, 0, -0.7),
+                Point3::from_xyz(0.7, 1.4, 0.7),
+                Lambertian::from_color(Color::from_rgb(0.8, 0.3, 0.2)),
+            ),
+            Transform::rotate_y(30.0).then(Transform::translate(Vec3::from_xyz(-4, 0, 0))),
+        )),
+        Box::new(Cylinder::from_base_radius_height_material(
+            Point3::from_xyz(-2, 0, 0),
+            0.6,
+            1.6,
+            Metal::from_color(Color::from_rgb(0.8, 0.8, 0.8), 0.2),
+        )),
+        // a glass tube lying on its side
+        Box::new(Transformed::new(
+            Cylinder::from_base_radius_height_material(
+                Point3::new(),
+                0.3,
+                1.5,
+                Dielectric::from_ir(1.5),
+            )
+            .open(),
+            Transform::rotate_z(90.0).then(Transform::translate(Vec3::from_xyz(-1.5, 0.3, 2))),
+        )),
+        Box::new(Cone::from_base_radius_height_material(
+            Point3::new(),
+            0.7,
+            1.8,
+            Lambertian::from_color(Color::from_rgb(0.2, 0.5, 0.8)),
+        )),
+        Box::new(Transformed::new(
+            Torus::from_center_radii_material(
+                Point3::new(),
+                0.7,
+                0.25,
+                Metal::from_color(Color::from_rgb(0.9, 0.7, 0.3), 0.05),
+            ),
+            Transform::rotate_x(60.0).then(Transform::translate(Vec3::from_xyz(2, 0.9, 0))),
+        )),
+        Box::new(Capsule::from_ends_radius_material(
+            Point3::from_xyz(3.6, 0.4, -0.5),
+            Point3::from_xyz(4.4, 1.5, 0.5),
+            0.4,
+            Lambertian::from_color(Color::from_rgb(0.3, 0.7, 0.3)),
+        )),
+    ]
+}
+
+fn csg() -> Vec<Box<dyn Hitable>> {
+    let glass = Dielectric::from_ir(1.5);
+    let steel = Metal::from_color(Color::from_rgb(0.8, 0.8, 0.85), 0.1);
+
+    vec![
+        Box::new(Plane::from_point_normal_material(
+            Point3::new(),
+            Vec3::from_xyz(0, 1, 0),
+            Checker::from_colors(
+                Color::from_rgb(0.6, 0.6, 0.6),
+                Color::from_rgb(0.3, 0.3, 0.3),
+                1.0,
+            ),
+        )),
+        // a biconvex lens, where two spheres overlap
+        Box::new(Intersection::new(
+            Sphere::from_center_radius_material(Point3::from_xyz(-3, 1.6, -2), 2.5, glass),
+            Sphere::from_center_radius_material(Point3::from_xyz(-3, 1.6, 2), 2.5, glass),
+        )),
+        // a box with a ball shaped bite taken out of the corner, which shows the red inside
+        Box::new(Transformed::new(
+            Difference::new(
+                BoxShape::from_corners_material(
+                    Point3::from_xyz(-0.8, 0, -0.8),
+                    Point3::from_xyz(0.8, 1.6, 0.8),
+                    Lambertian::from_color(Color::from_rgb(0.9, 0.9, 0.85)),
+                ),
+                Sphere::from_center_radius_material(
+                    Point3::from_xyz(0.8, 1.6, 0.8),
+                    1,
+                    Lambertian::from_color(Color::from_rgb(0.8, 0.1, 0.1)),
+                ),
+            ),
+            Transform::rotate_y(-20.0),
+        )),
+        // a hollow metal shell, cut open so the inside can be seen
+        Box::new(Difference::new(
+            Difference::new(
+                Sphere::from_center_radius_material(Point3::from_xyz(3, 1, 0), 1, steel),
+                Sphere::from_center_radius_material(Point3::from_xyz(3, 1, 0), 0.9, steel),
+            ),
+            BoxShape::from_corners_material(
+                Point3::from_xyz(3, 1, 0),
+                Point3::from_xyz(4.5, 2.5, 1.5),
+                steel,
+            ),
+        )),
+        // three cylinders joined into a cross
+        Box::new(Transformed::new(
+            Union::new(
+                Union::new(
+                    Cylinder::from_base_radius_height_material(
+                        Point3::from_xyz(0, -0.6, 0),
+                        0.2,
+                        1.2,
+                        steel,
+                    ),
+                    Transformed::new(
+                        Cylinder::from_base_radius_height_material(
+                            Point3::from_xyz(0, -0.6, 0),
+                            0.2,
+                            1.2,
+                            steel,
+                        ),
+                        Transform::rotate_x(90.0),
+                    ),
+                ),
+                Transformed::new(
+                    Cylinder::from_base_radius_height_material(
+                        Point3::from_xyz(0, -0.6, 0),
+                        0.2,
+                        1.2,
+                        steel,
+                    ),
+                    Transform::rotate_z(90.0),
+                ),
+            ),
+            Transform::rotate_y(30.0).then(Transform::translate(Vec3::from_xyz(1.2, 0.6, 2.5))),
+        )),
+    ]
+}
+
+fn sdf() -> Vec<Box<dyn Hitable>> {
+    let floor = Plane::from_point_normal_material(
+        Point3::new(),
+        Vec3::from_xyz(0, 1, 0),
+        Lambertian::from_color(Color::from_rgb(0.5, 0.5, 0.5)),
+    );
+
+    // three balls and a stick melted together
+    let blob = SmoothUnion::new(
+        SmoothUnion::new(
+            SdfSphere::new(0.7),
+            Translate::new(SdfSphere::new(0.5), Vec3::from_xyz(0.6, 0.6, 0)),
+            0.4,
+        ),
+        SdfCapsule::new(
+            Point3::from_xyz(-0.3, 0.2, 0),
+            Point3::from_xyz(-0.6, 1.2, 0.3),
+            0.25,
+        ),
+        0.3,
+    );
+
+    // a rounded box twisted around its middle
+    let twisted = Twist::new(SdfBox::new(Vec3::from_xyz(0.4, 1, 0.4), 0.08), 1.2);
+
+    // a cylinder with a rounded groove cut around it
+    let grooved = SmoothSubtract::new(SdfCylinder::new(0.6, 0.9), SdfTorus::new(0.7, 0.2), 0.1);
+
+    // a field of small pegs that goes on until the box cuts it off
+    let pegs = Repeat::new(SdfCylinder::new(0.08, 0.15), Vec3::from_xyz(0.4, 0, 0.4));
+
+    vec![
+        Box::new(floor),
+        Box::new(SdfObject::from_sdf_bounds_material(
+            Translate::new(blob, Vec3::from_xyz(-4, 0.8, 0)),
+            Point3::from_xyz(-5, 0, -1),
+            Point3::from_xyz(-2.8, 2.4, 1.2),
+            Lambertian::from_color(Color::from_rgb(0.8, 0.3, 0.3)),
+        )),
+        Box::new(
+            SdfObject::from_sdf_bounds_material(
+                Translate::new(twisted, Vec3::from_xyz(-1.6, 1, 0)),
+                Point3::from_xyz(-2.3, 0, -0.7),
+                Point3::from_xyz(-0.9, 2, 0.7),
+                Metal::from_color(Color::from_rgb(0.8, 0.8, 0.8), 0.1),
+            )
+            .with_step_scale(0.6),
+        ),
+        Box::new(
+            SdfObject::from_sdf_bounds_material(
+                Translate::new(Mandelbulb::new(8.0, 8), Vec3::from_xyz(1, 1.2, 0)),
+                Point3::from_xyz(-0.2, 0, -1.2),
+                Point3::from_xyz(2.2, 2.4, 1.2),
+                Lambertian::from_color(Color::from_rgb(0.9, 0.7, 0.4)),
+            )
+            .with_step_scale(0.8),
+        ),
+        Box::new(SdfObject::from_sdf_bounds_material(
+            Translate::new(grooved, Vec3::from_xyz(3.8, 0.9, 0)),
+            Point3::from_xyz(3.1, 0, -0.7),
+            Point3::from_xyz(4.5, 1.8, 0.7),
+            Dielectric::from_ir(1.5),
+        )),
+        Box::new(SdfObject::from_sdf_bounds_material(
+            Translate::new(pegs, Vec3::from_xyz(0, 0.15, 0)),
+            Point3::from_xyz(-5, 0, 1.6),
+            Point3::from_xyz(5, 0.3, 3.2),
+            Lambertian::from_color(Color::from_rgb(0.3, 0.5, 0.8)),
+        )),
+    ]
+}
+
+fn terrain(
+    heightmap_path: Option<&str>,
+    rng: &mut impl Rng,
+) -> error::Result<Vec<Box<dyn Hitable>>> {
+    let min = Point3::from_xyz(-24, 0, -24);
+    let max = Point3::from_xyz(24, 6, 24);
+    let grass = Lambertian::from_color(Color::from_rgb(0.35, 0.5, 0.25));
+
+    let heightfield = match heightmap_path {
+        Some(path) => Heightfield::load(path, min, max, grass)?,
+        None => {
+            // rolling hills made from fractal noise
+            let n = 512;
+            let perlin = Perlin::new(rng);
+            let values = (0..n * n)
+                .map(|i| {
+                    let p = Point3::from_xyz((i % n) as f64, 0.5, (i / n) as f64) / n as f64 * 6.0;
+                    (0.5 + 0.6 * perlin.fbm(p, 6)).clamp(0.0, 1.0) as f32
+                })
+                .collect();
+            Heightfield::from_values((n, n), values, min, max, grass)?
+        }
+    };
+
+    Ok(vec![
+        Box::new(heightfield),
+        // a lake that fills the valleys
+        Box::new(Plane::from_point_normal_material(
+            Point3::from_xyz(0, 2, 0),
+            Vec3::from_xyz(0, 1, 0),
+            Metal::from_color(Color::from_rgb(0.3, 0.45, 0.6), 0.02),
+        )),
+    ])
+}
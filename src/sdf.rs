@@ -0,0 +1,379 @@
+use crate::aabb::Aabb;
+use crate::hitable::{HitRecord, Hitable};
+use crate::material::Material;
+use crate::point3::{Point3, Vec3};
+use crate::ray::Ray;
+
+/// A shape described by the distance from any point to its surface
+pub trait Sdf: Send + Sync {
+    /// The signed distance from the point to the closest surface. Negative inside the shape.
+    /// It may be smaller than the real distance, but never larger, or sphere tracing will step through the surface
+    fn distance(&self, p: Point3) -> f64;
+
+    /// The gradient of the distance at the point, which points away from the surface.
+    /// Shapes that return None get their normals from finite differences of the distance
+    fn gradient(&self, _p: Point3) -> Option<Vec3> {
+        None
+    }
+}
+
+/// How close to the surface sphere tracing has to get before it counts as a hit
+const SURFACE_DISTANCE: f64 = 1e-4;
+
+/// The most steps sphere tracing takes along a ray before giving up
+const MAX_STEPS: usize = 512;
+
+/// An object made from a signed distance field, found by sphere tracing inside a bounding box.
+/// Whatever part of the shape sticks out of the box is cut off
+pub struct SdfObject<S: Sdf, M: Material> {
+    sdf: S,
+    bbox: Aabb,
+    step_scale: f64, // How much of the distance each step takes. Lower is slower but safer
+    material: M,
+}
+
+impl<S: Sdf, M: Material> SdfObject<S, M> {
+    /// Construct an object from a distance field, that is traced between the two corners of a box
+    pub fn from_sdf_bounds_material(sdf: S, min: Point3, max: Point3, material: M) -> Self {
+        Self {
+            sdf,
+            bbox: Aabb::new(min, max),
+            step_scale: 1.0,
+            material,
+        }
+    }
+
+    /// Takes shorter steps while tracing. Needed for operators like twist, that make the distance overestimate
+    pub fn with_step_scale(mut self, step_scale: f64) -> Self {
+        self.step_scale = step_scale.clamp(0.01, 1.0);
+        self
+    }
+
+    /// The normal at a point on the surface, from the gradient or from central differences
+    fn normal(&self, p: Point3) -> Vec3 {
+        if let Some(gradient) = self.sdf.gradient(p) {
+            return gradient.unit_vec();
+        }
+
+        let h = SURFACE_DISTANCE;
+        let difference =
+            |offset: Vec3| self.sdf.distance(p + offset) - self.sdf.distance(p - offset);
+        Vec3::from_xyz(
+            difference(Vec3::from_xyz(h, 0, 0)),
+            difference(Vec3::from_xyz(0, h, 0)),
+            difference(Vec3::from_xyz(0, 0, h)),
+        )
+        .unit_vec()
+    }
+}
+
+impl<S: Sdf, M: Material> Hitable for SdfObject<S, M> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let (t_enter, t_exit) = self.bbox.hit_interval(r, t_min, t_max)?;
+        let ray_length = r.direction().length();
+
+        // rays that start on the surface, like ones scattered off it, have to leave it before they can hit it again
+        let mut t = t_enter;
+        let mut left_surface = false;
+        for _ in 0..MAX_STEPS {
+            let distance = self.sdf.distance(r.at(t)).abs();
+            if distance < SURFACE_DISTANCE {
+                if left_surface && t > t_min {
+                    let point = r.at(t);
+                    return Some(HitRecord::new(point, t, self.normal(point), &self.material));
+                }
+            } else {
+                left_surface = true;
+            }
+
+            t += self.step_scale * distance.max(SURFACE_DISTANCE) / ray_length;
+            if t > t_exit {
+                return None;
+            }
+        }
+
+        None
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.bbox)
+    }
+}
+
+/// A sphere around (0,0,0)
+pub struct SdfSphere {
+    radius: f64,
+}
+
+impl SdfSphere {
+    pub fn new(radius: f64) -> Self {
+        Self { radius }
+    }
+}
+
+impl Sdf for SdfSphere {
+    fn distance(&self, p: Point3) -> f64 {
+        p.length() - self.radius
+    }
+
+    fn gradient(&self, p: Point3) -> Option<Vec3> {
+        Some(p)
+    }
+}
+
+/// A box around (0,0,0) with rounded edges
+pub struct SdfBox {
+    half_size: Vec3, // The distance from the center to the sides along each axis, including the rounding
+    rounding: f64,   // The radius of the rounded edges
+}
+
+impl SdfBox {
+    pub fn new(half_size: Vec3, rounding: f64) -> Self {
+        Self {
+            half_size,
+            rounding,
+        }
+    }
+}
+
+impl Sdf for SdfBox {
+    fn distance(&self, p: Point3) -> f64 {
+        let q =
+            Vec3::from_xyz(p.x().abs(), p.y().abs(), p.z().abs()) - self.half_size + self.rounding;
+        let outside = Vec3::from_xyz(q.x().max(0.0), q.y().max(0.0), q.z().max(0.0)).length();
+        let inside = q.x().max(q.y()).max(q.z()).min(0.0);
+        outside + inside - self.rounding
+    }
+}
+
+/// A donut around the y-axis, centered at (0,0,0)
+pub struct SdfTorus {
+    major_radius: f64, // The distance from the center to the middle of the tube
+    minor_radius: f64, // The radius of the tube
+}
+
+impl SdfTorus {
+    pub fn new(major_radius: f64, minor_radius: f64) -> Self {
+        Self {
+            major_radius,
+            minor_radius,
+        }
+    }
+}
+
+impl Sdf for SdfTorus {
+    fn distance(&self, p: Point3) -> f64 {
+        let ring = (p.x().powi(2) + p.z().powi(2)).sqrt() - self.major_radius;
+        (ring.powi(2) + p.y().powi(2)).sqrt() - self.minor_radius
+    }
+}
+
+/// A cylinder with rounded ends around the line between two points
+pub struct SdfCapsule {
+    a: Point3,
+    b: Point3,
+    radius: f64,
+}
+
+impl SdfCapsule {
+    pub fn new(a: Point3, b: Point3, radius: f64) -> Self {
+        Self { a, b, radius }
+    }
+}
+
+impl Sdf for SdfCapsule {
+    fn distance(&self, p: Point3) -> f64 {
+        let (pa, ba) = (p - self.a, self.b - self.a);
+        let h = (pa.dot_product(&ba) / ba.length_squared()).clamp(0.0, 1.0);
+        (pa - ba * h).length() - self.radius
+    }
+}
+
+/// A capped cylinder around the y-axis, centered at (0,0,0)
+pub struct SdfCylinder {
+    radius: f64,
+    half_height: f64,
+}
+
+impl SdfCylinder {
+    pub fn new(radius: f64, half_height: f64) -> Self {
+        Self {
+            radius,
+            half_height,
+        }
+    }
+}
+
+impl Sdf for SdfCylinder {
+    fn distance(&self, p: Point3) -> f64 {
+        let dx = (p.x().powi(2) + p.z().powi(2)).sqrt() - self.radius;
+        let dy = p.y().abs() - self.half_height;
+        dx.max(dy).min(0.0) + (dx.max(0.0).powi(2) + dy.max(0.0).powi(2)).sqrt()
+    }
+}
+
+/// The mandelbulb fractal around (0,0,0), with a radius of a bit more than 1.
+/// The distance is an estimate, so it should be traced with a step scale below 1
+pub struct Mandelbulb {
+    power: f64,
+    iterations: u32, // More iterations give more detail, but are slower
+}
+
+impl Mandelbulb {
+    pub fn new(power: f64, iterations: u32) -> Self {
+        Self { power, iterations }
+    }
+}
+
+impl Sdf for Mandelbulb {
+    fn distance(&self, p: Point3) -> f64 {
+        let mut z = p;
+        let mut dr = 1.0;
+        let mut r = z.length();
+
+        for _ in 0..self.iterations {
+            if r > 2.0 {
+                break;
+            }
+
+            // raise z to the power in spherical coordinates, and add the starting point
+            let theta = (z.z() / r).acos() * self.power;
+            let phi = z.y().atan2(z.x()) * self.power;
+            dr = r.powf(self.power - 1.0) * self.power * dr + 1.0;
+            z = Vec3::from_xyz(
+                theta.sin() * phi.cos(),
+                theta.sin() * phi.sin(),
+                theta.cos(),
+            ) * r.powf(self.power)
+                + p;
+            r = z.length();
+        }
+
+        0.5 * r.ln() * r / dr
+    }
+}
+
+/// Moves a shape by the offset
+pub struct Translate<S: Sdf> {
+    sdf: S,
+    offset: Vec3,
+}
+
+impl<S: Sdf> Translate<S> {
+    pub fn new(sdf: S, offset: Vec3) -> Self {
+        Self { sdf, offset }
+    }
+}
+
+impl<S: Sdf> Sdf for Translate<S> {
+    fn distance(&self, p: Point3) -> f64 {
+        self.sdf.distance(p - self.offset)
+    }
+
+    fn gradient(&self, p: Point3) -> Option<Vec3> {
+        self.sdf.gradient(p - self.offset)
+    }
+}
+
+/// Joins two shapes, and blends them together where they are closer than k to each other
+pub struct SmoothUnion<A: Sdf, B: Sdf> {
+    a: A,
+    b: B,
+    k: f64, // How far the blending reaches. 0 is a sharp union
+}
+
+impl<A: Sdf, B: Sdf> SmoothUnion<A, B> {
+    pub fn new(a: A, b: B, k: f64) -> Self {
+        Self { a, b, k }
+    }
+}
+
+impl<A: Sdf, B: Sdf> Sdf for SmoothUnion<A, B> {
+    fn distance(&self, p: Point3) -> f64 {
+        let (da, db) = (self.a.distance(p), self.b.distance(p));
+        if self.k <= 0.0 {
+            return da.min(db);
+        }
+
+        let h = (0.5 + 0.5 * (db - da) / self.k).clamp(0.0, 1.0);
+        db * (1.0 - h) + da * h - self.k * h * (1.0 - h)
+    }
+}
+
+/// Cuts the second shape out of the first, and rounds the cut edges within a distance of k
+pub struct SmoothSubtract<A: Sdf, B: Sdf> {
+    a: A,
+    b: B,
+    k: f64, // How far the rounding reaches. 0 is a sharp cut
+}
+
+impl<A: Sdf, B: Sdf> SmoothSubtract<A, B> {
+    pub fn new(a: A, b: B, k: f64) -> Self {
+        Self { a, b, k }
+    }
+}
+
+impl<A: Sdf, B: Sdf> Sdf for SmoothSubtract<A, B> {
+    fn distance(&self, p: Point3) -> f64 {
+        let (da, db) = (self.a.distance(p), self.b.distance(p));
+        if self.k <= 0.0 {
+            return da.max(-db);
+        }
+
+        let h = (0.5 - 0.5 * (da + db) / self.k).clamp(0.0, 1.0);
+        da * (1.0 - h) - db * h + self.k * h * (1.0 - h)
+    }
+}
+
+/// Twists a shape around the y-axis. The distance gets overestimated, so it should be traced with a step scale below 1
+pub struct Twist<S: Sdf> {
+    sdf: S,
+    rate: f64, // How many radians the shape is turned pr unit of height
+}
+
+impl<S: Sdf> Twist<S> {
+    pub fn new(sdf: S, rate: f64) -> Self {
+        Self { sdf, rate }
+    }
+}
+
+impl<S: Sdf> Sdf for Twist<S> {
+    fn distance(&self, p: Point3) -> f64 {
+        let (sin, cos) = (self.rate * p.y()).sin_cos();
+        self.sdf.distance(Point3::from_xyz(
+            cos * p.x() - sin * p.z(),
+            p.y(),
+            sin * p.x() + cos * p.z(),
+        ))
+    }
+}
+
+/// Repeats a shape forever in a grid. The shape should fit inside one cell of the grid
+pub struct Repeat<S: Sdf> {
+    sdf: S,
+    period: Vec3, // The size of a cell along each axis. 0 doesn't repeat along that axis
+}
+
+impl<S: Sdf> Repeat<S> {
+    pub fn new(sdf: S, period: Vec3) -> Self {
+        Self { sdf, period }
+    }
+}
+
+impl<S: Sdf> Sdf for Repeat<S> {
+    fn distance(&self, p: Point3) -> f64 {
+        // move the point into the cell around (0,0,0)
+        let wrap = |x: f64, period: f64| {
+            if period > 0.0 {
+                x - period * (x / period).round()
+            } else {
+                x
+            }
+        };
+        self.sdf.distance(Point3::from_xyz(
+            wrap(p.x(), self.period.x()),
+            wrap(p.y(), self.period.y()),
+            wrap(p.z(), self.period.z()),
+        ))
+    }
+}
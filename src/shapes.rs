@@ -0,0 +1,599 @@
+use std::f64::consts::PI;
+
+use crate::aabb::Aabb;
+use crate::hitable::{HitRecord, Hitable};
+use crate::material::Material;
+use crate::point3::{Point3, Vec3};
+use crate::ray::Ray;
+
+/// The closest hit found so far while checking the different parts of a shape
+struct Closest {
+    t: f64,
+    normal: Vec3,
+    uv: (f64, f64),
+}
+
+impl Closest {
+    /// Keeps the hit if it's in the interval and closer than the current one
+    fn consider(
+        closest: &mut Option<Closest>,
+        t: f64,
+        t_min: f64,
+        t_max: f64,
+        surface: impl FnOnce() -> (Vec3, (f64, f64)),
+    ) {
+        if t <= t_min || t_max <= t || closest.as_ref().is_some_and(|c| c.t <= t) {
+            return;
+        }
+
+        let (normal, uv) = surface();
+        *closest = Some(Closest { t, normal, uv });
+    }
+
+    fn into_record<'a>(self, r: &Ray, material: &'a dyn Material) -> HitRecord<'a> {
+        HitRecord::new(r.at(self.t), self.t, self.normal, material).with_uv(self.uv.0, self.uv.1)
+    }
+}
+
+/// Returns both roots of a x² + b x + c = 0, smallest first
+fn solve_quadratic(a: f64, b: f64, c: f64) -> Option<(f64, f64)> {
+    if a.abs() < 1e-12 {
+        // the equation is linear
+        if b.abs() < 1e-12 {
+            return None;
+        }
+        return Some((-c / b, -c / b));
+    }
+
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+
+    let sqrt = discriminant.sqrt();
+    let (t0, t1) = ((-b - sqrt) / (2.0 * a), (-b + sqrt) / (2.0 * a));
+    Some((t0.min(t1), t0.max(t1)))
+}
+
+/// The angle around the y-axis, going from 0 to 1
+fn angle_around_y(p: Vec3) -> f64 {
+    0.5 + p.z().atan2(p.x()) / (2.0 * PI)
+}
+
+/// A box with its sides aligned with the axes. It can be rotated by wrapping it in a Transformed
+pub struct BoxShape<M: Material> {
+    min: Point3, // The corner with the smallest coordinates
+    max: Point3, // The corner with the largest coordinates
+    material: M,
+}
+
+impl<M: Material> BoxShape<M> {
+    /// Construct a box from two of its opposite corners
+    pub fn from_corners_material(a: Point3, b: Point3, material: M) -> Self {
+        let corners = Aabb::new(a, b).corners();
+
+        Self {
+            min: corners[0],
+            max: corners[7],
+            material,
+        }
+    }
+}
+
+impl<M: Material> Hitable for BoxShape<M> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        // slab test, that keeps track of which side the ray enters and leaves through
+        let (mut t_near, mut t_far) = (f64::NEG_INFINITY, f64::INFINITY);
+        let (mut near_axis, mut far_axis) = (0, 0);
+        for axis in 0..3 {
+            let inv_d = 1.0 / r.direction().axis(axis);
+            let mut t0 = (self.min.axis(axis) - r.origin().axis(axis)) * inv_d;
+            let mut t1 = (self.max.axis(axis) - r.origin().axis(axis)) * inv_d;
+            if inv_d < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+
+            if t0 > t_near {
+                (t_near, near_axis) = (t0, axis);
+            }
+            if t1 < t_far {
+                (t_far, far_axis) = (t1, axis);
+            }
+        }
+        if t_far < t_near {
+            return None;
+        }
+
+        let mut closest = None;
+        for (t, axis) in [(t_near, near_axis), (t_far, far_axis)] {
+            Closest::consider(&mut closest, t, t_min, t_max, || {
+                let p = r.at(t);
+                let center = (self.min + self.max) * 0.5;
+
+                // the normal points out through the side that was hit
+                let mut normal = [0.0; 3];
+                normal[axis] = (p.axis(axis) - center.axis(axis)).signum();
+
+                // the side is mapped with the two other axes
+                let (a, b) = ((axis + 1) % 3, (axis + 2) % 3);
+                let uv = (
+                    (p.axis(a) - self.min.axis(a)) / (self.max.axis(a) - self.min.axis(a)),
+                    (p.axis(b) - self.min.axis(b)) / (self.max.axis(b) - self.min.axis(b)),
+                );
+                (Vec3::from_xyz(normal[0], normal[1], normal[2]), uv)
+            });
+        }
+
+        closest.map(|c| c.into_record(r, &self.material))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(Aabb::new(self.min, self.max))
+    }
+}
+
+/// A cylinder standing upright on its base. It can be tilted by wrapping it in a Transformed
+pub struct Cylinder<M: Material> {
+    base: Point3, // The center of the bottom
+    radius: f64,
+    height: f64,
+    capped: bool, // Whether the top and bottom are closed
+    material: M,
+}
+
+impl<M: Material> Cylinder<M> {
+    /// Construct a closed cylinder going up from the center of its base
+    pub fn from_base_radius_height_material(
+        base: Point3,
+        radius: impl Into<f64>,
+        height: impl Into<f64>,
+        material: M,
+    ) -> Self {
+        Self {
+            base,
+            radius: radius.into(),
+            height: height.into(),
+            capped: true,
+            material,
+        }
+    }
+
+    /// Removes the top and bottom, which makes the cylinder a tube
+    pub fn open(mut self) -> Self {
+        self.capped = false;
+        self
+    }
+}
+
+impl<M: Material> Hitable for Cylinder<M> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let o = r.origin() - self.base;
+        let d = r.direction();
+        let mut closest = None;
+
+        // the side, where x² + z² = radius²
+        if let Some((t0, t1)) = solve_quadratic(
+            d.x().powi(2) + d.z().powi(2),
+            2.0 * (o.x() * d.x() + o.z() * d.z()),
+            o.x().powi(2) + o.z().powi(2) - self.radius.powi(2),
+        ) {
+            for t in [t0, t1] {
+                let p = o + d * t;
+                if (0.0..=self.height).contains(&p.y()) {
+                    Closest::consider(&mut closest, t, t_min, t_max, || {
+                        let normal = Vec3::from_xyz(p.x(), 0, p.z()) / self.radius;
+                        (normal, (angle_around_y(p), p.y() / self.height))
+                    });
+                }
+            }
+        }
+
+        if self.capped {
+            for (y, normal_y) in [(0.0, -1.0), (self.height, 1.0)] {
+                let t = (y - o.y()) / d.y();
+                let p = o + d * t;
+                let distance = (p.x().powi(2) + p.z().powi(2)).sqrt();
+                if t.is_finite() && distance <= self.radius {
+                    Closest::consider(&mut closest, t, t_min, t_max, || {
+                        let normal = Vec3::from_xyz(0, normal_y, 0);
+                        (normal, (angle_around_y(p), distance / self.radius))
+                    });
+                }
+            }
+        }
+
+        closest.map(|c| c.into_record(r, &self.material))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let reach = Vec3::from_xyz(self.radius, 0, self.radius);
+        Some(Aabb::new(
+            self.base - reach,
+            self.base + reach + Vec3::from_xyz(0, self.height, 0),
+        ))
+    }
+}
+
+/// A cone standing on its base, with the tip pointing up. It can be tilted by wrapping it in a Transformed
+pub struct Cone<M: Material> {
+    base: Point3, // The center of the bottom
+    radius: f64,  // The radius of the bottom
+    height: f64,
+    material: M,
+}
+
+impl<M: Material> Cone<M> {
+    /// Construct a closed cone going up from the center of its base
+    pub fn from_base_radius_height_material(
+        base: Point3,
+        radius: impl Into<f64>,
+        height: impl Into<f64>,
+        material: M,
+    ) -> Self {
+        Self {
+            base,
+            radius: radius.into(),
+            height: height.into(),
+            material,
+        }
+    }
+}
+
+impl<M: Material> Hitable for Cone<M> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let o = r.origin() - self.base;
+        let d = r.direction();
+        let k = self.radius / self.height; // How much the radius shrinks pr unit of height
+        let mut closest = None;
+
+        // the side, where x² + z² = (radius - k y)²
+        let radius_at_origin = self.radius - k * o.y();
+        if let Some((t0, t1)) = solve_quadratic(
+            d.x().powi(2) + d.z().powi(2) - (k * d.y()).powi(2),
+            2.0 * (o.x() * d.x() + o.z() * d.z() + k * d.y() * radius_at_origin),
+            o.x().powi(2) + o.z().powi(2) - radius_at_origin.powi(2),
+        ) {
+            for t in [t0, t1] {
+                let p = o + d * t;
+                if (0.0..=self.height).contains(&p.y()) {
+                    Closest::consider(&mut closest, t, t_min, t_max, || {
+                        let distance = (p.x().powi(2) + p.z().powi(2)).sqrt();
+                        let normal = Vec3::from_xyz(p.x(), k * distance, p.z()).unit_vec();
+                        (normal, (angle_around_y(p), p.y() / self.height))
+                    });
+                }
+            }
+        }
+
+        // the base
+        let t = -o.y() / d.y();
+        let p = o + d * t;
+        let distance = (p.x().powi(2) + p.z().powi(2)).sqrt();
+        if t.is_finite() && distance <= self.radius {
+            Closest::consider(&mut closest, t, t_min, t_max, || {
+                let normal = Vec3::from_xyz(0, -1, 0);
+                (normal, (angle_around_y(p), distance / self.radius))
+            });
+        }
+
+        closest.map(|c| c.into_record(r, &self.material))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let reach = Vec3::from_xyz(self.radius, 0, self.radius);
+        Some(Aabb::new(
+            self.base - reach,
+            self.base + reach + Vec3::from_xyz(0, self.height, 0),
+        ))
+    }
+}
+
+/// A donut lying flat, going around the y-axis. It can be rotated by wrapping it in a Transformed
+pub struct Torus<M: Material> {
+    center: Point3,
+    major_radius: f64, // The distance from the center to the middle of the tube
+    minor_radius: f64, // The radius of the tube
+    material: M,
+}
+
+impl<M: Material> Torus<M> {
+    pub fn from_center_radii_material(
+        center: Point3,
+        major_radius: impl Into<f64>,
+        minor_radius: impl Into<f64>,
+        material: M,
+    ) -> Self {
+        Self {
+            center,
+            major_radius: major_radius.into(),
+            minor_radius: minor_radius.into(),
+            material,
+        }
+    }
+}
+
+impl<M: Material> Hitable for Torus<M> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let (big_r, small_r) = (self.major_radius, self.minor_radius);
+
+        // the quartic is solved with a unit direction, starting close to the torus, to keep it precise
+        let length = r.direction().length();
+        let d = r.direction() / length;
+        let start = (-(r.origin() - self.center).dot_product(&d) - big_r - small_r).max(0.0);
+        let o = r.origin() - self.center + d * start;
+
+        // (|p|² + R² - r²)² = 4 R² (x² + z²), with p = o + s d
+        let n = o.dot_product(&d);
+        let q = o.length_squared() + big_r.powi(2) - small_r.powi(2);
+        let four_r2 = 4.0 * big_r.powi(2);
+        let roots = solve_quartic(
+            4.0 * n,
+            4.0 * n * n + 2.0 * q - four_r2 * (d.x().powi(2) + d.z().powi(2)),
+            4.0 * n * q - 2.0 * four_r2 * (o.x() * d.x() + o.z() * d.z()),
+            q * q - four_r2 * (o.x().powi(2) + o.z().powi(2)),
+        );
+
+        let mut closest = None;
+        for s in roots {
+            let t = (s + start) / length;
+            Closest::consider(&mut closest, t, t_min, t_max, || {
+                let p = o + d * s;
+                let ring = (p.x().powi(2) + p.z().powi(2)).sqrt();
+
+                // the normal points away from the closest point on the ring in the middle of the tube
+                let center_of_tube = Vec3::from_xyz(p.x(), 0, p.z()) * (big_r / ring);
+                let normal = (p - center_of_tube).unit_vec();
+                let around_tube = 0.5 + p.y().atan2(ring - big_r) / (2.0 * PI);
+                (normal, (angle_around_y(p), around_tube))
+            });
+        }
+
+        closest.map(|c| c.into_record(r, &self.material))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let outer = self.major_radius + self.minor_radius;
+        let reach = Vec3::from_xyz(outer, self.minor_radius, outer);
+        Some(Aabb::new(self.center - reach, self.center + reach))
+    }
+}
+
+/// A cylinder with half spheres on the ends, around the line between two points
+pub struct Capsule<M: Material> {
+    a: Point3, // The center of one end
+    b: Point3, // The center of the other end
+    radius: f64,
+    material: M,
+}
+
+impl<M: Material> Capsule<M> {
+    pub fn from_ends_radius_material(
+        a: Point3,
+        b: Point3,
+        radius: impl Into<f64>,
+        material: M,
+    ) -> Self {
+        Self {
+            a,
+            b,
+            radius: radius.into(),
+            material,
+        }
+    }
+}
+
+impl<M: Material> Hitable for Capsule<M> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let axis = self.b - self.a;
+        let length = axis.length();
+        let w = axis / length;
+        let o = r.origin() - self.a;
+        let d = r.direction();
+
+        // how far along the axis a point is, measured from a
+        let along = |t: f64| (o + d * t).dot_product(&w);
+        let mut candidates = [f64::NAN; 6];
+
+        // the side is the part of an infinite cylinder around the axis that is between the ends
+        let d_perp = d - w * d.dot_product(&w);
+        let o_perp = o - w * o.dot_product(&w);
+        if let Some((t0, t1)) = solve_quadratic(
+            d_perp.length_squared(),
+            2.0 * d_perp.dot_product(&o_perp),
+            o_perp.length_squared() - self.radius.powi(2),
+        ) {
+            for (i, t) in [t0, t1].into_iter().enumerate() {
+                if (0.0..=length).contains(&along(t)) {
+                    candidates[i] = t;
+                }
+            }
+        }
+
+        // the half spheres are the parts of the spheres at the ends that are outside the side
+        for (i, end) in [Vec3::new(), axis].into_iter().enumerate() {
+            let on_end = |along: f64| if i == 0 { along < 0.0 } else { along > length };
+            let oc = o - end;
+            if let Some((t0, t1)) = solve_quadratic(
+                d.length_squared(),
+                2.0 * oc.dot_product(&d),
+                oc.length_squared() - self.radius.powi(2),
+            ) {
+                for (j, t) in [t0, t1].into_iter().enumerate() {
+                    if on_end(along(t)) {
+                        candidates[2 + 2 * i + j] = t;
+                    }
+                }
+            }
+        }
+
+        let mut closest = None;
+        for t in candidates.into_iter().filter(|t| !t.is_nan()) {
+            Closest::consider(&mut closest, t, t_min, t_max, || {
+                let p = o + d * t;
+
+                // the normal points away from the closest point on the axis
+                let projection = p.dot_product(&w).clamp(0.0, length);
+                let normal = (p - w * projection) / self.radius;
+
+                let (tangent, bitangent) = w.orthonormal_basis();
+                let angle = p.dot_product(&bitangent).atan2(p.dot_product(&tangent));
+                let v = (p.dot_product(&w) + self.radius) / (length + 2.0 * self.radius);
+                (normal, (0.5 + angle / (2.0 * PI), v))
+            });
+        }
+
+        closest.map(|c| c.into_record(r, &self.material))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(Aabb::new(self.a, self.b).pad(self.radius))
+    }
+}
+
+/// Returns the real roots of x³ + a x² + b x + c = 0
+fn solve_cubic(a: f64, b: f64, c: f64) -> Vec<f64> {
+    // substitute x = y - a/3 to get y³ + p y + q = 0
+    let p = (b - a * a / 3.0) / 3.0;
+    let q = (2.0 * a * a * a / 27.0 - a * b / 3.0 + c) / 2.0;
+    let discriminant = q * q + p * p * p;
+
+    let roots = if discriminant.abs() < 1e-12 {
+        if q.abs() < 1e-12 {
+            vec![0.0]
+        } else {
+            let u = (-q).cbrt();
+            vec![2.0 * u, -u]
+        }
+    } else if discriminant < 0.0 {
+        // three real roots, found with trigonometry
+        let phi = (-q / (-p * p * p).sqrt()).clamp(-1.0, 1.0).acos() / 3.0;
+        let t = 2.0 * (-p).sqrt();
+        vec![
+            t * phi.cos(),
+            -t * (phi + PI / 3.0).cos(),
+            -t * (phi - PI / 3.0).cos(),
+        ]
+    } else {
+        let sqrt = discriminant.sqrt();
+        vec![(sqrt - q).cbrt() - (sqrt + q).cbrt()]
+    };
+
+    roots.into_iter().map(|y| y - a / 3.0).collect()
+}
+
+/// Returns the real roots of x⁴ + a x³ + b x² + c x + d = 0, using Ferrari's method.
+/// The roots are polished with a few newton steps afterwards, since the closed form loses precision
+fn solve_quartic(a: f64, b: f64, c: f64, d: f64) -> Vec<f64> {
+    // substitute x = y - a/4 to get y⁴ + p y² + q y + r = 0
+    let a2 = a * a;
+    let p = -3.0 / 8.0 * a2 + b;
+    let q = a2 * a / 8.0 - a * b / 2.0 + c;
+    let r = -3.0 / 256.0 * a2 * a2 + a2 * b / 16.0 - a * c / 4.0 + d;
+
+    let mut roots = if r.abs() < 1e-12 {
+        // y (y³ + p y + q) = 0
+        let mut roots = solve_cubic(0.0, p, q);
+        roots.push(0.0);
+        roots
+    } else {
+        // split the quartic into two quadratics with a root of the resolvent cubic. The largest root is used,
+        // since it is the one that makes u and v non-negative whenever the quartic has real roots
+        let z = solve_cubic(-p / 2.0, -r, r * p / 2.0 - q * q / 8.0)
+            .into_iter()
+            .fold(f64::NEG_INFINITY, f64::max);
+        let u = z * z - r;
+        let v = 2.0 * z - p;
+        if u < -1e-12 || v < -1e-12 {
+            return Vec::new();
+        }
+        let (u, v) = (u.max(0.0).sqrt(), v.max(0.0).sqrt());
+        let v = if q < 0.0 { -v } else { v };
+
+        let mut roots = Vec::with_capacity(4);
+        for (b, c) in [(v, z - u), (-v, z + u)] {
+            if let Some((y0, y1)) = solve_quadratic(1.0, b, c) {
+                roots.extend([y0, y1]);
+            }
+        }
+        roots
+    };
+
+    for x in roots.iter_mut() {
+        *x -= a / 4.0;
+        for _ in 0..2 {
+            let f = (((*x + a) * *x + b) * *x + c) * *x + d;
+            let df = ((4.0 * *x + 3.0 * a) * *x + 2.0 * b) * *x + c;
+            if df.abs() > 1e-12 {
+                *x -= f / df;
+            }
+        }
+    }
+
+    roots
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The coefficients after the leading 1 of the polynomial with the given roots
+    fn coefficients(roots: &[f64]) -> Vec<f64> {
+        let mut poly = vec![1.0];
+        for root in roots {
+            let mut next = vec![0.0; poly.len() + 1];
+            for (i, c) in poly.iter().enumerate() {
+                next[i] += c;
+                next[i + 1] -= c * root;
+            }
+            poly = next;
+        }
+        poly[1..].to_vec()
+    }
+
+    /// Checks that the expected roots are found, in increasing order, and no others. Double roots count once
+    fn assert_roots(mut found: Vec<f64>, expected: &[f64]) {
+        found.sort_by(f64::total_cmp);
+        found.dedup_by(|a, b| (*a - *b).abs() < 1e-6);
+        assert_eq!(found.len(), expected.len(), "found {:?}", found);
+        for (root, expected) in found.iter().zip(expected) {
+            assert!((root - expected).abs() < 1e-6, "found {:?}", found);
+        }
+    }
+
+    #[test]
+    fn cubic_finds_every_real_root() {
+        let c = coefficients(&[1.0, 2.0, 3.0]);
+        assert_roots(solve_cubic(c[0], c[1], c[2]), &[1.0, 2.0, 3.0]);
+
+        // x³ - 8 has one real root and two complex ones
+        assert_roots(solve_cubic(0.0, 0.0, -8.0), &[2.0]);
+
+        let c = coefficients(&[1.0, 1.0, -2.0]);
+        assert_roots(solve_cubic(c[0], c[1], c[2]), &[-2.0, 1.0]);
+    }
+
+    #[test]
+    fn quartic_finds_every_real_root() {
+        // a ray straight through the middle of a torus
+        let roots = [-4.0, -3.5, 3.5, 4.0];
+        let c = coefficients(&roots);
+        assert_roots(solve_quartic(c[0], c[1], c[2], c[3]), &roots);
+
+        let c = coefficients(&[1.0, 2.0, -3.0, 0.5]);
+        assert_roots(
+            solve_quartic(c[0], c[1], c[2], c[3]),
+            &[-3.0, 0.5, 1.0, 2.0],
+        );
+
+        // a root at the mean of the roots takes the shortcut where the depressed quartic has a root at 0
+        let c = coefficients(&[0.0, 1.0, 2.0, -3.0]);
+        assert_roots(
+            solve_quartic(c[0], c[1], c[2], c[3]),
+            &[-3.0, 0.0, 1.0, 2.0],
+        );
+
+        // (x² + 1)(x - 1)(x - 2) only has two real roots, and x⁴ + 1 has none
+        assert_roots(solve_quartic(-3.0, 3.0, -3.0, 2.0), &[1.0, 2.0]);
+        assert_roots(solve_quartic(0.0, 0.0, 0.0, 1.0), &[]);
+    }
+}
@@ -0,0 +1,248 @@
+use std::ops::{Add, Mul};
+
+use crate::point3::Color;
+
+/// The shortest wavelength (in nanometers) that is traced in spectral mode
+pub const LAMBDA_MIN: f64 = 380.0;
+/// The longest wavelength (in nanometers) that is traced in spectral mode
+pub const LAMBDA_MAX: f64 = 720.0;
+/// The amount of wavelengths that are carried along each path
+pub const SPECTRUM_SAMPLES: usize = 4;
+
+lazy_static! {
+    /// The linear rgb value of a constant spectrum of 1. Used to white balance the spectral output,
+    /// so that white surfaces under a white sky stay white
+    static ref WHITE_RGB: Color = {
+        let steps = 1000;
+        let step_size = (LAMBDA_MAX - LAMBDA_MIN) / steps as f64;
+        let mut xyz = [0.0; 3];
+        for i in 0..steps {
+            let lambda = LAMBDA_MIN + (i as f64 + 0.5) * step_size;
+            let cmf = color_matching(lambda);
+            for c in 0..3 {
+                xyz[c] += cmf[c] * step_size;
+            }
+        }
+        xyz_to_linear_srgb(xyz)
+    };
+}
+
+/// The wavelengths that a single path carries. The first wavelength is the hero wavelength,
+/// which decides the direction of the path. The others are evenly rotated around it.
+#[derive(Clone, Copy)]
+pub struct SampledWavelengths {
+    lambda: [f64; SPECTRUM_SAMPLES],
+    secondary_terminated: bool, // Set when a wavelength dependent event only followed the hero wavelength
+}
+
+impl SampledWavelengths {
+    /// Samples the wavelengths for a path from a uniform random number between 0 and 1
+    pub fn sample(u: f64) -> Self {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let hero = LAMBDA_MIN + u * range;
+        let mut lambda = [hero; SPECTRUM_SAMPLES];
+        for (i, l) in lambda.iter_mut().enumerate().skip(1) {
+            *l = hero + range * i as f64 / SPECTRUM_SAMPLES as f64;
+            if *l > LAMBDA_MAX {
+                *l -= range;
+            }
+        }
+
+        Self {
+            lambda,
+            secondary_terminated: false,
+        }
+    }
+
+    /// The wavelength that decides the direction of the path
+    pub fn hero(&self) -> f64 {
+        self.lambda[0]
+    }
+
+    /// returns the wavelengths of the path
+    pub fn lambda(&self) -> [f64; SPECTRUM_SAMPLES] {
+        self.lambda
+    }
+
+    /// Stops the secondary wavelengths from contributing. Has to be called when the path
+    /// hits something where the direction depends on the wavelength, like dispersive glass
+    pub fn terminate_secondary(&mut self) {
+        self.secondary_terminated = true;
+    }
+
+    /// Converts radiance values, one for each wavelength, to a linear rgb color
+    pub fn radiance_to_color(&self, radiance: &SampledSpectrum) -> Color {
+        let used = if self.secondary_terminated {
+            1
+        } else {
+            SPECTRUM_SAMPLES
+        };
+
+        // Monte Carlo estimate of the integral of radiance * color matching function.
+        // The wavelengths are uniformly distributed, so the pdf is 1 / (LAMBDA_MAX - LAMBDA_MIN)
+        let mut xyz = [0.0; 3];
+        for i in 0..used {
+            let cmf = color_matching(self.lambda[i]);
+            for c in 0..3 {
+                xyz[c] += radiance.values[i] * cmf[c] * (LAMBDA_MAX - LAMBDA_MIN) / used as f64;
+            }
+        }
+
+        let rgb = xyz_to_linear_srgb(xyz);
+        Color::from_rgb(
+            (rgb.r() / WHITE_RGB.r()).max(0.0),
+            (rgb.g() / WHITE_RGB.g()).max(0.0),
+            (rgb.b() / WHITE_RGB.b()).max(0.0),
+        )
+    }
+}
+
+/// A value for each of the wavelengths in SampledWavelengths
+#[derive(Clone, Copy)]
+pub struct SampledSpectrum {
+    values: [f64; SPECTRUM_SAMPLES],
+}
+
+impl SampledSpectrum {
+    /// Creates a spectrum with the same value at every wavelength
+    pub fn constant(value: f64) -> Self {
+        Self {
+            values: [value; SPECTRUM_SAMPLES],
+        }
+    }
+
+    /// Uplifts an rgb color to a spectrum, and evaluates it at the given wavelengths
+    pub fn from_color(color: &Color, wavelengths: &SampledWavelengths) -> Self {
+        let mut values = [0.0; SPECTRUM_SAMPLES];
+        for (value, lambda) in values.iter_mut().zip(wavelengths.lambda()) {
+            *value = rgb_to_spectrum(color, lambda);
+        }
+        Self { values }
+    }
+}
+
+impl Add for SampledSpectrum {
+    type Output = SampledSpectrum;
+
+    fn add(self, other: SampledSpectrum) -> Self {
+        let mut values = self.values;
+        for (v, o) in values.iter_mut().zip(other.values) {
+            *v += o;
+        }
+        Self { values }
+    }
+}
+
+impl Mul for SampledSpectrum {
+    type Output = SampledSpectrum;
+
+    fn mul(self, other: SampledSpectrum) -> Self {
+        let mut values = self.values;
+        for (v, o) in values.iter_mut().zip(other.values) {
+            *v *= o;
+        }
+        Self { values }
+    }
+}
+
+impl Mul<f64> for SampledSpectrum {
+    type Output = SampledSpectrum;
+
+    fn mul(self, other: f64) -> Self {
+        let mut values = self.values;
+        for v in values.iter_mut() {
+            *v *= other;
+        }
+        Self { values }
+    }
+}
+
+/// Smits' basis spectra for rgb to spectrum conversion. Each spectrum has 10 evenly spaced bins from 380nm to 720nm.
+const SMITS_WHITE: [f64; 10] = [
+    1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000,
+];
+const SMITS_CYAN: [f64; 10] = [
+    0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000,
+];
+const SMITS_MAGENTA: [f64; 10] = [
+    1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959,
+];
+const SMITS_YELLOW: [f64; 10] = [
+    0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840,
+];
+const SMITS_RED: [f64; 10] = [
+    0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149,
+];
+const SMITS_GREEN: [f64; 10] = [
+    0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025,
+];
+const SMITS_BLUE: [f64; 10] = [
+    1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496,
+];
+
+/// Evaluates one of Smits' basis spectra at a wavelength, interpolating linearly between the bin centers
+fn smits_basis(basis: &[f64; 10], lambda: f64) -> f64 {
+    let bin_width = (LAMBDA_MAX - LAMBDA_MIN) / 10.0;
+    let x = ((lambda - LAMBDA_MIN) / bin_width - 0.5).clamp(0.0, 9.0);
+    let i = (x as usize).min(8);
+    let frac = x - i as f64;
+    basis[i] * (1.0 - frac) + basis[i + 1] * frac
+}
+
+/// Uplifts an rgb color to a smooth spectrum using Smits' method, and evaluates it at a wavelength
+pub fn rgb_to_spectrum(color: &Color, lambda: f64) -> f64 {
+    let (r, g, b) = (color.r(), color.g(), color.b());
+    let at = |basis: &[f64; 10]| smits_basis(basis, lambda);
+
+    if r <= g && r <= b {
+        let white = r * at(&SMITS_WHITE);
+        if g <= b {
+            white + (g - r) * at(&SMITS_CYAN) + (b - g) * at(&SMITS_BLUE)
+        } else {
+            white + (b - r) * at(&SMITS_CYAN) + (g - b) * at(&SMITS_GREEN)
+        }
+    } else if g <= r && g <= b {
+        let white = g * at(&SMITS_WHITE);
+        if r <= b {
+            white + (r - g) * at(&SMITS_MAGENTA) + (b - r) * at(&SMITS_BLUE)
+        } else {
+            white + (b - g) * at(&SMITS_MAGENTA) + (r - b) * at(&SMITS_RED)
+        }
+    } else {
+        let white = b * at(&SMITS_WHITE);
+        if r <= g {
+            white + (r - b) * at(&SMITS_YELLOW) + (g - r) * at(&SMITS_GREEN)
+        } else {
+            white + (g - b) * at(&SMITS_YELLOW) + (r - g) * at(&SMITS_RED)
+        }
+    }
+}
+
+/// Piecewise gaussian used by the analytic color matching functions
+fn piecewise_gaussian(lambda: f64, mu: f64, sigma_low: f64, sigma_high: f64) -> f64 {
+    let sigma = if lambda < mu { sigma_low } else { sigma_high };
+    (-0.5 * ((lambda - mu) / sigma).powi(2)).exp()
+}
+
+/// The CIE 1931 color matching functions, using the multi-lobe fit by Wyman, Sloan and Shirley
+pub fn color_matching(lambda: f64) -> [f64; 3] {
+    let x = 1.056 * piecewise_gaussian(lambda, 599.8, 37.9, 31.0)
+        + 0.362 * piecewise_gaussian(lambda, 442.0, 16.0, 26.7)
+        - 0.065 * piecewise_gaussian(lambda, 501.1, 20.4, 26.2);
+    let y = 0.821 * piecewise_gaussian(lambda, 568.8, 46.9, 40.5)
+        + 0.286 * piecewise_gaussian(lambda, 530.9, 16.3, 31.1);
+    let z = 1.217 * piecewise_gaussian(lambda, 437.0, 11.8, 36.0)
+        + 0.681 * piecewise_gaussian(lambda, 459.0, 26.0, 13.8);
+
+    [x, y, z]
+}
+
+/// Converts a CIE XYZ color to linear sRGB
+fn xyz_to_linear_srgb(xyz: [f64; 3]) -> Color {
+    let [x, y, z] = xyz;
+    Color::from_rgb(
+        3.2404542 * x - 1.5371385 * y - 0.4985314 * z,
+        -0.9692660 * x + 1.8760108 * y + 0.0415560 * z,
+        0.0556434 * x - 0.2040259 * y + 1.0572252 * z,
+    )
+}
@@ -1,3 +1,6 @@
+use std::f64::consts::PI;
+
+use crate::aabb::Aabb;
 use crate::hitable::{HitRecord, Hitable};
 use crate::material::Material;
 use crate::point3::Point3;
@@ -28,41 +31,119 @@ impl<M: Material> Sphere<M> {
 impl<M: Material> Hitable for Sphere<M> {
     /// Calculates if a ray hits the sphere, and returns a hitrecord if it is hit.
     /// Else it returns none.
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        // using quadratic formula to calculate intersections
-        let oc = r.origin() - self.center;
-        let a = r.direction().length_squared();
-        let half_b = oc.dot_product(&r.direction());
-        let c = oc.length_squared() - self.radius.powi(2);
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        hit_sphere(self.center, self.radius, &self.material, r, t_min, t_max)
+    }
 
-        let discriminant = half_b.powi(2) - a * c;
+    fn bounding_box(&self) -> Option<Aabb> {
+        let radius = self.radius.abs();
+        Some(Aabb::new(self.center - radius, self.center + radius))
+    }
+}
 
-        if discriminant < 0.0 {
-            return None;
-        }
+/// A sphere that moves in a straight line from one center to another while the camera shutter is open
+pub struct MovingSphere<M: Material> {
+    center0: Point3, // The center at time0
+    center1: Point3, // The center at time1
+    time0: f64,
+    time1: f64,
+    radius: f64,
+    material: M,
+}
 
-        // calculates the sqrt once, and saves the value
-        let discriminant_sqrt = discriminant.sqrt();
+impl<M: Material> MovingSphere<M> {
+    /// Construct a sphere that is at center0 at time0 and at center1 at time1
+    pub fn from_centers_radius_material(
+        (center0, time0): (Point3, f64),
+        (center1, time1): (Point3, f64),
+        radius: impl Into<f64>,
+        material: M,
+    ) -> Self {
+        MovingSphere {
+            center0,
+            center1,
+            time0,
+            time1,
+            radius: radius.into(),
+            material,
+        }
+    }
 
-        // detect the nearest hit:
-        let mut hit = (-half_b - discriminant_sqrt) / a;
-        if hit <= t_min || t_max <= hit {
-            // adding the sqrt is always further away
-            hit = (-half_b + discriminant_sqrt) / a;
-            if hit <= t_min || t_max <= hit {
-                return None;
-            }
+    /// The center of the sphere at a moment in time. Before time0 and after time1 the sphere stays where it
+    /// starts and ends, like keyframed transforms do, so it never leaves its bounding box
+    pub fn center(&self, time: f64) -> Point3 {
+        if self.time1 == self.time0 {
+            return self.center0;
         }
 
-        // Constructs the hitrecord
-        let point = r.at(hit);
-        let record = HitRecord::new(
-            point,
-            hit,
-            (point - self.center) / self.radius,
+        let progress = ((time - self.time0) / (self.time1 - self.time0)).clamp(0.0, 1.0);
+        self.center0 + (self.center1 - self.center0) * progress
+    }
+}
+
+impl<M: Material> Hitable for MovingSphere<M> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        hit_sphere(
+            self.center(r.time()),
+            self.radius,
             &self.material,
-        );
+            r,
+            t_min,
+            t_max,
+        )
+    }
+
+    /// The box surrounds the sphere everywhere along its path
+    fn bounding_box(&self) -> Option<Aabb> {
+        let radius = self.radius.abs();
+        let box0 = Aabb::new(self.center0 - radius, self.center0 + radius);
+        let box1 = Aabb::new(self.center1 - radius, self.center1 + radius);
+        Some(box0.surrounding(&box1))
+    }
+}
+
+/// Calculates if a ray hits a sphere, and returns a hitrecord if it is hit.
+/// Else it returns none.
+fn hit_sphere<'a>(
+    center: Point3,
+    radius: f64,
+    material: &'a dyn Material,
+    r: &Ray,
+    t_min: f64,
+    t_max: f64,
+) -> Option<HitRecord<'a>> {
+    // using quadratic formula to calculate intersections
+    let oc = r.origin() - center;
+    let a = r.direction().length_squared();
+    let half_b = oc.dot_product(&r.direction());
+    let c = oc.length_squared() - radius.powi(2);
 
-        Some(record)
+    let discriminant = half_b.powi(2) - a * c;
+
+    if discriminant < 0.0 {
+        return None;
     }
+
+    // calculates the sqrt once, and saves the value
+    let discriminant_sqrt = discriminant.sqrt();
+
+    // detect the nearest hit:
+    let mut hit = (-half_b - discriminant_sqrt) / a;
+    if hit <= t_min || t_max <= hit {
+        // adding the sqrt is always further away
+        hit = (-half_b + discriminant_sqrt) / a;
+        if hit <= t_min || t_max <= hit {
+            return None;
+        }
+    }
+
+    // Constructs the hitrecord
+    let point = r.at(hit);
+    let normal = (point - center) / radius;
+    let record = HitRecord::new(point, hit, normal, material).with_uv(
+        0.5 + normal.z().atan2(-normal.x()) / (2.0 * PI),
+        normal.y().clamp(-1.0, 1.0).acos() / PI,
+    );
+
+    Some(record)
 }
@@ -0,0 +1,235 @@
+use std::{cell::Cell, fs, path::Path, time::Duration};
+
+/// Why a path stopped bouncing around the scene
+#[derive(Clone, Copy)]
+pub enum Termination {
+    Escaped,  // The ray didn't hit anything and picked up the background
+    Absorbed, // The material didn't scatter the ray, like a light
+    MaxDepth, // The path reached the max amount of bounces
+    Blocked,  // The lens barrel blocked the camera ray before it left the camera
+}
+
+impl Termination {
+    const ALL: [Termination; 4] = [
+        Termination::Escaped,
+        Termination::Absorbed,
+        Termination::MaxDepth,
+        Termination::Blocked,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Termination::Escaped => "escaped",
+            Termination::Absorbed => "absorbed",
+            Termination::MaxDepth => "max_depth",
+            Termination::Blocked => "blocked",
+        }
+    }
+}
+
+/// Counters collected while rendering, to find out where the time of a render goes
+#[derive(Clone, Copy, Default)]
+pub struct RenderStats {
+    primary_rays: u64,   // Rays sent from the camera, one pr path
+    secondary_rays: u64, // Rays scattered off surfaces
+    terminations: [u64; 4],
+    bvh_nodes_visited: u64,
+    primitive_tests: u64, // Intersection tests against the objects in the leaves of the bvh and in lists
+    elapsed: Duration,
+}
+
+thread_local! {
+    // Every thread counts on its own, and the counts are added together when the render is done
+    static STATS: Cell<RenderStats> = Cell::new(RenderStats::default());
+}
+
+/// Changes the counters of the current thread
+fn record(change: impl FnOnce(&mut RenderStats)) {
+    STATS.with(|stats| {
+        let mut current = stats.get();
+        change(&mut current);
+        stats.set(current);
+    });
+}
+
+/// Counts a ray sent from the camera
+pub fn count_primary_ray() {
+    record(|stats| stats.primary_rays += 1);
+}
+
+/// Counts a ray scattered off a surface
+pub fn count_secondary_ray() {
+    record(|stats| stats.secondary_rays += 1);
+}
+
+/// Counts a path that stopped
+pub fn count_termination(termination: Termination) {
+    record(|stats| stats.terminations[termination as usize] += 1);
+}
+
+/// Counts a node of a bvh whose box was tested against a ray
+pub fn count_bvh_node() {
+    record(|stats| stats.bvh_nodes_visited += 1);
+}
+
+/// Counts an intersection test against an object
+pub fn count_primitive_test() {
+    record(|stats| stats.primitive_tests += 1);
+}
+
+/// The amount of rays the current thread has cast so far
+pub fn rays_cast() -> u64 {
+    STATS.with(|stats| stats.get().rays())
+}
+
+/// Returns the counters of the current thread and starts them over from 0
+pub fn take() -> RenderStats {
+    STATS.with(|stats| stats.take())
+}
+
+impl RenderStats {
+    /// Adds the counters of another thread to these
+    pub fn merge(&mut self, other: &RenderStats) {
+        self.primary_rays += other.primary_rays;
+        self.secondary_rays += other.secondary_rays;
+        for (count, other_count) in self.terminations.iter_mut().zip(other.terminations) {
+            *count += other_count;
+        }
+        self.bvh_nodes_visited += other.bvh_nodes_visited;
+        self.primitive_tests += other.primitive_tests;
+    }
+
+    /// The counters as a list of numbers, to send them to another process. The time isn't included
+    pub fn values(&self) -> [u64; 8] {
+        let [escaped, absorbed, max_depth, blocked] = self.terminations;
+        [
+            self.primary_rays,
+            self.secondary_rays,
+            escaped,
+            absorbed,
+            max_depth,
+            blocked,
+            self.bvh_nodes_visited,
+            self.primitive_tests,
+        ]
+    }
+
+    /// Creates the stats from the list of numbers made by values()
+    pub fn from_values(values: [u64; 8]) -> Self {
+        let [primary_rays, secondary_rays, escaped, absorbed, max_depth, blocked, bvh_nodes_visited, primitive_tests] =
+            values;
+        Self {
+            primary_rays,
+            secondary_rays,
+            terminations: [escaped, absorbed, max_depth, blocked],
+            bvh_nodes_visited,
+            primitive_tests,
+            elapsed: Duration::ZERO,
+        }
+    }
+
+    /// Sets how long the render took
+    pub fn set_elapsed(&mut self, elapsed: Duration) {
+        self.elapsed = elapsed;
+    }
+
+    /// returns the amount of rays cast, both from the camera and scattered
+    pub fn rays(&self) -> u64 {
+        self.primary_rays + self.secondary_rays
+    }
+
+    fn rays_pr_second(&self) -> f64 {
+        ratio(self.rays() as f64, self.elapsed.as_secs_f64())
+    }
+
+    /// The average amount of rays in a path, counting the camera ray
+    fn average_path_length(&self) -> f64 {
+        ratio(self.rays() as f64, self.primary_rays as f64)
+    }
+
+    fn bvh_nodes_pr_ray(&self) -> f64 {
+        ratio(self.bvh_nodes_visited as f64, self.rays() as f64)
+    }
+
+    fn primitive_tests_pr_ray(&self) -> f64 {
+        ratio(self.primitive_tests as f64, self.rays() as f64)
+    }
+
+    /// A few lines that sum up the stats, for printing after a render
+    pub fn summary(&self) -> String {
+        let terminations = Termination::ALL
+            .iter()
+            .map(|termination| {
+                format!(
+                    "\n  {:<10} {}",
+                    termination.name(),
+                    self.terminations[*termination as usize]
+                )
+            })
+            .collect::<String>();
+
+        format!(
+            "Render stats\n\
+             primary rays:           {}\n\
+             secondary rays:         {}\n\
+             rays pr second:         {:.0}\n\
+             average path length:    {:.2}\n\
+             bvh nodes pr ray:       {:.2}\n\
+             primitive tests pr ray: {:.2}\n\
+             paths terminated by:{}",
+            self.primary_rays,
+            self.secondary_rays,
+            self.rays_pr_second(),
+            self.average_path_length(),
+            self.bvh_nodes_pr_ray(),
+            self.primitive_tests_pr_ray(),
+            terminations
+        )
+    }
+
+    /// The stats as a json object
+    pub fn json(&self) -> String {
+        let terminations = Termination::ALL
+            .iter()
+            .map(|termination| {
+                format!(
+                    "\"{}\": {}",
+                    termination.name(),
+                    self.terminations[*termination as usize]
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        format!(
+            "{{\n  \"seconds\": {},\n  \"primary_rays\": {},\n  \"secondary_rays\": {},\n  \
+             \"rays_per_second\": {},\n  \"average_path_length\": {},\n  \"bvh_nodes_visited\": {},\n  \
+             \"bvh_nodes_per_ray\": {},\n  \"primitive_tests\": {},\n  \"primitive_tests_per_ray\": {},\n  \
+             \"terminations\": {{ {} }}\n}}\n",
+            self.elapsed.as_secs_f64(),
+            self.primary_rays,
+            self.secondary_rays,
+            self.rays_pr_second(),
+            self.average_path_length(),
+            self.bvh_nodes_visited,
+            self.bvh_nodes_pr_ray(),
+            self.primitive_tests,
+            self.primitive_tests_pr_ray(),
+            terminations
+        )
+    }
+
+    /// Writes the stats to a json file
+    pub fn write_json(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        fs::write(path, self.json())
+    }
+}
+
+/// Divides, but gives 0 instead of NaN or infinity when there is nothing to divide by
+fn ratio(a: f64, b: f64) -> f64 {
+    if b > 0.0 {
+        a / b
+    } else {
+        0.0
+    }
+}
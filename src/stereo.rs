@@ -0,0 +1,102 @@
+/// How the two views of a stereo image are placed next to each other. Chosen with `--stereo <layout>`
+#[derive(Clone, Copy)]
+pub enum StereoLayout {
+    SideBySide, // The left eye in the left half of the image, and the right eye in the right half
+    TopBottom,  // The left eye in the top half of the image, and the right eye in the bottom half
+}
+
+impl StereoLayout {
+    /// Finds a layout from its name on the command line
+    pub fn from_name(name: &str) -> Option<StereoLayout> {
+        match name {
+            "side-by-side" => Some(StereoLayout::SideBySide),
+            "top-bottom" => Some(StereoLayout::TopBottom),
+            _ => None,
+        }
+    }
+}
+
+/// One of the two eyes of a stereo camera
+#[derive(Clone, Copy)]
+pub enum Eye {
+    Left,
+    Right,
+}
+
+impl Eye {
+    /// Which way the eye is moved from the camera position along the camera's right direction
+    pub fn side(&self) -> f64 {
+        match self {
+            Eye::Left => -1.0,
+            Eye::Right => 1.0,
+        }
+    }
+}
+
+/// Settings for rendering a left and a right eye view into the same image
+#[derive(Clone, Copy)]
+pub struct Stereo {
+    interocular: f64,         // The distance between the eyes
+    convergence: Option<f64>, // The distance where the views line up. None uses the focus distance
+    layout: StereoLayout,
+}
+
+impl Stereo {
+    pub fn new(interocular: f64, layout: StereoLayout) -> Self {
+        Self {
+            interocular,
+            convergence: None,
+            layout,
+        }
+    }
+
+    /// Sets the distance where the two views line up. Objects closer than this seem to come out of the screen
+    pub fn with_convergence(mut self, convergence: f64) -> Self {
+        self.convergence = Some(convergence);
+        self
+    }
+
+    /// Checks that the settings make sense
+    pub fn validate(&self) -> Result<(), String> {
+        if self.interocular < 0.0 {
+            return Err(format!(
+                "the interocular distance can't be negative, got {}",
+                self.interocular
+            ));
+        }
+        match self.convergence {
+            Some(convergence) if convergence <= 0.0 => Err(format!(
+                "the convergence distance has to be positive, got {}",
+                convergence
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Half the distance between the eyes, which is how far each eye is from the camera position
+    pub fn eye_offset(&self) -> f64 {
+        self.interocular / 2.0
+    }
+
+    /// The distance where the views line up, falling back to the given focus distance
+    pub fn convergence_or(&self, focus_distance: f64) -> f64 {
+        self.convergence.unwrap_or(focus_distance)
+    }
+
+    /// The size of the whole image when each view has the given size
+    pub fn output_size(&self, width: i64, height: i64) -> (i64, i64) {
+        match self.layout {
+            StereoLayout::SideBySide => (width * 2, height),
+            StereoLayout::TopBottom => (width, height * 2),
+        }
+    }
+
+    /// Finds the eye a pixel of the whole image belongs to, and the pixel's position in that eye's view
+    pub fn split(&self, x: f64, y: f64, width: i64, height: i64) -> (Eye, f64, f64) {
+        match self.layout {
+            StereoLayout::SideBySide if x >= width as f64 => (Eye::Right, x - width as f64, y),
+            StereoLayout::TopBottom if y >= height as f64 => (Eye::Right, x, y - height as f64),
+            _ => (Eye::Left, x, y),
+        }
+    }
+}
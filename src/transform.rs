@@ -0,0 +1,679 @@
+use std::f64::consts::PI;
+use std::ops::Mul;
+
+use crate::aabb::Aabb;
+use crate::error;
+use crate::hitable::{HitRecord, Hitable};
+use crate::point3::{Point3, Vec3};
+use crate::ray::Ray;
+
+type Matrix = [[f64; 4]; 4];
+
+const IDENTITY: Matrix = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+];
+
+/// An affine transformation stored as a 4x4 matrix, along with its inverse
+#[derive(Debug, Clone, Copy)]
+pub struct Transform {
+    matrix: Matrix,
+    inverse: Matrix,
+}
+
+impl Transform {
+    /// The transform that does nothing
+    pub fn identity() -> Self {
+        Self {
+            matrix: IDENTITY,
+            inverse: IDENTITY,
+        }
+    }
+
+    /// Moves things by the offset
+    pub fn translate(offset: Vec3) -> Self {
+        let mut matrix = IDENTITY;
+        let mut inverse = IDENTITY;
+        for (axis, value) in [offset.x(), offset.y(), offset.z()].into_iter().enumerate() {
+            matrix[axis][3] = value;
+            inverse[axis][3] = -value;
+        }
+
+        Self { matrix, inverse }
+    }
+
+    /// Scales things along each axis. None of the factors can be 0
+    pub fn scale(x: impl Into<f64>, y: impl Into<f64>, z: impl Into<f64>) -> Self {
+        let mut matrix = IDENTITY;
+        let mut inverse = IDENTITY;
+        for (axis, value) in [x.into(), y.into(), z.into()].into_iter().enumerate() {
+            matrix[axis][axis] = value;
+            inverse[axis][axis] = 1.0 / value;
+        }
+
+        Self { matrix, inverse }
+    }
+
+    /// Rotates things around the x-axis
+    pub fn rotate_x(degrees: f64) -> Self {
+        Transform::rotate(Vec3::from_xyz(1, 0, 0), degrees)
+    }
+
+    /// Rotates things around the y-axis
+    pub fn rotate_y(degrees: f64) -> Self {
+        Transform::rotate(Vec3::from_xyz(0, 1, 0), degrees)
+    }
+
+    /// Rotates things around the z-axis
+    pub fn rotate_z(degrees: f64) -> Self {
+        Transform::rotate(Vec3::from_xyz(0, 0, 1), degrees)
+    }
+
+    /// Rotates things counterclockwise around an axis going through (0,0,0)
+    pub fn rotate(axis: Vec3, degrees: f64) -> Self {
+        let a = axis.unit_vec();
+        let (sin, cos) = (degrees * PI / 180.0).sin_cos();
+
+        // Rodrigues' rotation formula
+        let mut matrix = IDENTITY;
+        matrix[0][0] = a.x() * a.x() + (1.0 - a.x() * a.x()) * cos;
+        matrix[0][1] = a.x() * a.y() * (1.0 - cos) - a.z() * sin;
+        matrix[0][2] = a.x() * a.z() * (1.0 - cos) + a.y() * sin;
+        matrix[1][0] = a.x() * a.y() * (1.0 - cos) + a.z() * sin;
+        matrix[1][1] = a.y() * a.y() + (1.0 - a.y() * a.y()) * cos;
+        matrix[1][2] = a.y() * a.z() * (1.0 - cos) - a.x() * sin;
+        matrix[2][0] = a.x() * a.z() * (1.0 - cos) - a.y() * sin;
+        matrix[2][1] = a.y() * a.z() * (1.0 - cos) + a.x() * sin;
+        matrix[2][2] = a.z() * a.z() + (1.0 - a.z() * a.z()) * cos;
+
+        // the inverse of a rotation is its transpose
+        Self {
+            matrix,
+            inverse: transpose(&matrix),
+        }
+    }
+
+    /// Returns a transform that first does this transform, and then the other one
+    pub fn then(&self, other: Transform) -> Self {
+        other * *self
+    }
+
+    /// Returns the transform that undoes this one
+    pub fn inverse(&self) -> Self {
+        Self {
+            matrix: self.inverse,
+            inverse: self.matrix,
+        }
+    }
+
+    /// Transforms a point, which is affected by translation
+    pub fn point(&self, p: Point3) -> Point3 {
+        transform_point(&self.matrix, p)
+    }
+
+    /// Transforms a direction, which isn't affected by translation
+    pub fn vector(&self, v: Vec3) -> Vec3 {
+        transform_vector(&self.matrix, v)
+    }
+
+    /// Transforms a surface normal. Normals have to be transformed with the inverse transposed matrix
+    /// to stay perpendicular to the surface when it is scaled unevenly. The result is not normalised
+    pub fn normal(&self, n: Vec3) -> Vec3 {
+        transform_vector(&transpose(&self.inverse), n)
+    }
+
+    /// Transforms a ray. The t values along the ray stay the same, since the direction isn't normalised
+    pub fn ray(&self, r: &Ray) -> Ray {
+        Ray::new(self.point(r.origin()), self.vector(r.direction()))
+            .with_wavelength(r.wavelength())
+            .with_time(r.time())
+    }
+
+    /// Transforms a bounding box, and returns the box that surrounds the result
+    pub fn bbox(&self, bbox: &Aabb) -> Aabb {
+        Aabb::from_points(&bbox.corners().map(|corner| self.point(corner)))
+    }
+
+    /// How much the transform scales volumes by, negative if it mirrors
+    pub fn determinant(&self) -> f64 {
+        determinant(&self.matrix)
+    }
+}
+
+/// A transform that changes over time, given by keyframes. Between two keyframes the translation,
+/// rotation and scale are interpolated separately, so rotating objects don't get squashed along the way
+#[derive(Debug, Clone)]
+pub struct AnimatedTransform {
+    keyframes: Vec<Keyframe>, // Sorted by time
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Keyframe {
+    time: f64,
+    transform: Transform,
+    translation: Vec3,
+    rotation: Quaternion,
+    scale: Matrix, // The part of the transform that is left after removing translation and rotation
+}
+
+impl AnimatedTransform {
+    /// Creates an animation from a list of times and the transform at each of those times.
+    /// Before the first keyframe and after the last one, the object doesn't move
+    pub fn new(keyframes: Vec<(f64, Transform)>) -> error::Result<Self> {
+        if keyframes.is_empty() {
+            return Err(error::Error::InvalidSettings(
+                "an animation needs a keyframe".to_string(),
+            ));
+        }
+
+        let mut keyframes: Vec<Keyframe> = keyframes
+            .into_iter()
+            .map(|(time, transform)| Keyframe::new(time, transform))
+            .collect();
+        keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+        Ok(Self { keyframes })
+    }
+
+    /// The transform at a moment in time
+    pub fn at(&self, time: f64) -> Transform {
+        let first = &self.keyframes[0];
+        let last = &self.keyframes[self.keyframes.len() - 1];
+        if time <= first.time {
+            return first.transform;
+        }
+        if time >= last.time {
+            return last.transform;
+        }
+
+        let next = self.keyframes.iter().position(|k| k.time > time).unwrap();
+        let (k0, k1) = (&self.keyframes[next - 1], &self.keyframes[next]);
+        let progress = (time - k0.time) / (k1.time - k0.time);
+
+        let translation = k0.translation + (k1.translation - k0.translation) * progress;
+        let rotation = slerp(&k0.rotation, &k1.rotation, progress);
+        let mut scale = IDENTITY;
+        for (i, row) in scale.iter_mut().enumerate().take(3) {
+            for (j, value) in row.iter_mut().enumerate().take(3) {
+                *value = k0.scale[i][j] * (1.0 - progress) + k1.scale[i][j] * progress;
+            }
+        }
+
+        let rotation = quaternion_to_matrix(&rotation);
+        Transform::translate(translation)
+            * Transform {
+                matrix: rotation,
+                inverse: transpose(&rotation),
+            }
+            * Transform {
+                matrix: scale,
+                inverse: invert(&scale),
+            }
+    }
+
+    /// Returns a box that surrounds the bounding box everywhere along the animation
+    pub fn bbox(&self, bbox: &Aabb) -> Aabb {
+        const STEPS: usize = 64;
+
+        let mut result = self.keyframes[0].transform.bbox(bbox);
+        let mut max_step: f64 = 0.0;
+        for pair in self.keyframes.windows(2) {
+            let (t0, t1) = (pair[0].time, pair[1].time);
+            let mut previous = bbox.corners().map(|c| pair[0].transform.point(c));
+
+            for step in 1..=STEPS {
+                let transform = self.at(t0 + (t1 - t0) * step as f64 / STEPS as f64);
+                let corners = bbox.corners().map(|c| transform.point(c));
+
+                result = result.surrounding(&Aabb::from_points(&corners));
+                for (a, b) in corners.iter().zip(previous.iter()) {
+                    max_step = max_step.max(a.distance(b));
+                }
+                previous = corners;
+            }
+        }
+
+        // Between two samples a corner can curve outside of the straight line between them,
+        // but never by more than half the distance it moved
+        result.pad(max_step / 2.0)
+    }
+}
+
+impl Keyframe {
+    fn new(time: f64, transform: Transform) -> Self {
+        let m = transform.matrix;
+        let translation = Vec3::from_xyz(m[0][3], m[1][3], m[2][3]);
+
+        let mut upper = m;
+        for row in upper.iter_mut().take(3) {
+            row[3] = 0.0;
+        }
+
+        // polar decomposition: average the matrix with its inverse transpose until it is a pure rotation
+        let mut rotation = upper;
+        for _ in 0..100 {
+            let inverse_transpose = transpose(&invert(&rotation));
+            let mut difference: f64 = 0.0;
+            for i in 0..3 {
+                for j in 0..3 {
+                    let next = 0.5 * (rotation[i][j] + inverse_transpose[i][j]);
+                    difference = difference.max((next - rotation[i][j]).abs());
+                    rotation[i][j] = next;
+                }
+            }
+            if difference < 1e-12 {
+                break;
+            }
+        }
+
+        // a mirroring transform gives a rotation that also mirrors, which can't be a quaternion
+        if determinant(&rotation) < 0.0 {
+            for row in rotation.iter_mut().take(3) {
+                for value in row.iter_mut().take(3) {
+                    *value = -*value;
+                }
+            }
+        }
+
+        let scale = multiply(&transpose(&rotation), &upper);
+
+        Self {
+            time,
+            transform,
+            translation,
+            rotation: matrix_to_quaternion(&rotation),
+            scale,
+        }
+    }
+}
+
+/// A rotation stored as a unit quaternion: [w, x, y, z]
+type Quaternion = [f64; 4];
+
+fn matrix_to_quaternion(m: &Matrix) -> Quaternion {
+    let trace = m[0][0] + m[1][1] + m[2][2];
+    if trace > 0.0 {
+        let s = (trace + 1.0).sqrt() * 2.0;
+        [
+            s / 4.0,
+            (m[2][1] - m[1][2]) / s,
+            (m[0][2] - m[2][0]) / s,
+            (m[1][0] - m[0][1]) / s,
+        ]
+    } else if m[0][0] > m[1][1] && m[0][0] > m[2][2] {
+        let s = (1.0 + m[0][0] - m[1][1] - m[2][2]).sqrt() * 2.0;
+        [
+            (m[2][1] - m[1][2]) / s,
+            s / 4.0,
+            (m[0][1] + m[1][0]) / s,
+            (m[0][2] + m[2][0]) / s,
+        ]
+    } else if m[1][1] > m[2][2] {
+        let s = (1.0 + m[1][1] - m[0][0] - m[2][2]).sqrt() * 2.0;
+        [
+            (m[0][2] - m[2][0]) / s,
+            (m[0][1] + m[1][0]) / s,
+            s / 4.0,
+            (m[1][2] + m[2][1]) / s,
+        ]
+    } else {
+        let s = (1.0 + m[2][2] - m[0][0] - m[1][1]).sqrt() * 2.0;
+        [
+            (m[1][0] - m[0][1]) / s,
+            (m[0][2] + m[2][0]) / s,
+            (m[1][2] + m[2][1]) / s,
+            s / 4.0,
+        ]
+    }
+}
+
+fn quaternion_to_matrix(q: &Quaternion) -> Matrix {
+    let [w, x, y, z] = *q;
+    [
+        [
+            1.0 - 2.0 * (y * y + z * z),
+            2.0 * (x * y - w * z),
+            2.0 * (x * z + w * y),
+            0.0,
+        ],
+        [
+            2.0 * (x * y + w * z),
+            1.0 - 2.0 * (x * x + z * z),
+            2.0 * (y * z - w * x),
+            0.0,
+        ],
+        [
+            2.0 * (x * z - w * y),
+            2.0 * (y * z + w * x),
+            1.0 - 2.0 * (x * x + y * y),
+            0.0,
+        ],
+        [0.0, 0.0, 0.0, 1.0],
+    ]
+}
+
+/// Spherical linear interpolation between two rotations, which rotates at a constant speed
+fn slerp(q0: &Quaternion, q1: &Quaternion, t: f64) -> Quaternion {
+    let mut dot: f64 = q0.iter().zip(q1.iter()).map(|(a, b)| a * b).sum();
+
+    // go the short way around
+    let mut q1 = *q1;
+    if dot < 0.0 {
+        q1 = q1.map(|v| -v);
+        dot = -dot;
+    }
+
+    let (w0, w1) = if dot > 0.9995 {
+        (1.0 - t, t)
+    } else {
+        let theta = dot.acos();
+        (
+            ((1.0 - t) * theta).sin() / theta.sin(),
+            (t * theta).sin() / theta.sin(),
+        )
+    };
+
+    let mut q = [0.0; 4];
+    for (i, value) in q.iter_mut().enumerate() {
+        *value = w0 * q0[i] + w1 * q1[i];
+    }
+    let length = q.iter().map(|v| v * v).sum::<f64>().sqrt();
+    q.map(|v| v / length)
+}
+
+fn determinant(m: &Matrix) -> f64 {
+    m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+        - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+        + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+}
+
+/// Inverts a matrix with Gauss-Jordan elimination
+fn invert(m: &Matrix) -> Matrix {
+    let mut a = *m;
+    let mut inverse = IDENTITY;
+
+    for column in 0..4 {
+        // use the row with the largest value as the pivot, to keep the result precise
+        let pivot = (column..4)
+            .max_by(|&i, &j| a[i][column].abs().total_cmp(&a[j][column].abs()))
+            .unwrap();
+        a.swap(column, pivot);
+        inverse.swap(column, pivot);
+
+        let divisor = a[column][column];
+        for j in 0..4 {
+            a[column][j] /= divisor;
+            inverse[column][j] /= divisor;
+        }
+
+        for row in 0..4 {
+            if row != column {
+                let factor = a[row][column];
+                for j in 0..4 {
+                    a[row][j] -= factor * a[column][j];
+                    inverse[row][j] -= factor * inverse[column][j];
+                }
+            }
+        }
+    }
+
+    inverse
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform::identity()
+    }
+}
+
+impl Mul for Transform {
+    type Output = Transform;
+
+    /// Combines two transforms. The right hand side is applied first
+    fn mul(self, other: Transform) -> Transform {
+        Transform {
+            matrix: multiply(&self.matrix, &other.matrix),
+            inverse: multiply(&other.inverse, &self.inverse),
+        }
+    }
+}
+
+fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+    let mut result = [[0.0; 4]; 4];
+    for (i, row) in result.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = (0..4).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    result
+}
+
+fn transpose(m: &Matrix) -> Matrix {
+    let mut result = [[0.0; 4]; 4];
+    for (i, row) in result.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = m[j][i];
+        }
+    }
+    result
+}
+
+fn transform_point(m: &Matrix, p: Point3) -> Point3 {
+    transform_vector(m, p) + Point3::from_xyz(m[0][3], m[1][3], m[2][3])
+}
+
+fn transform_vector(m: &Matrix, v: Vec3) -> Vec3 {
+    Vec3::from_xyz(
+        m[0][0] * v.x() + m[0][1] * v.y() + m[0][2] * v.z(),
+        m[1][0] * v.x() + m[1][1] * v.y() + m[1][2] * v.z(),
+        m[2][0] * v.x() + m[2][1] * v.y() + m[2][2] * v.z(),
+    )
+}
+
+/// An object placed in the world with a transform. The object is defined in its own object space,
+/// and the same object can be shared between many instances by wrapping it in an Arc
+pub struct Transformed<H: Hitable> {
+    object: H,
+    motion: Motion, // From object space to world space
+}
+
+// The static transform is kept inline, since it is read on every hit
+#[allow(clippy::large_enum_variant)]
+enum Motion {
+    Static(Transform),
+    Animated(AnimatedTransform),
+}
+
+impl<H: Hitable> Transformed<H> {
+    pub fn new(object: H, transform: Transform) -> Self {
+        Self {
+            object,
+            motion: Motion::Static(transform),
+        }
+    }
+
+    /// Places the object with a transform that changes over time, which gives motion blur
+    pub fn animated(object: H, animation: AnimatedTransform) -> Self {
+        Self {
+            object,
+            motion: Motion::Animated(animation),
+        }
+    }
+}
+
+impl<H: Hitable> Transformed<H> {
+    /// The transform from object space to world space at the time
+    fn transform_at(&self, time: f64) -> Transform {
+        match &self.motion {
+            Motion::Static(transform) => *transform,
+            Motion::Animated(animation) => animation.at(time),
+        }
+    }
+}
+
+impl<H: Hitable> Hitable for Transformed<H> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let transform = self.transform_at(r.time());
+
+        // move the ray into object space, and move the hit back out into world space
+        let object_ray = transform.inverse().ray(r);
+        let rec = self.object.hit(&object_ray, t_min, t_max)?;
+        let (u, v) = rec.uv();
+
+        // the ray is kept in the space of the innermost object, since that is where media look up their density
+        Some(
+            HitRecord::new(
+                transform.point(rec.point()),
+                rec.t(),
+                transform.normal(rec.normal()).unit_vec(),
+                rec.material(),
+            )
+            .with_uv(u, v)
+            .with_object_ray(rec.object_ray(&object_ray)),
+        )
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let bbox = self.object.bounding_box()?;
+        Some(match &self.motion {
+            Motion::Static(transform) => transform.bbox(&bbox),
+            Motion::Animated(animation) => animation.bbox(&bbox),
+        })
+    }
+
+    // Light sampling doesn't know the time of the ray, so an animated light is sampled where it is at time 0.
+    // The pdf and the directions come from the same transform, so the image stays right, a moving light is
+    // only found less often by the light samples
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        let transform = self.transform_at(0.0);
+        let to_object = transform.inverse();
+        let object_direction = to_object.vector(direction).unit_vec();
+        let pdf = self
+            .object
+            .pdf_value(to_object.point(origin), object_direction);
+
+        // the transform stretches the directions around the origin unevenly when it scales,
+        // which changes the density pr solid angle by |A d|^3 / |det A| for a unit object direction d
+        pdf * transform.vector(object_direction).length().powi(3) / transform.determinant().abs()
+    }
+
+    fn random_direction(&self, origin: Point3) -> Vec3 {
+        let transform = self.transform_at(0.0);
+        let object_origin = transform.inverse().point(origin);
+        transform.vector(self.object.random_direction(object_origin))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+    use crate::planar::Quad;
+
+    fn assert_matrix_eq(found: &Matrix, expected: &Matrix) {
+        for i in 0..4 {
+            for j in 0..4 {
+                assert!(
+                    (found[i][j] - expected[i][j]).abs() < 1e-9,
+                    "{:?} != {:?}",
+                    found,
+                    expected
+                );
+            }
+        }
+    }
+
+    fn skewed() -> Transform {
+        Transform::scale(2, -0.5, 3)
+            .then(Transform::rotate(Vec3::from_xyz(1, -2, 0.5), 70.0))
+            .then(Transform::translate(Vec3::from_xyz(4, 1, -2)))
+    }
+
+    #[test]
+    fn inverse_undoes_the_transform() {
+        let transform = skewed();
+        assert_matrix_eq(
+            &multiply(&transform.matrix, &invert(&transform.matrix)),
+            &IDENTITY,
+        );
+        assert_matrix_eq(&transform.then(transform.inverse()).matrix, &IDENTITY);
+        assert!((transform.determinant() + 3.0).abs() < 1e-9);
+
+        let p = Point3::from_xyz(0.3, -7, 2);
+        assert!(transform.inverse().point(transform.point(p)).distance(&p) < 1e-9);
+    }
+
+    #[test]
+    fn keyframe_splits_the_transform_into_rotation_and_scale() {
+        for transform in [
+            skewed(),
+            Transform::rotate_y(120.0).then(Transform::scale(1, 4, 0.25)),
+        ] {
+            let keyframe = Keyframe::new(0.0, transform);
+            let rotation = quaternion_to_matrix(&keyframe.rotation);
+            assert_matrix_eq(&multiply(&rotation, &transpose(&rotation)), &IDENTITY);
+            assert!((determinant(&rotation) - 1.0).abs() < 1e-9);
+
+            let rebuilt = Transform::translate(keyframe.translation).matrix;
+            let rebuilt = multiply(&rebuilt, &multiply(&rotation, &keyframe.scale));
+            assert_matrix_eq(&rebuilt, &transform.matrix);
+        }
+    }
+
+    #[test]
+    fn animation_rotates_without_squashing() {
+        let scale = Transform::scale(2, 1, 1);
+        let animation = AnimatedTransform::new(vec![
+            (0.0, scale),
+            (1.0, scale.then(Transform::rotate_z(90.0))),
+        ])
+        .unwrap();
+
+        // halfway through, the long x axis has turned by 45 degrees and kept its length
+        let halfway = animation.at(0.5);
+        let x = halfway.vector(Vec3::from_xyz(1, 0, 0));
+        let expected = Vec3::from_xyz(2, 2, 0) * std::f64::consts::FRAC_1_SQRT_2;
+        assert!(x.distance(&expected) < 1e-9, "{:?}", x);
+        assert_matrix_eq(&halfway.then(halfway.inverse()).matrix, &IDENTITY);
+    }
+
+    #[test]
+    fn transformed_light_has_the_pdf_of_the_light_built_in_world_space() {
+        let (corner, u, v) = (
+            Point3::from_xyz(-1, -1, 0),
+            Vec3::from_xyz(2, 0, 0),
+            Vec3::from_xyz(0, 2, 0),
+        );
+        let transform = Transform::scale(3, 0.5, 1)
+            .then(Transform::rotate(Vec3::from_xyz(1, 2, 3), 40.0))
+            .then(Transform::translate(Vec3::from_xyz(0.5, 0, -3)));
+
+        let transformed = Transformed::new(
+            Quad::from_corner_edges_material(corner, u, v, Lambertian::new()),
+            transform,
+        );
+        let world_space = Quad::from_corner_edges_material(
+            transform.point(corner),
+            transform.vector(u),
+            transform.vector(v),
+            Lambertian::new(),
+        );
+
+        let origin = Point3::from_xyz(0.2, 0.1, 0.3);
+        for _ in 0..100 {
+            let direction = transformed.random_direction(origin);
+            let expected = world_space.pdf_value(origin, direction);
+            assert!(expected > 0.0);
+            let pdf = transformed.pdf_value(origin, direction);
+            assert!(
+                (pdf - expected).abs() < 1e-6 * expected,
+                "{} != {}",
+                pdf,
+                expected
+            );
+        }
+    }
+}